@@ -0,0 +1,175 @@
+use crate::lrc::{Lrc, strip_word_timings, word_timings};
+use clap::ValueEnum;
+use std::fmt::Write;
+
+/// How long the last line stays on screen, when no later line clears it
+const LAST_LINE_MS: u64 = 5000;
+
+/// Where lyrics subtitles are placed on the video
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubtitlePosition {
+    Bottom,
+    Middle,
+    Top,
+}
+
+impl SubtitlePosition {
+    /// The numpad-style alignment ASS styles use, centered horizontally
+    fn alignment(self) -> u8 {
+        match self {
+            SubtitlePosition::Bottom => 2,
+            SubtitlePosition::Middle => 5,
+            SubtitlePosition::Top => 8,
+        }
+    }
+}
+
+/// Look of the lyrics in an ASS subtitle file; colors are in ASS's `&HAABBGGRR` notation
+#[derive(Debug, Clone)]
+pub struct AssStyle {
+    pub font: String,
+    pub font_size: u32,
+    /// Color of the text, or of the words already sung with karaoke effects
+    pub color: String,
+    /// Color of the words not sung yet with karaoke effects
+    pub karaoke_color: String,
+    pub outline_color: String,
+    pub position: SubtitlePosition,
+    /// Highlight each word as it is sung, or the whole line when there are no word timings
+    pub karaoke: bool,
+}
+
+/// Parses a `#RRGGBB` or `#RRGGBBAA` color (alpha 00 being transparent, as on the web) into
+/// ASS notation
+pub fn parse_color(color: &str) -> Result<String, String> {
+    let hex = color.trim().trim_start_matches('#');
+    let invalid = || format!("invalid color '{}' (expected #RRGGBB or #RRGGBBAA)", color);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let byte = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| invalid());
+    let (red, green, blue) = (byte(0)?, byte(2)?, byte(4)?);
+    // ASS counts transparency up from 00 for opaque
+    let alpha = if hex.len() == 8 { 255 - byte(6)? } else { 0 };
+    Ok(format!(
+        "&H{:02X}{:02X}{:02X}{:02X}",
+        alpha, blue, green, red
+    ))
+}
+
+/// Turns synced lyrics into an ASS subtitle script, one event per line from its timestamp
+/// until the next line replaces it
+pub fn to_ass(lrc: &Lrc, title: &str, style: &AssStyle) -> String {
+    let mut ass = format!(
+        "[Script Info]\n\
+         ; Written by lrcphile\n\
+         Title: {}\n\
+         ScriptType: v4.00+\n\
+         PlayResX: 1920\n\
+         PlayResY: 1080\n\
+         WrapStyle: 0\n\
+         ScaledBorderAndShadow: yes\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Lyrics,{},{},{},{},{},&H80000000,0,0,0,0,100,100,0,0,1,3,1,{},60,60,60,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        title,
+        style.font,
+        style.font_size,
+        style.color,
+        style.karaoke_color,
+        style.outline_color,
+        style.position.alignment()
+    );
+
+    for cue in cues(lrc) {
+        let text = if style.karaoke {
+            karaoke_text(cue.text, cue.start_ms, cue.end_ms)
+        } else {
+            escape(strip_word_timings(cue.text).trim())
+        };
+        let _ = writeln!(
+            ass,
+            "Dialogue: 0,{},{},Lyrics,,0,0,0,,{}",
+            ass_time(cue.start_ms),
+            ass_time(cue.end_ms),
+            text
+        );
+    }
+    ass
+}
+
+/// A lyrics line as a subtitle, on screen from its timestamp until the next line
+pub struct Cue<'a> {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// The line as written, with any enhanced LRC word timings
+    pub text: &'a str,
+}
+
+/// The lines of synced lyrics as subtitles; blank lines only clear the screen, so they end
+/// the line before them without a cue of their own
+pub fn cues(lrc: &Lrc) -> Vec<Cue<'_>> {
+    let timeline = lrc.timeline();
+    timeline
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, text))| !strip_word_timings(text).trim().is_empty())
+        .map(|(index, &(start, text))| Cue {
+            start_ms: start.millis,
+            end_ms: timeline
+                .get(index + 1)
+                .map_or(start.millis + LAST_LINE_MS, |(next, _)| next.millis)
+                .max(start.millis),
+            text,
+        })
+        .collect()
+}
+
+/// A line with `\kf` sweeps: per word when the line carries enhanced LRC word timings like
+/// `<00:12.34>`, otherwise across the whole line
+fn karaoke_text(text: &str, start_ms: u64, end_ms: u64) -> String {
+    let words = word_timings(text);
+    if words.is_empty() {
+        return format!(
+            "{{\\kf{}}}{}",
+            (end_ms - start_ms) / 10,
+            escape(text.trim())
+        );
+    }
+
+    let mut karaoke = String::new();
+    for (index, (word_start, word)) in words.iter().enumerate() {
+        let word_start = (*word_start).max(start_ms);
+        let word_end = words
+            .get(index + 1)
+            .map_or(end_ms, |(next, _)| *next)
+            .max(word_start);
+        let _ = write!(
+            karaoke,
+            "{{\\kf{}}}{}",
+            (word_end - word_start) / 10,
+            escape(word)
+        );
+    }
+    karaoke.trim().to_string()
+}
+
+/// Braces would start an ASS override block, so they're shown as parentheses
+fn escape(text: &str) -> String {
+    text.replace('{', "(").replace('}', ")")
+}
+
+/// ASS timestamps count hours and centiseconds, e.g. `0:01:10.25`
+fn ass_time(millis: u64) -> String {
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000 / 10
+    )
+}
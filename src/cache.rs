@@ -0,0 +1,271 @@
+use crate::error::Result;
+use crate::provider::{HttpResponse, Transport};
+use directories::ProjectDirs;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long a cached response is used before the instance is asked again, so lyrics
+/// added or corrected upstream eventually show up
+const ENTRY_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How far below the size limit eviction goes, so a full cache isn't pruned on every write
+const PRUNE_TARGET_PERCENT: u64 = 90;
+
+/// A response stored on disk, keyed by the hash of its URL
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    /// The hash of the URL rather than the URL itself, which may carry an API key
+    key: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    /// When the response was received, in seconds since the epoch
+    stored: u64,
+}
+
+/// Number and total size of cache entries
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Lookup responses kept on disk so repeated runs don't ask the instance the same thing again;
+/// entries are evicted least recently used first once the cache outgrows its limit
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Running estimate of the cache size, corrected whenever it is pruned
+    bytes: AtomicU64,
+    pruning: std::sync::Mutex<()>,
+}
+
+impl ResponseCache {
+    /// `lrcphile/responses` under the user cache directory (`$XDG_CACHE_HOME` on Linux)
+    pub fn default_location() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "lrcphile").map(|dirs| dirs.cache_dir().join("responses"))
+    }
+
+    pub fn open(dir: PathBuf, max_bytes: u64) -> Self {
+        let cache = Self {
+            dir,
+            max_bytes,
+            bytes: AtomicU64::new(0),
+            pruning: std::sync::Mutex::new(()),
+        };
+        cache.bytes.store(cache.stats().bytes, Ordering::Relaxed);
+        cache
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Returns the cached response for a URL unless it has expired, marking it as recently used
+    pub fn get(&self, url: &str) -> Option<HttpResponse> {
+        let key = entry_key(url);
+        let path = self.entry_path(&key);
+        let entry: CachedResponse = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        if entry.key != key || now_secs().saturating_sub(entry.stored) > ENTRY_TTL.as_secs() {
+            return None;
+        }
+
+        // The modification time doubles as the last use, which eviction goes by
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(HttpResponse {
+            status: entry.status,
+            headers: entry.headers,
+            body: entry.body.into_bytes(),
+        })
+    }
+
+    /// Stores a response; failures are ignored since the cache only saves requests
+    pub fn put(&self, url: &str, response: &HttpResponse) {
+        let Ok(body) = String::from_utf8(response.body.clone()) else {
+            return;
+        };
+        let key = entry_key(url);
+        let entry = CachedResponse {
+            key: key.clone(),
+            status: response.status,
+            headers: response.headers.clone(),
+            body,
+            stored: now_secs(),
+        };
+        let Ok(content) = serde_json::to_vec(&entry) else {
+            return;
+        };
+
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        let path = self.entry_path(&key);
+        let temp_path = path.with_extension("json.tmp");
+        if fs::write(&temp_path, &content).is_ok() && fs::rename(&temp_path, &path).is_ok() {
+            let bytes = self
+                .bytes
+                .fetch_add(content.len() as u64, Ordering::Relaxed);
+            if bytes + content.len() as u64 > self.max_bytes {
+                let _ = self.prune();
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.entries()
+            .iter()
+            .fold(CacheStats::default(), |stats, (_, bytes, _)| CacheStats {
+                entries: stats.entries + 1,
+                bytes: stats.bytes + bytes,
+            })
+    }
+
+    /// Removes expired entries, then the least recently used ones until the cache is
+    /// comfortably below its size limit; returns what was removed
+    pub fn prune(&self) -> Result<CacheStats> {
+        // Another worker is already pruning
+        let Ok(_guard) = self.pruning.try_lock() else {
+            return Ok(CacheStats::default());
+        };
+
+        let mut entries = self.entries();
+        entries.sort_by_key(|(_, _, used)| *used);
+        let mut remaining: u64 = entries.iter().map(|(_, bytes, _)| bytes).sum();
+        let target = self.max_bytes / 100 * PRUNE_TARGET_PERCENT;
+        let expired_before = SystemTime::now() - ENTRY_TTL;
+
+        let mut removed = CacheStats::default();
+        for (path, bytes, used) in entries {
+            if remaining <= target && used >= expired_before {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            remaining -= bytes;
+            removed.entries += 1;
+            removed.bytes += bytes;
+        }
+
+        self.bytes.store(remaining, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    /// Removes every entry; returns what was removed
+    pub fn clear(&self) -> Result<CacheStats> {
+        let mut removed = CacheStats::default();
+        for (path, bytes, _) in self.entries() {
+            fs::remove_file(&path)?;
+            removed.entries += 1;
+            removed.bytes += bytes;
+        }
+
+        self.bytes.store(0, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    /// Every entry with its size and last use
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        read_dir
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+            })
+            .collect()
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+fn entry_key(url: &str) -> String {
+    Sha256::digest(url)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Answers lookups from the response cache when it can, and stores what the instance returns
+pub struct CachingTransport {
+    inner: Arc<dyn Transport>,
+    cache: Arc<ResponseCache>,
+    /// Skip cached responses (but still store fresh ones), for runs that re-check lyrics
+    refresh: bool,
+}
+
+impl CachingTransport {
+    pub fn new(inner: Arc<dyn Transport>, cache: Arc<ResponseCache>, refresh: bool) -> Self {
+        Self {
+            inner,
+            cache,
+            refresh,
+        }
+    }
+}
+
+impl Transport for CachingTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            // Conditional requests are already cheap and must reach the instance to mean anything
+            if !headers.is_empty() {
+                return self.inner.get(url, headers).await;
+            }
+
+            if !self.refresh
+                && let Some(response) = self.cache.get(url)
+            {
+                return Ok(response);
+            }
+
+            let response = self.inner.get(url, headers).await?;
+            // Only definite answers are kept; errors and rate limits are worth asking again
+            if matches!(response.status, 200..=299 | 404) {
+                self.cache.put(url, &response);
+            }
+            Ok(response)
+        })
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        self.inner.post(url, headers, body)
+    }
+}
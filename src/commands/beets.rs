@@ -0,0 +1,190 @@
+use crate::error::{LrcphileError, Result};
+use crate::provider::LyricsProvider;
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, FileOutcome, LyricsStatus, ProcessingStats, Throttle, TrackMetadata,
+    fetch_with_retry, is_edited_since_fetch, known_lrclib_id, prepare_lyrics_file, progress_bar,
+    save_lyrics_file, written_lyrics_hash,
+};
+use colored::Colorize;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// Fields expected on each line of the listing, separated by tabs
+const BEETS_FIELDS: &str = "$path, $title, $artist, $album, $length";
+
+/// Fetches lyrics for the items of a beets library listing, trusting beets' metadata over file tags
+pub async fn run(listing: &Path, args: &Cli, state: &Arc<Mutex<StateDb>>) -> Result<()> {
+    let mut input = String::new();
+    if listing == Path::new("-") {
+        io::stdin().read_to_string(&mut input)?;
+    } else {
+        input = fs::read_to_string(listing)?;
+    }
+
+    let mut items = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_item(line) {
+            Some(item) => items.push(item),
+            None => eprintln!(
+                "{} {}",
+                "Warning:".yellow().bold(),
+                format!(
+                    "Line {}: expected tab-separated {}",
+                    number + 1,
+                    BEETS_FIELDS
+                )
+                .yellow()
+            ),
+        }
+    }
+
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} beets items", items.len()).bright_cyan()
+    );
+
+    let provider = args.lyrics_provider()?;
+    let throttle = Arc::new(Throttle::default());
+    let progress = progress_bar(items.len());
+    progress.set_message("Processing beets items...");
+    let console = Console::with_progress(&progress);
+    let mut stats = ProcessingStats::new(items.len());
+
+    stream::iter(items)
+        .map(|(path, metadata)| {
+            let provider = provider.clone();
+            let throttle = throttle.clone();
+            let console = console.clone();
+            async move {
+                let status = LyricsStatus::of(&path, args);
+                let outcome = if status == LyricsStatus::Instrumental
+                    || (status != LyricsStatus::Missing
+                        && (!args.override_files
+                            || (!args.force && is_edited_since_fetch(&path, state, args).await)))
+                {
+                    FileOutcome::Skipped
+                } else {
+                    match fetch_item(
+                        &path,
+                        metadata,
+                        args,
+                        provider.as_ref(),
+                        &throttle,
+                        state,
+                        &console,
+                    )
+                    .await
+                    {
+                        Ok(()) => FileOutcome::Fetched,
+                        Err(LrcphileError::NotFound) => FileOutcome::Failed,
+                        Err(e) => {
+                            console.failed(&path, e);
+                            FileOutcome::Failed
+                        }
+                    }
+                };
+                (path, outcome)
+            }
+        })
+        .buffer_unordered(args.concurrency())
+        .for_each(|(path, outcome)| {
+            stats.record(&path, outcome, args);
+            progress.inc(1);
+            future::ready(())
+        })
+        .await;
+
+    progress.finish_with_message("Processing complete!");
+    stats.display_summary();
+    Ok(())
+}
+
+/// Parses one tab-separated line of `beet ls -f` output into an item path and its metadata
+fn parse_item(line: &str) -> Option<(PathBuf, TrackMetadata)> {
+    let mut fields = line.split('\t');
+    let path = PathBuf::from(fields.next()?);
+    let track_name = fields.next()?.to_string();
+    let artist_name = fields.next()?.to_string();
+    let album_name = fields.next()?.to_string();
+    let duration = parse_length(fields.next()?)?;
+    // Optional trailing $disc and $track columns
+    let mut number = || fields.next()?.trim().parse::<u32>().ok().filter(|&n| n > 0);
+    let disc_number = number();
+    let track_number = number();
+
+    Some((
+        path,
+        TrackMetadata {
+            track_name,
+            artist_name,
+            album_name,
+            genre: None,
+            duration,
+            disc_number,
+            track_number,
+            lrclib_id: None,
+            validators: None,
+        },
+    ))
+}
+
+/// Parses a length formatted as `M:SS` or `H:MM:SS`, or plain seconds, like beets' `$length`
+/// and the `[length:]` tag of LRC files
+pub(super) fn parse_length(length: &str) -> Option<f64> {
+    length.trim().split(':').try_fold(0.0, |total, part| {
+        Some(total * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+async fn fetch_item(
+    path: &Path,
+    mut metadata: TrackMetadata,
+    args: &Cli,
+    provider: &dyn LyricsProvider,
+    throttle: &Throttle,
+    state: &Mutex<StateDb>,
+    console: &Console,
+) -> Result<()> {
+    metadata.lrclib_id = known_lrclib_id(path, Some(&metadata), state, args).await;
+    let lyrics = fetch_with_retry(
+        provider,
+        &metadata,
+        throttle,
+        console,
+        args.search_fallback,
+        args.interactive,
+    )
+    .await?;
+    let lyrics_file = prepare_lyrics_file(&lyrics, args.instrumental_placeholder, args.lyrics_type)
+        .ok_or(LrcphileError::NotFound)?;
+    let lyrics_path = save_lyrics_file(
+        path,
+        &lyrics_file.content,
+        lyrics_file.extension,
+        Some(&metadata),
+        state,
+        args,
+    )
+    .await?;
+
+    let mut state = state.lock().await;
+    state.record_lrclib_id(path, lyrics.lrclib_id(), &metadata);
+    state.record_lyrics_hash(
+        path,
+        written_lyrics_hash(&lyrics_path, &lyrics_file.content),
+    );
+    state.record_processed(path);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::error::{LrcphileError, Result};
+use crate::provider::{Lrclib, LyricsProvider};
+use crate::{Cli, TrackMetadata};
+use colored::Colorize;
+use futures::stream::{self, StreamExt};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How one synthetic lookup ended
+enum Answer {
+    /// The instance answered, with lyrics or without
+    Answered(Duration),
+    RateLimited,
+    Failed,
+}
+
+/// Sends synthetic lookups to a self-hosted instance, with the run's concurrency and rate
+/// limit, and reports their latency and the throughput reached
+pub async fn run(requests: usize, search: bool, args: &Cli) -> Result<()> {
+    // Load testing a service everyone shares is never polite
+    if args.is_public_instance() {
+        return Err(LrcphileError::Provider(
+            "bench only runs against self-hosted instances; pass one with --url".to_string(),
+        ));
+    }
+
+    let mut options = args.client_options();
+    // Cached answers would measure the disk instead of the instance
+    options.cache = None;
+    let provider = Lrclib::connect(&args.url, args.cached_only, &options)?;
+    let concurrency = args.concurrency();
+    println!(
+        "{} {}",
+        "Benchmarking:".green().bold(),
+        format!(
+            "{} {} lookups against {}, {} at a time",
+            requests,
+            if search { "search" } else { "exact" },
+            args.url,
+            concurrency
+        )
+        .bright_cyan()
+    );
+
+    let started = Instant::now();
+    let answers: Vec<Answer> = stream::iter(0..requests)
+        .map(|index| {
+            let provider = &provider;
+            async move {
+                let metadata = synthetic_track(index);
+                let sent = Instant::now();
+                let result = if search {
+                    provider.search(&metadata).await.map(|_| ())
+                } else {
+                    provider.fetch(&metadata).await.map(|_| ())
+                };
+                match result {
+                    Ok(()) | Err(LrcphileError::NotFound) => Answer::Answered(sent.elapsed()),
+                    Err(LrcphileError::RateLimited { .. }) => Answer::RateLimited,
+                    Err(_) => Answer::Failed,
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    let elapsed = started.elapsed();
+
+    let mut latencies: Vec<Duration> = answers
+        .iter()
+        .filter_map(|answer| match answer {
+            Answer::Answered(latency) => Some(*latency),
+            _ => None,
+        })
+        .collect();
+    latencies.sort();
+    let rate_limited = answers
+        .iter()
+        .filter(|answer| matches!(answer, Answer::RateLimited))
+        .count();
+    let failed = answers.len() - latencies.len() - rate_limited;
+
+    println!("\n{}", "Results:".bright_cyan().bold());
+    println!("  {:<14} {}", "Answered:", latencies.len());
+    if rate_limited > 0 {
+        println!(
+            "  {:<14} {}",
+            "Rate limited:",
+            rate_limited.to_string().yellow()
+        );
+    }
+    if failed > 0 {
+        println!("  {:<14} {}", "Failed:", failed.to_string().red());
+    }
+    println!(
+        "  {:<14} {:.1} lookups/s over {:.1} s",
+        "Throughput:",
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        elapsed.as_secs_f64()
+    );
+    if !latencies.is_empty() {
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "  {:<14} p50 {}  p90 {}  p99 {}  max {}",
+            "Latency:",
+            format_latency(percentile(50)),
+            format_latency(percentile(90)),
+            format_latency(percentile(99)),
+            format_latency(percentile(100))
+        );
+    }
+    Ok(())
+}
+
+/// A made-up track no instance has lyrics for, different for every lookup so the
+/// instance can't answer from its own caches
+fn synthetic_track(index: usize) -> TrackMetadata {
+    TrackMetadata {
+        track_name: format!("lrcphile bench track {}", index),
+        artist_name: "lrcphile bench".to_string(),
+        album_name: "lrcphile bench".to_string(),
+        genre: None,
+        duration: 120.0 + (index % 180) as f64,
+        disc_number: None,
+        track_number: None,
+        lrclib_id: None,
+        validators: None,
+    }
+}
+
+fn format_latency(latency: Duration) -> String {
+    format!("{:.1} ms", latency.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,240 @@
+use super::export::find_lyrics;
+use crate::error::{LrcphileError, Result};
+use crate::{BookletFormat, Cli, find_instrumental_placeholder, lrc, process_directory};
+use colored::Colorize;
+use lofty::{prelude::TaggedFileExt, probe::Probe, tag::Accessor};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// One track of the booklet, in the order it appears on the album
+struct Track {
+    disc: u32,
+    number: Option<u32>,
+    title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    /// Plain lyrics; `None` when the track has none, empty for instrumentals
+    lyrics: Option<String>,
+}
+
+/// Collects an album's lyrics into one Markdown or HTML document, in track order
+pub fn run(dir: &Path, format: BookletFormat, output: Option<&Path>, args: &Cli) -> Result<()> {
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let audio_files = process_directory(dir, &scan_args)?;
+    if audio_files.is_empty() {
+        return Err(LrcphileError::Metadata(format!(
+            "No audio files in {}",
+            dir.display()
+        )));
+    }
+
+    let mut tracks: Vec<(PathBuf, Track)> = audio_files
+        .into_iter()
+        .map(|path| {
+            let track = read_track(&path, args);
+            (path, track)
+        })
+        .collect();
+    tracks.sort_by(|(a_path, a), (b_path, b)| {
+        (a.disc, a.number.is_none(), a.number, a_path).cmp(&(
+            b.disc,
+            b.number.is_none(),
+            b.number,
+            b_path,
+        ))
+    });
+    let tracks: Vec<Track> = tracks.into_iter().map(|(_, track)| track).collect();
+
+    let booklet = match format {
+        BookletFormat::Markdown => to_markdown(&tracks),
+        BookletFormat::Html => to_html(&tracks),
+    };
+    match output {
+        Some(output) => {
+            fs::write(output, booklet)?;
+            println!(
+                "{} {}",
+                "Wrote:".green().bold(),
+                format!("booklet of {} tracks to {}", tracks.len(), output.display())
+                    .bright_green()
+            );
+        }
+        None => print!("{}", booklet),
+    }
+
+    let missing = tracks.iter().filter(|track| track.lyrics.is_none()).count();
+    if missing > 0 {
+        eprintln!(
+            "{} {}",
+            "Missing:".yellow().bold(),
+            format!("{} tracks have no lyrics", missing).yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Reads a track's position and names from its tags, falling back to the file name, and
+/// its lyrics as plain text
+fn read_track(path: &PathBuf, args: &Cli) -> Track {
+    let tagged_file = Probe::open(path).and_then(|probe| probe.read()).ok();
+    let tag = tagged_file.as_ref().and_then(|file| file.primary_tag());
+    let text = |value: Option<std::borrow::Cow<'_, str>>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let lyrics = if find_instrumental_placeholder(path, args).is_some() {
+        Some(String::new())
+    } else {
+        find_lyrics(path, args).map(|(lyrics, extension)| match extension {
+            "lrc" => lrc::to_plain_text(&lyrics),
+            _ => lyrics,
+        })
+    };
+
+    Track {
+        disc: tag.and_then(|tag| tag.disk()).unwrap_or(1),
+        number: tag.and_then(|tag| tag.track()),
+        title: text(tag.and_then(|tag| tag.title()))
+            .or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default(),
+        artist: text(tag.and_then(|tag| tag.artist())),
+        album: text(tag.and_then(|tag| tag.album())),
+        lyrics: lyrics.map(|lyrics| lyrics.trim().to_string()),
+    }
+}
+
+/// Album title and artist for the booklet's heading; the artist is only given when every
+/// track shares it, so compilations credit each track instead
+fn album_heading(tracks: &[Track]) -> (String, Option<&str>) {
+    let album = tracks
+        .iter()
+        .find_map(|track| track.album.clone())
+        .unwrap_or_else(|| "Lyrics".to_string());
+    let artist = tracks[0].artist.as_deref();
+    let shared = tracks.iter().all(|track| track.artist.as_deref() == artist);
+    (album, artist.filter(|_| shared))
+}
+
+/// The track's heading, numbered when it has a track number and credited when the album
+/// has no single artist
+fn track_heading(track: &Track, album_artist: Option<&str>) -> String {
+    let mut heading = match track.number {
+        Some(number) => format!("{}. {}", number, track.title),
+        None => track.title.clone(),
+    };
+    if album_artist.is_none()
+        && let Some(artist) = &track.artist
+    {
+        heading.push_str(&format!(" — {}", artist));
+    }
+    heading
+}
+
+fn has_several_discs(tracks: &[Track]) -> bool {
+    tracks.iter().any(|track| track.disc != tracks[0].disc)
+}
+
+fn to_markdown(tracks: &[Track]) -> String {
+    let (album, artist) = album_heading(tracks);
+    let several_discs = has_several_discs(tracks);
+    let mut markdown = format!("# {}\n", album);
+    if let Some(artist) = artist {
+        let _ = write!(markdown, "\n*{}*\n", artist);
+    }
+
+    let mut disc = None;
+    for track in tracks {
+        if several_discs && disc != Some(track.disc) {
+            disc = Some(track.disc);
+            let _ = write!(markdown, "\n## Disc {}\n", track.disc);
+        }
+        let level = if several_discs { "###" } else { "##" };
+        let _ = write!(markdown, "\n{} {}\n\n", level, track_heading(track, artist));
+        match track.lyrics.as_deref() {
+            None => markdown.push_str("*No lyrics*\n"),
+            Some("") => markdown.push_str("*Instrumental*\n"),
+            Some(lyrics) => {
+                // A trailing backslash keeps each lyrics line on its own line
+                let lines: Vec<&str> = lyrics.lines().map(str::trim_end).collect();
+                for (index, line) in lines.iter().enumerate() {
+                    let last = index + 1 == lines.len() || lines[index + 1].is_empty();
+                    match (line.is_empty(), last) {
+                        (true, _) => markdown.push('\n'),
+                        (false, true) => {
+                            let _ = writeln!(markdown, "{}", line);
+                        }
+                        (false, false) => {
+                            let _ = writeln!(markdown, "{}\\", line);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    markdown
+}
+
+/// Page style of the HTML booklet, laid out to print well (or be saved as a PDF) from a
+/// browser with each track starting on a new page
+const HTML_STYLE: &str =
+    "body { font-family: Georgia, serif; max-width: 40em; margin: 2em auto; line-height: 1.5; }
+h1 { text-align: center; margin-bottom: 0; }
+.artist { text-align: center; font-style: italic; margin-top: 0.25em; }
+h2, h3 { margin-top: 2em; }
+.lyrics { white-space: pre-line; }
+.note { font-style: italic; color: #666; }
+@media print { section { break-before: page; } }";
+
+fn to_html(tracks: &[Track]) -> String {
+    let (album, artist) = album_heading(tracks);
+    let several_discs = has_several_discs(tracks);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape_html(&album),
+        HTML_STYLE,
+        escape_html(&album)
+    );
+    if let Some(artist) = artist {
+        let _ = writeln!(html, "<p class=\"artist\">{}</p>", escape_html(artist));
+    }
+
+    let mut disc = None;
+    for track in tracks {
+        if several_discs && disc != Some(track.disc) {
+            disc = Some(track.disc);
+            let _ = writeln!(html, "<h2>Disc {}</h2>", track.disc);
+        }
+        let level = if several_discs { "h3" } else { "h2" };
+        let _ = writeln!(
+            html,
+            "<section>\n<{level}>{}</{level}>",
+            escape_html(&track_heading(track, artist))
+        );
+        match track.lyrics.as_deref() {
+            None => html.push_str("<p class=\"note\">No lyrics</p>\n"),
+            Some("") => html.push_str("<p class=\"note\">Instrumental</p>\n"),
+            Some(lyrics) => {
+                let _ = writeln!(html, "<p class=\"lyrics\">{}</p>", escape_html(lyrics));
+            }
+        }
+        html.push_str("</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
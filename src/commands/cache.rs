@@ -0,0 +1,53 @@
+use crate::cache::CacheStats;
+use crate::error::{LrcphileError, Result};
+use crate::{CacheAction, Cli};
+use colored::Colorize;
+
+/// Reports on or trims the response cache
+pub fn run(action: CacheAction, args: &Cli) -> Result<()> {
+    let cache = args.response_cache().ok_or_else(|| {
+        LrcphileError::Provider("Could not determine the cache directory".to_string())
+    })?;
+
+    match action {
+        CacheAction::Stats => {
+            let stats = cache.stats();
+            println!("  {} {}", "Location:".white(), cache.dir().display());
+            println!(
+                "  {} {}",
+                "Entries:".white(),
+                stats.entries.to_string().bright_white().bold()
+            );
+            println!(
+                "  {} {} of {}",
+                "Size:".white(),
+                format_size(stats.bytes).bright_white().bold(),
+                format_size(cache.max_bytes())
+            );
+        }
+        CacheAction::Clear => report(cache.clear()?),
+        CacheAction::Prune => report(cache.prune()?),
+    }
+    Ok(())
+}
+
+fn report(removed: CacheStats) {
+    println!(
+        "{} {}",
+        "Removed:".green().bold(),
+        format!(
+            "{} cached responses ({})",
+            removed.entries,
+            format_size(removed.bytes)
+        )
+        .bright_green()
+    );
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
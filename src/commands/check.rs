@@ -0,0 +1,66 @@
+use crate::error::Result;
+use crate::manifest::{Manifest, file_hash};
+use colored::Colorize;
+use std::path::Path;
+
+/// Verifies that every lyrics file of a manifest still exists with the same contents, without
+/// writing anything; exits with an error status when any drifted
+pub fn run(manifest_path: &Path, dir: Option<&Path>) -> Result<()> {
+    let manifest = Manifest::load(manifest_path)?;
+    // A library restored or moved elsewhere is checked where it is now
+    let root = dir.unwrap_or(&manifest.root);
+    println!(
+        "{} {}",
+        "Checking:".green().bold(),
+        format!(
+            "{} lyrics files recorded {} under {}",
+            manifest.files.len(),
+            manifest.created,
+            root.display()
+        )
+        .bright_cyan()
+    );
+
+    let mut missing = 0;
+    let mut changed = 0;
+    for entry in &manifest.files {
+        let lyrics_path = root.join(&entry.path);
+        match file_hash(&lyrics_path) {
+            Ok(hash) if hash == entry.sha256 => {}
+            Ok(_) => {
+                changed += 1;
+                println!(
+                    "{} {}",
+                    "Changed:".yellow().bold(),
+                    lyrics_path.display().to_string().yellow()
+                );
+            }
+            Err(_) => {
+                missing += 1;
+                println!(
+                    "{} {}",
+                    "Missing:".red().bold(),
+                    lyrics_path.display().to_string().red()
+                );
+            }
+        }
+    }
+
+    let intact = manifest.files.len() - missing - changed;
+    println!(
+        "{} {}",
+        "Verified:".green().bold(),
+        format!(
+            "{} of {} lyrics files intact, {} changed, {} missing",
+            intact,
+            manifest.files.len(),
+            changed,
+            missing
+        )
+        .bright_green()
+    );
+    if missing + changed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use crate::error::Result;
+use crate::{
+    Cli, find_instrumental_placeholder, lyrics_file_exists, process_directory,
+    remove_embedded_lyrics,
+};
+use colored::Colorize;
+use std::{fs, path::Path};
+
+/// Removes files lrcphile wrote from a library, and with `embedded` the lyrics in its tags
+pub fn run(
+    dir: &Path,
+    instrumentals: bool,
+    embedded: bool,
+    only_with_sidecar: bool,
+    args: &Cli,
+) -> Result<()> {
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let audio_files = process_directory(dir, &scan_args)?;
+
+    let mut removed = 0;
+    let mut stripped = 0;
+    for audio_path in &audio_files {
+        let placeholder = instrumentals
+            .then(|| find_instrumental_placeholder(audio_path, args))
+            .flatten();
+        if let Some(placeholder) = placeholder {
+            match fs::remove_file(&placeholder) {
+                Ok(()) => removed += 1,
+                Err(e) => eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!("Failed to remove {}: {}", placeholder.display(), e).yellow()
+                ),
+            }
+        }
+
+        let has_sidecar = || {
+            lyrics_file_exists(audio_path, "lrc", args).unwrap_or(false)
+                || lyrics_file_exists(audio_path, "txt", args).unwrap_or(false)
+        };
+        if embedded && (!only_with_sidecar || has_sidecar()) {
+            match remove_embedded_lyrics(audio_path) {
+                Ok(true) => stripped += 1,
+                Ok(false) => {}
+                Err(e) => eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!(
+                        "Failed to remove embedded lyrics from {}: {}",
+                        audio_path.display(),
+                        e
+                    )
+                    .yellow()
+                ),
+            }
+        }
+    }
+
+    if instrumentals {
+        println!(
+            "{} {}",
+            "Removed:".green().bold(),
+            format!("{} instrumental placeholders", removed).bright_green()
+        );
+    }
+    if embedded {
+        println!(
+            "{} {}",
+            "Removed:".green().bold(),
+            format!("embedded lyrics from {} audio files", stripped).bright_green()
+        );
+    }
+    Ok(())
+}
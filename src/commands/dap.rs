@@ -0,0 +1,244 @@
+use super::export::{device_key, device_lyrics_path, shared_device_paths};
+use crate::error::{LrcphileError, Result};
+use crate::lock::LibraryLock;
+use crate::{
+    Cli, Console, DeviceLayout, FileOutcome, ProcessingStats, Throttle, arrange_lyrics,
+    encode_lyrics, fetch_with_retry, prepare_lyrics_file, progress_bar, read_lyrics_file,
+    read_metadata, write_lyrics_file, writing_to,
+};
+use colored::Colorize;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Rockbox keeps each tag of its database in a file of its own; this one holds the paths
+const ROCKBOX_FILENAME_TAGS: &str = ".rockbox/database_4.tcd";
+
+/// Top bytes of every Rockbox database file's magic, followed by a format version byte
+const ROCKBOX_MAGIC: u32 = 0x544348;
+
+/// Folder Rockbox's lyrics plugin looks in besides each track's own
+const ROCKBOX_LYRICS_DIR: &str = "Lyrics";
+
+/// Fetches lyrics for the tracks a portable player knows about, from its Rockbox database or
+/// a track list exported from it, into the folder the player reads lyrics from
+pub async fn run(
+    mount: &Path,
+    tracks: Option<&Path>,
+    device: DeviceLayout,
+    to: Option<&Path>,
+    args: &Cli,
+) -> Result<()> {
+    let device_paths = match tracks {
+        Some(list) => read_track_list(list)?,
+        None => {
+            let database = mount.join(ROCKBOX_FILENAME_TAGS);
+            if !database.is_file() {
+                return Err(LrcphileError::InvalidPath {
+                    path: database,
+                    reason: "no Rockbox database there; pass --tracks with a track list exported from the player",
+                });
+            }
+            read_rockbox_database(&database)?
+        }
+    };
+    let listed = device_paths.len();
+    let audio_files: Vec<PathBuf> = device_paths
+        .iter()
+        .map(|device_path| on_mount(mount, device_path))
+        .filter(|track| track.is_file())
+        .collect();
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!(
+            "{} tracks on the player, {} listed tracks not there",
+            audio_files.len(),
+            listed - audio_files.len()
+        )
+        .bright_cyan()
+    );
+
+    // HiBy players read lyrics beside each track, Rockbox from one folder as well
+    let to = to.map(Path::to_path_buf).unwrap_or_else(|| match device {
+        DeviceLayout::Rockbox => mount.join(ROCKBOX_LYRICS_DIR),
+        DeviceLayout::Hiby => mount.to_path_buf(),
+    });
+    let device_args = writing_to(args, &to);
+    let shared = shared_device_paths(
+        device,
+        &to,
+        audio_files
+            .iter()
+            .map(|track| (track, track.strip_prefix(mount).ok().map(Path::to_path_buf))),
+    );
+    // Held while syncing, so a second sync to the same player refuses to start
+    let _lock = LibraryLock::acquire(mount, args.break_lock)?;
+
+    let provider = args.lyrics_provider()?;
+    let throttle = Arc::new(Throttle::default());
+    let progress = progress_bar(audio_files.len());
+    progress.set_message("Fetching lyrics for the player...");
+    let console = Console::with_progress(&progress);
+    let mut stats = ProcessingStats::new(audio_files.len());
+
+    stream::iter(audio_files)
+        .map(|track| {
+            let provider = provider.clone();
+            let throttle = throttle.clone();
+            let console = console.clone();
+            let to = &to;
+            let device_args = &device_args;
+            let shared = &shared;
+            async move {
+                let relative = track.strip_prefix(mount).ok();
+                if device_key(device, to, &track, relative).is_some_and(|key| shared.contains(&key))
+                {
+                    console.failed(
+                        &track,
+                        "Another track has the same file name, so their lyrics would overwrite \
+                         each other in one folder; use --device hiby to mirror the folders",
+                    );
+                    return (track, FileOutcome::Failed);
+                }
+                let target =
+                    |extension| device_lyrics_path(device, to, &track, relative, extension);
+                let exists = ["lrc", "txt"]
+                    .into_iter()
+                    .any(|extension| target(extension).is_some_and(|target| target.exists()));
+                if exists && !args.override_files {
+                    return (track, FileOutcome::Skipped);
+                }
+
+                let written = async {
+                    let metadata = read_metadata(&track, args).await?;
+                    let lyrics = fetch_with_retry(
+                        provider.as_ref(),
+                        &metadata,
+                        &throttle,
+                        &console,
+                        args.search_fallback,
+                        false,
+                    )
+                    .await?;
+                    let lyrics_file = prepare_lyrics_file(
+                        &lyrics,
+                        args.instrumental_placeholder,
+                        args.lyrics_type,
+                    )
+                    .ok_or(LrcphileError::NotFound)?;
+                    let target = target(lyrics_file.extension).ok_or_else(|| {
+                        LrcphileError::InvalidPath {
+                            path: track.clone(),
+                            reason: "Could not determine file name",
+                        }
+                    })?;
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let content = arrange_lyrics(&lyrics_file.content, lyrics_file.extension, args);
+                    write_lyrics_file(&target, encode_lyrics(&content, args), device_args)?;
+                    Ok::<_, LrcphileError>(())
+                }
+                .await;
+                let outcome = match written {
+                    Ok(()) => FileOutcome::Fetched,
+                    Err(LrcphileError::NotFound) => FileOutcome::Failed,
+                    Err(e) => {
+                        console.failed(&track, e);
+                        FileOutcome::Failed
+                    }
+                };
+                (track, outcome)
+            }
+        })
+        .buffer_unordered(args.concurrency())
+        .for_each(|(track, outcome)| {
+            stats.record(&track, outcome, args);
+            progress.inc(1);
+            future::ready(())
+        })
+        .await;
+
+    progress.finish_with_message("Processing complete!");
+    stats.display_summary();
+    Ok(())
+}
+
+/// Paths of a track list, one per line as the player sees them; `#` lines are comments, so
+/// M3U playlists work too
+fn read_track_list(list: &Path) -> Result<Vec<String>> {
+    Ok(read_lyrics_file(list)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads the track paths out of a Rockbox `database_4.tcd`: a header of magic, data size and
+/// entry count, then for each entry its length, its index and the NUL-padded path, all in
+/// the player's byte order
+fn read_rockbox_database(database: &Path) -> Result<Vec<String>> {
+    let data = fs::read(database)?;
+    let invalid =
+        || LrcphileError::Parse(format!("{}: not a Rockbox database", database.display()));
+    let header: [u8; 4] = data
+        .get(..4)
+        .and_then(|magic| magic.try_into().ok())
+        .ok_or_else(invalid)?;
+    let little_endian = if u32::from_le_bytes(header) >> 8 == ROCKBOX_MAGIC {
+        true
+    } else if u32::from_be_bytes(header) >> 8 == ROCKBOX_MAGIC {
+        false
+    } else {
+        return Err(invalid());
+    };
+    let read_u32 = |offset: usize| {
+        let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        } as usize)
+    };
+
+    let entry_count = read_u32(8).ok_or_else(invalid)?;
+    let mut paths = Vec::with_capacity(entry_count.min(100_000));
+    let mut offset = 12;
+    while paths.len() < entry_count {
+        let Some(length) = read_u32(offset) else {
+            break;
+        };
+        let start = offset + 8;
+        let tag = data.get(start..start + length).ok_or_else(invalid)?;
+        let path = tag.split(|&byte| byte == 0).next().unwrap_or_default();
+        if !path.is_empty() {
+            paths.push(String::from_utf8_lossy(path).into_owned());
+        }
+        offset = start + length;
+    }
+    Ok(paths)
+}
+
+/// Where a path as the player sees it, like `/Music/Song.flac` or `/<microSD1>/Music/Song.flac`,
+/// is on the mounted player
+fn on_mount(mount: &Path, device_path: &str) -> PathBuf {
+    let path = Path::new(device_path);
+    if path.starts_with(mount) {
+        return path.to_path_buf();
+    }
+    let device_path = device_path.replace('\\', "/");
+    let mut relative = device_path.trim_start_matches('/');
+    // Rockbox names extra volumes like <microSD1> or <HD1>
+    if relative.starts_with('<')
+        && let Some(end) = relative.find(">/")
+    {
+        relative = &relative[end + 2..];
+    }
+    mount.join(relative)
+}
@@ -0,0 +1,164 @@
+use crate::cache::ResponseCache;
+use crate::config::Config;
+use crate::error::Result;
+use crate::provider::{self, ClientOptions};
+use crate::state::StateDb;
+use crate::{AUDIO_EXTENSIONS, Cli, default_music_dir};
+use colored::Colorize;
+use std::{fs, path::Path, time::Instant};
+
+/// Runs every check and prints the effective configuration, failing if any check failed
+pub async fn run(args: &Cli, config: &Config) -> Result<()> {
+    let mut problems = 0;
+    let mut report = |ok: bool, message: String| {
+        if ok {
+            println!("  {} {}", "ok".green().bold(), message);
+        } else {
+            problems += 1;
+            println!("  {} {}", "!!".red().bold(), message.red());
+        }
+    };
+
+    println!("{}", "Checks:".bright_cyan().bold());
+
+    match Config::default_location() {
+        Some(location) if location.exists() => match Config::load(&location) {
+            Ok(_) => report(true, format!("Config file {} is valid", location.display())),
+            Err(e) => report(false, format!("Config file is invalid: {}", e)),
+        },
+        Some(location) => report(
+            true,
+            format!("No config file at {} (using defaults)", location.display()),
+        ),
+        None => report(
+            false,
+            "Could not determine the config directory".to_string(),
+        ),
+    }
+
+    let (reachable, message) = check_instance(&args.url, &args.client_options()).await;
+    report(reachable, message);
+
+    match args.lyrics_provider() {
+        Ok(_) => report(
+            true,
+            format!("Providers {} are set up", args.providers.join(", ")),
+        ),
+        Err(e) => report(false, format!("Providers can't be set up: {}", e)),
+    }
+
+    let music_dir = args.path.clone().or_else(|| default_music_dir(config));
+    match &music_dir {
+        Some(dir) => {
+            let (writable, message) = check_writable(dir);
+            report(writable, message);
+        }
+        None => report(false, "Could not determine the music directory".to_string()),
+    }
+
+    println!("\n{}", "Effective configuration:".bright_cyan().bold());
+    let describe = |path: Option<&Path>| {
+        path.map(|path| path.display().to_string())
+            .unwrap_or_else(|| "(none)".to_string())
+    };
+    println!("  {} {}", "Instance:".white(), args.url);
+    println!("  {} {}", "Providers:".white(), args.providers.join(", "));
+    for (host, ip) in &args.resolve {
+        println!("  {} {} -> {}", "Resolve:".white(), host, ip);
+    }
+    println!(
+        "  {} {}",
+        "Music directory:".white(),
+        describe(music_dir.as_deref())
+    );
+    println!(
+        "  {} {}",
+        "Config file:".white(),
+        describe(Config::default_location().as_deref())
+    );
+    println!(
+        "  {} {}",
+        "State file:".white(),
+        describe(
+            args.state_file
+                .clone()
+                .or_else(StateDb::default_location)
+                .as_deref()
+        )
+    );
+    println!(
+        "  {} {}",
+        "Response cache:".white(),
+        if args.no_cache {
+            "(disabled)".to_string()
+        } else {
+            describe(ResponseCache::default_location().as_deref())
+        }
+    );
+    println!(
+        "  {} {}",
+        "Audio formats:".white(),
+        AUDIO_EXTENSIONS.join(", ")
+    );
+
+    if problems > 0 {
+        println!(
+            "\n{}",
+            format!("{} problem(s) found", problems).red().bold()
+        );
+        std::process::exit(1);
+    }
+    println!("\n{}", "No problems found".green().bold());
+    Ok(())
+}
+
+/// Makes a cheap search request to confirm the instance answers like LRCLIB
+async fn check_instance(url: &str, options: &ClientOptions) -> (bool, String) {
+    let (base_url, transport) = match provider::connect(url, options) {
+        Ok(connection) => connection,
+        Err(e) => return (false, format!("Failed to initialize HTTP client: {}", e)),
+    };
+
+    let api_url = format!("{}/api/search?q=lrcphile", base_url.trim_end_matches('/'));
+    let started = Instant::now();
+    match transport.get(&api_url, Vec::new()).await {
+        Ok(response) if (200..=299).contains(&response.status) => (
+            true,
+            format!(
+                "Instance {} responded in {} ms",
+                url,
+                started.elapsed().as_millis()
+            ),
+        ),
+        Ok(response) => (
+            false,
+            format!("Instance {} answered with status {}", url, response.status),
+        ),
+        Err(e) => (false, format!("Instance {} is unreachable: {}", url, e)),
+    }
+}
+
+/// Creates and removes a scratch file to confirm lyrics files can be written
+fn check_writable(dir: &Path) -> (bool, String) {
+    if !dir.is_dir() {
+        return (
+            false,
+            format!("Music directory {} does not exist", dir.display()),
+        );
+    }
+
+    let probe = dir.join(".lrcphile-doctor");
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            (
+                true,
+                format!("Music directory {} is writable", dir.display()),
+            )
+        }
+        Err(e) => (
+            false,
+            format!("Music directory {} is not writable: {}", dir.display(), e),
+        ),
+    }
+}
@@ -0,0 +1,197 @@
+use crate::config::Config;
+use crate::error::{LrcphileError, Result};
+use crate::lrc::Lrc;
+use crate::{
+    Cli, DeviceLayout, arrange_lyrics, default_music_dir, encode_lyrics, get_lyrics_file_path,
+    read_embedded_lyrics, read_lyrics_file, write_lyrics_file, writing_to,
+};
+use colored::Colorize;
+use reqwest::Url;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Writes the lyrics of a playlist's tracks to a device directory, named the way the device
+/// looks them up
+pub fn run(
+    playlist: &Path,
+    to: &Path,
+    device: DeviceLayout,
+    args: &Cli,
+    config: &Config,
+) -> Result<()> {
+    let tracks = read_playlist(playlist)?;
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} tracks in {}", tracks.len(), playlist.display()).bright_cyan()
+    );
+
+    let playlist_dir = playlist.parent().unwrap_or(Path::new(""));
+    let device_args = writing_to(args, to);
+    let music_dir = default_music_dir(config);
+    let relative_path = |track: &PathBuf| {
+        track
+            .strip_prefix(playlist_dir)
+            .ok()
+            .or_else(|| track.strip_prefix(music_dir.as_ref()?).ok())
+            .map(Path::to_path_buf)
+    };
+    let shared = shared_device_paths(
+        device,
+        to,
+        tracks.iter().map(|track| (track, relative_path(track))),
+    );
+    let mut exported = 0;
+    let mut missing = 0;
+    let mut clashing = 0;
+    for track in &tracks {
+        let relative = relative_path(track);
+        if device_key(device, to, track, relative.as_deref())
+            .is_some_and(|key| shared.contains(&key))
+        {
+            clashing += 1;
+            continue;
+        }
+        let Some((lyrics, extension)) = find_lyrics(track, args) else {
+            missing += 1;
+            continue;
+        };
+
+        let Some(target) = device_lyrics_path(device, to, track, relative.as_deref(), extension)
+        else {
+            missing += 1;
+            continue;
+        };
+
+        let written = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(LrcphileError::from)
+            .and_then(|()| {
+                let lyrics = arrange_lyrics(&lyrics, extension, args);
+                write_lyrics_file(&target, encode_lyrics(&lyrics, args), &device_args)
+            });
+        match written {
+            Ok(()) => exported += 1,
+            Err(e) => eprintln!(
+                "{} {}",
+                "Failed:".red().bold(),
+                format!("Writing {}: {}", target.display(), e).red()
+            ),
+        }
+    }
+
+    println!(
+        "{} {}",
+        "Exported:".green().bold(),
+        format!("{} lyrics files to {}", exported, to.display()).bright_green()
+    );
+    if missing > 0 {
+        println!(
+            "{} {}",
+            "Missing:".yellow().bold(),
+            format!("{} tracks have no lyrics", missing).yellow()
+        );
+    }
+    if clashing > 0 {
+        println!(
+            "{} {}",
+            "Skipped:".yellow().bold(),
+            format!(
+                "{} tracks share a file name with another track, so their lyrics would \
+                 overwrite each other in one folder; use --device hiby to mirror the folders",
+                clashing
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Lyrics files on the device that more than one track would be written to, as when two
+/// albums in Rockbox's flat folder both have an `01 Intro.flac`. Those tracks are left out
+/// rather than given each other's lyrics. Compared without case, since players mostly use FAT
+pub(super) fn shared_device_paths<'a>(
+    device: DeviceLayout,
+    to: &Path,
+    tracks: impl IntoIterator<Item = (&'a PathBuf, Option<PathBuf>)>,
+) -> HashSet<String> {
+    let mut claims: HashMap<String, usize> = HashMap::new();
+    for (track, relative) in tracks {
+        if let Some(key) = device_key(device, to, track, relative.as_deref()) {
+            *claims.entry(key).or_default() += 1;
+        }
+    }
+    claims
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// The device lyrics path of a track, lowercased and without extension, to find tracks
+/// that would share one
+pub(super) fn device_key(
+    device: DeviceLayout,
+    to: &Path,
+    track: &Path,
+    relative: Option<&Path>,
+) -> Option<String> {
+    let target = device_lyrics_path(device, to, track, relative, "lrc")?;
+    Some(target.with_extension("").to_string_lossy().to_lowercase())
+}
+
+/// Where a device looks for a track's lyrics: a flat directory of files named after the audio
+/// files for Rockbox, or a tree mirroring the track's `relative` path for HiBy
+pub(super) fn device_lyrics_path(
+    device: DeviceLayout,
+    to: &Path,
+    track: &Path,
+    relative: Option<&Path>,
+    extension: &str,
+) -> Option<PathBuf> {
+    // Built by hand, since `with_extension` would cut stems like `01. Intro` at their dot
+    let file_name = format!("{}.{}", track.file_stem()?.to_string_lossy(), extension);
+    Some(match (device, relative) {
+        (DeviceLayout::Hiby, Some(relative)) => to.join(relative).with_file_name(file_name),
+        _ => to.join(file_name),
+    })
+}
+
+/// Track paths of an M3U/M3U8 playlist, relative entries resolved against its directory
+fn read_playlist(playlist: &Path) -> Result<Vec<PathBuf>> {
+    // Plain .m3u files are often in a legacy encoding rather than UTF-8
+    let content = read_lyrics_file(playlist)?;
+    let base = playlist.parent().unwrap_or(Path::new(""));
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // `file://` URLs escape spaces and non-ASCII characters, which `Url` decodes
+        .map(|line| match Url::parse(line) {
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .unwrap_or_else(|()| PathBuf::from(url.path())),
+            _ => base.join(line),
+        })
+        .collect())
+}
+
+/// The track's lyrics and the extension they should be written with: its sidecar files
+/// first, then lyrics embedded in its tags
+pub(super) fn find_lyrics(track: &Path, args: &Cli) -> Option<(String, &'static str)> {
+    for extension in ["lrc", "txt"] {
+        let lyrics_path = get_lyrics_file_path(track, extension, args).ok()?;
+        if lyrics_path.exists() {
+            return Some((read_lyrics_file(&lyrics_path).ok()?, extension));
+        }
+    }
+
+    let lyrics = read_embedded_lyrics(track).ok()??;
+    let synced = Lrc::parse(&lyrics).is_synced();
+    Some((lyrics, if synced { "lrc" } else { "txt" }))
+}
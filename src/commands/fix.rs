@@ -0,0 +1,490 @@
+use crate::error::{LrcphileError, Result};
+use crate::lrc::{Lrc, LrcLine};
+use crate::provider::{LyricsResponse, MatchKind};
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, INSTRUMENTAL_EXTENSION, Throttle, TrackMetadata, editor, fix_tags,
+    get_lyrics_file_path, known_lrclib_id, lyrics_hash, picker, prepare_lyrics_file, prompt,
+    read_embedded_lyrics, read_lyrics_file, read_metadata_with_overrides, save_lyrics_file,
+    with_retry, written_lyrics_hash,
+};
+use colored::Colorize;
+use std::{collections::HashSet, fs, path::Path};
+use tokio::sync::Mutex;
+
+/// Most candidates shown, best first
+const MAX_CANDIDATES: usize = 9;
+
+/// Lyrics lines shown in each column
+const COLUMN_LINES: usize = 12;
+
+/// Narrowest a column gets before further candidates go onto another row
+const MIN_COLUMN_WIDTH: usize = 32;
+
+/// Spaces between two columns
+const COLUMN_GAP: usize = 3;
+
+/// Terminal width assumed when it can't be read
+const DEFAULT_WIDTH: usize = 100;
+
+/// The lyrics a track has now, numbered 0 among the candidates
+struct Current {
+    content: String,
+    extension: &'static str,
+    source: String,
+}
+
+/// Lyrics being worked on before they're saved
+struct Draft {
+    content: String,
+    extension: &'static str,
+    /// Index of the candidate they came from; `None` for the track's current lyrics
+    candidate: Option<usize>,
+    /// The content as picked, before any edit or shift
+    picked: String,
+}
+
+/// Shows a track's current lyrics beside what every provider has for it, and lets the user
+/// pick one, edit or shift it, and save it over the current lyrics
+pub async fn run(path: &Path, args: &Cli, state: &Mutex<StateDb>) -> Result<()> {
+    let path = path.to_path_buf();
+    if !path.is_file() {
+        return Err(LrcphileError::InvalidPath {
+            path,
+            reason: "not an audio file",
+        });
+    }
+    let mut metadata = read_metadata_with_overrides(&path, args).await?;
+    metadata.lrclib_id = known_lrclib_id(&path, Some(&metadata), state, args).await;
+    println!(
+        "{} {} - {} ({}, {}:{:02})",
+        "Fixing:".bright_cyan().bold(),
+        metadata.track_name.bright_white().bold(),
+        metadata.artist_name.bright_white(),
+        metadata.album_name,
+        metadata.duration as u32 / 60,
+        metadata.duration as u32 % 60
+    );
+
+    let current = current_lyrics(&path, args)?;
+    let mut candidates = gather_candidates(&metadata, args).await?;
+    if current.is_none() && candidates.is_empty() {
+        println!(
+            "{}",
+            "No lyrics on disk and none found by any provider.".yellow()
+        );
+        return Ok(());
+    }
+
+    let first = if current.is_some() { 0 } else { 1 };
+    'pick: loop {
+        print_columns(&columns(&metadata, current.as_ref(), &candidates));
+        let mut draft = loop {
+            let Some(answer) = prompt::ask(&format!(
+                "\nWork on which lyrics? [{}-{}/q to quit]: ",
+                first,
+                candidates.len()
+            )) else {
+                return Ok(());
+            };
+            if answer == "q" || answer == "quit" {
+                return Ok(());
+            }
+            match (answer.parse::<usize>(), &current) {
+                (Ok(0), Some(current)) => {
+                    break Draft {
+                        content: current.content.clone(),
+                        extension: current.extension,
+                        candidate: None,
+                        picked: current.content.clone(),
+                    };
+                }
+                (Ok(choice), _) if (1..=candidates.len()).contains(&choice) => {
+                    let candidate = &mut candidates[choice - 1];
+                    candidate.matched = MatchKind::Picked;
+                    match prepare_lyrics_file(
+                        candidate,
+                        args.instrumental_placeholder,
+                        args.lyrics_type,
+                    ) {
+                        Some(lyrics_file) => {
+                            break Draft {
+                                picked: lyrics_file.content.clone(),
+                                content: lyrics_file.content,
+                                extension: lyrics_file.extension,
+                                candidate: Some(choice - 1),
+                            };
+                        }
+                        None => println!(
+                            "{} {}",
+                            "Note:".yellow().bold(),
+                            "--lyrics-type or --instrumental-placeholder leave nothing to write"
+                                .yellow()
+                        ),
+                    }
+                }
+                _ => println!(
+                    "{}",
+                    format!(
+                        "Enter a number from {} to {}, or q.",
+                        first,
+                        candidates.len()
+                    )
+                    .yellow()
+                ),
+            }
+        };
+
+        loop {
+            println!();
+            for line in lyrics_lines(&draft.content).take(COLUMN_LINES) {
+                println!("  {}", line);
+            }
+            let Some(answer) = prompt::ask(
+                "[s]ave, [e]dit, shift by MS (+250 later, -250 sooner), [b]ack, [q]uit: ",
+            ) else {
+                return Ok(());
+            };
+            match answer.as_str() {
+                "s" | "save" => {
+                    let candidate = draft.candidate.map(|index| &candidates[index]);
+                    return save(&path, &draft, candidate, &metadata, args, state).await;
+                }
+                "e" | "edit" => match editor::edit_lyrics(&draft.content, draft.extension) {
+                    Ok(Some(content)) => draft.content = content,
+                    Ok(None) => {}
+                    Err(e) => println!(
+                        "{} {}",
+                        "Warning:".yellow().bold(),
+                        format!("Failed to edit the lyrics: {}", e).yellow()
+                    ),
+                },
+                "b" | "back" => continue 'pick,
+                "q" | "quit" => return Ok(()),
+                answer => match answer.parse::<i64>() {
+                    Ok(millis) => {
+                        let mut lrc = Lrc::parse(&draft.content);
+                        if lrc.is_synced() {
+                            lrc.shift(millis);
+                            draft.content = lrc.to_string();
+                            println!(
+                                "{} {}",
+                                "Shifted:".green().bold(),
+                                format!("by {:+} ms", millis).bright_green()
+                            );
+                        } else {
+                            println!("{}", "These lyrics have no timestamps to shift.".yellow());
+                        }
+                    }
+                    Err(_) => println!(
+                        "{}",
+                        "Enter s, e, b, q or a number of milliseconds.".yellow()
+                    ),
+                },
+            }
+        }
+    }
+}
+
+/// The track's lyrics file, or else the lyrics in its tags
+fn current_lyrics(path: &Path, args: &Cli) -> Result<Option<Current>> {
+    for extension in ["lrc", "txt"] {
+        let lyrics_path = get_lyrics_file_path(path, extension, args)?;
+        if lyrics_path.exists() {
+            return Ok(Some(Current {
+                content: read_lyrics_file(&lyrics_path)?,
+                extension,
+                source: lyrics_path.display().to_string(),
+            }));
+        }
+    }
+
+    // Unreadable tags just mean there are no embedded lyrics to show
+    Ok(read_embedded_lyrics(path).ok().flatten().map(|lyrics| {
+        let extension = if Lrc::parse(&lyrics).is_synced() {
+            "lrc"
+        } else {
+            "txt"
+        };
+        Current {
+            content: lyrics,
+            extension,
+            source: "embedded in the audio file".to_string(),
+        }
+    }))
+}
+
+/// Asks each provider for the track directly and by search, keeping the records with lyrics,
+/// most likely first; a provider that fails is reported and left out
+async fn gather_candidates(metadata: &TrackMetadata, args: &Cli) -> Result<Vec<LyricsResponse>> {
+    let throttle = Throttle::default();
+    let console = Console::default();
+    let mut candidates = Vec::new();
+    for (name, provider) in args.providers.iter().zip(args.lyrics_providers()?) {
+        let fetched = with_retry(&throttle, &console, || provider.fetch(metadata)).await;
+        let searched = with_retry(&throttle, &console, || provider.search(metadata))
+            .await
+            .map(|results| {
+                results
+                    .into_iter()
+                    .map(|result| LyricsResponse {
+                        matched: MatchKind::Search,
+                        ..result
+                    })
+                    .collect()
+            });
+        for result in [fetched.map(|record| vec![record]), searched] {
+            match result {
+                Ok(records) => candidates.extend(records),
+                Err(LrcphileError::NotFound) => {}
+                Err(e) => println!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!("{} failed: {}", name, e).yellow()
+                ),
+            }
+        }
+    }
+
+    // The direct match usually turns up in the search too
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate: &LyricsResponse| {
+        (candidate.instrumental
+            || candidate.synced_lyrics.is_some()
+            || candidate.plain_lyrics.is_some())
+            && seen.insert((candidate.provider.clone(), candidate.id))
+    });
+    for candidate in &mut candidates {
+        candidate.confidence = Some(picker::confidence(metadata, candidate));
+    }
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.confidence));
+    candidates.truncate(MAX_CANDIDATES);
+    Ok(candidates)
+}
+
+/// A column of the side by side view: a few heading lines, then the start of the lyrics
+struct Column {
+    heading: [String; 3],
+    lines: Vec<String>,
+}
+
+fn columns(
+    metadata: &TrackMetadata,
+    current: Option<&Current>,
+    candidates: &[LyricsResponse],
+) -> Vec<Column> {
+    let mut columns = Vec::new();
+    if let Some(current) = current {
+        columns.push(Column {
+            heading: [
+                "[0] current".to_string(),
+                current.source.clone(),
+                if current.extension == "lrc" {
+                    "synced"
+                } else {
+                    "plain"
+                }
+                .to_string(),
+            ],
+            lines: lyrics_lines(&current.content).take(COLUMN_LINES).collect(),
+        });
+    }
+    for (index, candidate) in candidates.iter().enumerate() {
+        let kind = if candidate.instrumental {
+            "instrumental"
+        } else if candidate.synced_lyrics.is_some() {
+            "synced"
+        } else {
+            "plain"
+        };
+        let lines = if candidate.instrumental {
+            vec!["(instrumental)".to_string()]
+        } else {
+            lyrics_lines(
+                candidate
+                    .synced_lyrics
+                    .as_deref()
+                    .or(candidate.plain_lyrics.as_deref())
+                    .unwrap_or_default(),
+            )
+            .take(COLUMN_LINES)
+            .collect()
+        };
+        columns.push(Column {
+            heading: [
+                format!(
+                    "[{}] {} - {}",
+                    index + 1,
+                    candidate.track_name,
+                    candidate.artist_name
+                ),
+                format!("{}: {}", candidate.provider, candidate.album_name),
+                format!(
+                    "{:+.0}s, {}, {}%",
+                    candidate.duration - metadata.duration,
+                    kind,
+                    candidate.confidence.unwrap_or(0)
+                ),
+            ],
+            lines,
+        });
+    }
+    columns
+}
+
+/// The lyrics lines of some content, without its header tags and blank lines
+fn lyrics_lines(content: &str) -> impl Iterator<Item = String> {
+    Lrc::parse(content)
+        .lines
+        .into_iter()
+        .filter(|line| match line {
+            LrcLine::Tag { .. } => false,
+            LrcLine::Other(text) => !text.trim().is_empty(),
+            LrcLine::Lyric { .. } => true,
+        })
+        .map(|line| line.to_string())
+}
+
+/// Prints the columns side by side, as many to a row as fit the terminal
+fn print_columns(columns: &[Column]) {
+    let width = ratatui::crossterm::terminal::size()
+        .map(|(width, _)| width as usize)
+        .unwrap_or(DEFAULT_WIDTH);
+    let per_row = ((width + COLUMN_GAP) / (MIN_COLUMN_WIDTH + COLUMN_GAP)).clamp(1, columns.len());
+    let column_width = ((width + COLUMN_GAP) / per_row)
+        .saturating_sub(COLUMN_GAP)
+        .max(1);
+    let fit = |text: &str| {
+        let text = if text.chars().count() > column_width {
+            let cut: String = text.chars().take(column_width - 1).collect();
+            format!("{}…", cut)
+        } else {
+            text.to_string()
+        };
+        format!("{:<width$}", text, width = column_width)
+    };
+    let gap = " ".repeat(COLUMN_GAP);
+
+    for row in columns.chunks(per_row) {
+        println!();
+        for line in 0..3 {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|column| {
+                    let cell = fit(&column.heading[line]);
+                    match line {
+                        0 => cell.bold().to_string(),
+                        2 => cell.dimmed().to_string(),
+                        _ => cell,
+                    }
+                })
+                .collect();
+            println!("{}", cells.join(&gap).trim_end());
+        }
+        let height = row
+            .iter()
+            .map(|column| column.lines.len())
+            .max()
+            .unwrap_or(0);
+        for line in 0..height {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|column| fit(column.lines.get(line).map_or("", String::as_str)))
+                .collect();
+            println!("{}", cells.join(&gap).trim_end());
+        }
+    }
+}
+
+/// Writes the lyrics in place of the track's current ones and records them in the state file
+async fn save(
+    path: &Path,
+    draft: &Draft,
+    candidate: Option<&LyricsResponse>,
+    metadata: &TrackMetadata,
+    args: &Cli,
+    state: &Mutex<StateDb>,
+) -> Result<()> {
+    let changed = draft.content != draft.picked;
+    if candidate.is_none() && !changed {
+        println!("{}", "Nothing changed; the current lyrics stay.".green());
+        return Ok(());
+    }
+
+    // The record is remembered with the tags the track ends up with
+    let mut tags = metadata.clone();
+    if args.fix_tags
+        && let Some(candidate) = candidate
+    {
+        match fix_tags(
+            path,
+            &candidate.track_name,
+            &candidate.artist_name,
+            &candidate.album_name,
+        ) {
+            Ok(true) => {
+                tags.track_name = candidate.track_name.clone();
+                tags.artist_name = candidate.artist_name.clone();
+                tags.album_name = candidate.album_name.clone();
+            }
+            Ok(false) => {}
+            Err(e) => println!(
+                "{} {}",
+                "Warning:".yellow().bold(),
+                format!("Failed to fix tags: {}", e).yellow()
+            ),
+        }
+    }
+    // The user saw the current lyrics beside the one they picked, so hand-made or edited
+    // lyrics are replaced knowingly
+    let args = &Cli {
+        force: true,
+        ..args.clone()
+    };
+    let lyrics_path = save_lyrics_file(
+        path,
+        &draft.content,
+        draft.extension,
+        Some(&tags),
+        state,
+        args,
+    )
+    .await?;
+    println!(
+        "{} {}",
+        "Wrote:".green().bold(),
+        lyrics_path.display().to_string().bright_green()
+    );
+    // Lyrics of another kind would be shown instead of these, or outlive them
+    for extension in ["lrc", "txt", INSTRUMENTAL_EXTENSION] {
+        if extension == draft.extension {
+            continue;
+        }
+        let other = get_lyrics_file_path(path, extension, args)?;
+        if other.exists() {
+            fs::remove_file(&other)?;
+            println!(
+                "{} {}",
+                "Removed:".green().bold(),
+                other.display().to_string().bright_green()
+            );
+        }
+    }
+
+    let mut state = state.lock().await;
+    if let Some(candidate) = candidate {
+        state.record_lrclib_id(path, candidate.lrclib_id(), &tags);
+        state.record_confidence(path, candidate.confidence);
+        state.record_validators(path, candidate.validators.clone());
+    }
+    state.record_no_lyrics(path, None);
+    // Hand edits are kept from later --override runs like any file edited after fetching
+    let hash = if changed {
+        lyrics_hash(&draft.picked)
+    } else {
+        written_lyrics_hash(&lyrics_path, &draft.content)
+    };
+    state.record_lyrics_hash(path, hash);
+    state.release(path, false);
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use crate::error::{LrcphileError, Result};
+use crate::provider::Lrclib;
+use crate::state::StateDb;
+use crate::{Cli, is_lyrics_extension, known_lrclib_id, read_header_tag, read_lyrics_file};
+use colored::Colorize;
+use std::path::Path;
+use tokio::sync::Mutex;
+
+/// Reports the lyrics stored for a track as incorrect
+pub async fn run(path: &Path, reason: &str, args: &Cli, state: &Mutex<StateDb>) -> Result<()> {
+    let track_id = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if is_lyrics_extension(extension, args) => read_lyrics_file(path)
+            .ok()
+            .and_then(|content| read_header_tag(&content, "lrclib_id")?.parse().ok()),
+        _ => known_lrclib_id(path, None, state, args).await,
+    }
+    .ok_or_else(|| {
+        LrcphileError::Metadata(format!(
+            "No LRCLIB ID is known for {}; fetch its lyrics with lrcphile first",
+            path.display()
+        ))
+    })?;
+
+    println!(
+        "{} {}",
+        "Flagging:".cyan().bold(),
+        format!(
+            "LRCLIB record {} (solving challenge, this may take a while)",
+            track_id
+        )
+        .bright_cyan()
+    );
+
+    let lrclib = Lrclib::connect(&args.url, false, &args.client_options())?;
+    lrclib.flag(track_id, reason).await?;
+
+    println!(
+        "{} {}",
+        "Flagged:".green().bold(),
+        format!("LRCLIB record {} was reported as incorrect", track_id).bright_green()
+    );
+    Ok(())
+}
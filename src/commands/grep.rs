@@ -0,0 +1,149 @@
+use crate::config::Config;
+use crate::error::{LrcphileError, Result};
+use crate::lyrics_index::LyricsIndex;
+use crate::{
+    Cli, default_music_dir, get_lyrics_file_path, lrc, process_directory, read_embedded_lyrics,
+    read_lyrics_file,
+};
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+
+/// Searches the lyrics of a library for a line, listing the tracks it appears in with the
+/// surrounding lines
+pub fn run(
+    query: &str,
+    dir: Option<&Path>,
+    embedded: bool,
+    context: usize,
+    args: &Cli,
+    config: &Config,
+) -> Result<()> {
+    let query = fold(query);
+    if query.is_empty() {
+        return Err(LrcphileError::Parse(
+            "Nothing to search for once punctuation is ignored".to_string(),
+        ));
+    }
+    let dir = dir
+        .map(Path::to_path_buf)
+        .or_else(|| default_music_dir(config))
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: PathBuf::new(),
+            reason: "could not determine the music directory; pass one explicitly",
+        })?;
+
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let audio_files = process_directory(&dir, &scan_args)?;
+
+    let mut index = LyricsIndex::default_location()
+        .map(|location| LyricsIndex::load(&location))
+        .unwrap_or_default();
+    let mut searched = 0;
+    let mut matched = 0;
+    for audio_path in &audio_files {
+        let Some(lyrics) = track_lyrics(audio_path, embedded, &mut index, args) else {
+            continue;
+        };
+        searched += 1;
+
+        let lines: Vec<&str> = lyrics.lines().collect();
+        let hits: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| fold(line).contains(&query))
+            .map(|(index, _)| index)
+            .collect();
+        if hits.is_empty() {
+            continue;
+        }
+
+        matched += 1;
+        println!("{}", audio_path.display().to_string().bright_cyan().bold());
+        let mut shown_until = 0;
+        for &hit in &hits {
+            let start = hit.saturating_sub(context).max(shown_until);
+            let end = (hit + context + 1).min(lines.len());
+            if start > shown_until && shown_until > 0 {
+                println!("{}", "  --".dimmed());
+            }
+            for (index, line) in lines.iter().enumerate().take(end).skip(start) {
+                let number = format!("{:>5}:", index + 1);
+                if hits.contains(&index) {
+                    println!("{} {}", number.yellow(), line.bright_white().bold());
+                } else {
+                    println!("{} {}", number.dimmed(), line.dimmed());
+                }
+            }
+            shown_until = end;
+        }
+        println!();
+    }
+
+    if let Err(e) = index.save() {
+        eprintln!(
+            "{} {}",
+            "Warning:".yellow().bold(),
+            format!("Failed to save the lyrics index: {}", e).yellow()
+        );
+    }
+
+    println!(
+        "{} {}",
+        "Matched:".green().bold(),
+        format!(
+            "{} of {} tracks with lyrics contain the line",
+            matched, searched
+        )
+        .bright_green()
+    );
+    Ok(())
+}
+
+/// A track's lyrics as plain text, from its lyrics file or, with `embedded`, its tags;
+/// taken from the index while the source is unchanged
+fn track_lyrics(
+    audio_path: &PathBuf,
+    embedded: bool,
+    index: &mut LyricsIndex,
+    args: &Cli,
+) -> Option<String> {
+    let sidecar = ["lrc", "txt"].into_iter().find_map(|extension| {
+        let lyrics_path = get_lyrics_file_path(audio_path, extension, args).ok()?;
+        lyrics_path.exists().then_some((lyrics_path, extension))
+    });
+    let (source, extension) = match sidecar {
+        Some(sidecar) => sidecar,
+        None if embedded => (audio_path.clone(), "lrc"),
+        None => return None,
+    };
+
+    if let Some(text) = index.get(audio_path, &source) {
+        return Some(text.to_string());
+    }
+
+    let lyrics = if source == *audio_path {
+        read_embedded_lyrics(audio_path).ok()??
+    } else {
+        read_lyrics_file(&source).ok()?
+    };
+    // Embedded lyrics may be synced or plain, and plain lines pass through unchanged
+    let text = match extension {
+        "lrc" => lrc::to_plain_text(&lyrics),
+        _ => lyrics,
+    };
+    index.insert(audio_path, &source, text.clone());
+    Some(text)
+}
+
+/// Lowercases the text and reduces punctuation and runs of whitespace to single spaces, so a
+/// line remembered without its commas or capitals still matches
+pub(super) fn fold(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,101 @@
+use super::grep::fold;
+use crate::error::{LrcphileError, Result};
+use crate::provider::{Lrclib, LyricsResponse};
+use crate::{Cli, Console, Throttle, process_directory, read_metadata, with_retry};
+use colored::Colorize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// Looks a lyrics snippet up on the LRCLIB instance and lists the tracks it could be from,
+/// marking those already in the library
+pub async fn run(text: &str, library: Option<&Path>, limit: usize, args: &Cli) -> Result<()> {
+    let query = fold(text);
+    if query.is_empty() {
+        return Err(LrcphileError::Parse(
+            "Nothing to search for once punctuation is ignored".to_string(),
+        ));
+    }
+
+    let provider = Lrclib::connect(&args.url, args.cached_only, &args.client_options())?;
+    let results = with_retry(&Throttle::default(), &Console::default(), || {
+        Box::pin(provider.search_text(text))
+    })
+    .await?;
+    let candidates = distinct_tracks(results, limit);
+    if candidates.is_empty() {
+        return Err(LrcphileError::NotFound);
+    }
+
+    let owned = match library {
+        Some(library) => Some(library_tracks(library, args).await?),
+        None => None,
+    };
+
+    for (index, candidate) in candidates.iter().enumerate() {
+        println!(
+            "{} {} - {} ({}, {}:{:02})",
+            format!("[{}]", index + 1).bright_white().bold(),
+            candidate.track_name.bright_white().bold(),
+            candidate.artist_name,
+            candidate.album_name,
+            candidate.duration as u32 / 60,
+            candidate.duration as u32 % 60
+        );
+        if let Some(line) = matching_line(candidate, &query) {
+            println!("      {}", line.dimmed());
+        }
+        if let Some(owned) = &owned {
+            match owned.get(&track_key(&candidate.track_name, &candidate.artist_name)) {
+                Some(path) => println!(
+                    "      {} {}",
+                    "In library:".green(),
+                    path.display().to_string().green()
+                ),
+                None => println!("      {}", "Not in library".dimmed()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The first `limit` results, keeping one record per title and artist since the same
+/// recording is often uploaded for several releases
+fn distinct_tracks(results: Vec<LyricsResponse>, limit: usize) -> Vec<LyricsResponse> {
+    let mut seen = HashSet::new();
+    results
+        .into_iter()
+        .filter(|result| seen.insert(track_key(&result.track_name, &result.artist_name)))
+        .take(limit)
+        .collect()
+}
+
+/// The lyrics line containing the snippet, to show why the record matched
+fn matching_line<'a>(candidate: &'a LyricsResponse, query: &str) -> Option<&'a str> {
+    candidate
+        .plain_lyrics
+        .as_deref()?
+        .lines()
+        .map(str::trim)
+        .find(|line| fold(line).contains(query))
+}
+
+/// Tracks of the library by title and artist, read from their tags
+async fn library_tracks(library: &Path, args: &Cli) -> Result<HashMap<(String, String), PathBuf>> {
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let mut tracks = HashMap::new();
+    for audio_path in process_directory(library, &scan_args)? {
+        if let Ok(metadata) = read_metadata(&audio_path, args).await {
+            tracks
+                .entry(track_key(&metadata.track_name, &metadata.artist_name))
+                .or_insert(audio_path);
+        }
+    }
+    Ok(tracks)
+}
+
+fn track_key(title: &str, artist: &str) -> (String, String) {
+    (fold(title), fold(artist))
+}
@@ -0,0 +1,344 @@
+use super::beets::parse_length;
+use super::grep::fold;
+use crate::config::Config;
+use crate::error::{LrcphileError, Result};
+use crate::picker::similarity;
+use crate::state::StateDb;
+use crate::{
+    AUDIO_EXTENSIONS, Cli, LRC_VARIANTS, default_music_dir, find_instrumental_placeholder,
+    has_manual_lyrics, is_edited_since_fetch, lyrics_file_exists, process_directory,
+    read_header_tag, read_lyrics_file, read_metadata, save_lyrics_file, written_lyrics_hash,
+};
+use colored::Colorize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// Similarity a loose LRC file's name needs to a track's name or tags to be matched to it
+const FUZZY_MATCH_THRESHOLD: f64 = 0.85;
+
+/// How far, in seconds, an LRC file's length may be from the track's to tell it apart from
+/// other files with the same title and artist
+const LENGTH_TOLERANCE: f64 = 2.0;
+
+/// Title and artist, lowercased, that LRC files are indexed by
+type TagKey = (String, String);
+
+/// LRC files of another tree, looked up by title and artist, by file stem, or else by the
+/// file name that comes closest to the track's
+#[derive(Default)]
+struct LyricsIndex {
+    /// Each file with its length, when known, to pick between releases of the same song
+    by_tags: HashMap<TagKey, Vec<(PathBuf, Option<f64>)>>,
+    /// `None` when several files share the stem, since guessing between them would be wrong
+    by_stem: HashMap<String, Option<PathBuf>>,
+    /// Every file by its folded name, for names like `01 - Artist - Title (lyrics).lrc`
+    by_name: Vec<(String, PathBuf)>,
+    len: usize,
+}
+
+impl LyricsIndex {
+    async fn build(root: &Path, args: &Cli) -> Self {
+        let mut index = LyricsIndex::default();
+        for lrc_path in find_lrc_files(root) {
+            if let Some((tags, length)) = lrc_tags(&lrc_path, args).await {
+                index
+                    .by_tags
+                    .entry(tags)
+                    .or_default()
+                    .push((lrc_path.clone(), length));
+            }
+            if let Some(stem) = normalized_stem(&lrc_path) {
+                index
+                    .by_stem
+                    .entry(stem)
+                    .and_modify(|existing| *existing = None)
+                    .or_insert_with(|| Some(lrc_path.clone()));
+            }
+            if let Some(name) = fuzzy_name(&lrc_path) {
+                index.by_name.push((name, lrc_path.clone()));
+            }
+            index.len += 1;
+        }
+        index
+    }
+
+    async fn find(&self, audio_path: &PathBuf, args: &Cli) -> Option<&PathBuf> {
+        let metadata = read_metadata(audio_path, args).await.ok();
+        if let Some(metadata) = &metadata
+            && let Some(files) = self
+                .by_tags
+                .get(&tag_key(&metadata.track_name, &metadata.artist_name))
+        {
+            if let [(lrc_path, _)] = files.as_slice() {
+                return Some(lrc_path);
+            }
+            // Several releases of the song: only one as long as the track will do, and
+            // guessing between the others would be wrong
+            let mut same_length = files.iter().filter(|(_, length)| {
+                length.is_some_and(|length| (length - metadata.duration).abs() <= LENGTH_TOLERANCE)
+            });
+            return match (same_length.next(), same_length.next()) {
+                (Some((lrc_path, _)), None) => Some(lrc_path),
+                _ => None,
+            };
+        }
+
+        if let Some(Some(lrc_path)) =
+            normalized_stem(audio_path).and_then(|stem| self.by_stem.get(&stem))
+        {
+            return Some(lrc_path);
+        }
+
+        // The names a downloaded file is likely to carry: the audio file's own, or built
+        // from its tags
+        let mut names: Vec<String> = fuzzy_name(audio_path).into_iter().collect();
+        if let Some(metadata) = &metadata {
+            names.push(fold(&format!(
+                "{} {}",
+                metadata.artist_name, metadata.track_name
+            )));
+            names.push(fold(&format!(
+                "{} {}",
+                metadata.track_name, metadata.artist_name
+            )));
+            names.push(fold(&metadata.track_name));
+        }
+        self.closest_name(&names)
+    }
+
+    /// The file whose name is most similar to one of the names, if it clears the threshold
+    /// and no other file is as close
+    fn closest_name(&self, names: &[String]) -> Option<&PathBuf> {
+        let mut best: Option<(f64, &PathBuf)> = None;
+        let mut tied = false;
+        for (lrc_name, lrc_path) in &self.by_name {
+            let score = names
+                .iter()
+                .map(|name| similarity(name, lrc_name))
+                .fold(0.0, f64::max);
+            match best {
+                Some((best_score, _)) if score < best_score => {}
+                Some((best_score, _)) if score == best_score => tied = true,
+                _ => {
+                    best = Some((score, lrc_path));
+                    tied = false;
+                }
+            }
+        }
+        best.filter(|(score, _)| *score >= FUZZY_MATCH_THRESHOLD && !tied)
+            .map(|(_, lrc_path)| lrc_path)
+    }
+}
+
+/// Copies LRC files from another tree onto the library's tracks that have no lyrics yet, or
+/// with `move_files`, moves them there
+pub async fn run(
+    from: &Path,
+    dir: Option<&Path>,
+    move_files: bool,
+    args: &Cli,
+    config: &Config,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    let dir = dir
+        .map(Path::to_path_buf)
+        .or_else(|| default_music_dir(config))
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: PathBuf::new(),
+            reason: "could not determine the music directory; pass one explicitly",
+        })?;
+
+    let index = LyricsIndex::build(from, args).await;
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} LRC files in {}", index.len, from.display()).bright_cyan()
+    );
+
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let audio_files = process_directory(&dir, &scan_args)?;
+
+    let mut imported = 0;
+    let mut unmatched = 0;
+    let mut used = HashSet::new();
+    for audio_path in &audio_files {
+        let has_lyrics = find_instrumental_placeholder(audio_path, args).is_some()
+            || ["lrc", "txt"]
+                .iter()
+                .any(|extension| lyrics_file_exists(audio_path, extension, args).unwrap_or(false));
+        if has_lyrics && !args.override_files {
+            continue;
+        }
+        // Checked before matching, so a kept file isn't reported as a failed import
+        if has_lyrics && args.preserve_manual && !args.force && has_manual_lyrics(audio_path, args)
+        {
+            continue;
+        }
+        if has_lyrics && !args.force && is_edited_since_fetch(audio_path, state, args).await {
+            println!(
+                "{} {}",
+                "Skipped:".yellow().bold(),
+                format!(
+                    "Keeping lyrics of {} edited since they were written (use --force to override them)",
+                    audio_path.display()
+                )
+                .yellow()
+            );
+            continue;
+        }
+
+        let Some(lrc_path) = index.find(audio_path, args).await else {
+            unmatched += 1;
+            continue;
+        };
+        // Going through the usual writer re-encodes legacy files and applies --bom/--crlf
+        let metadata = read_metadata(audio_path, args).await.ok();
+        let saved = match read_lyrics_file(lrc_path) {
+            Ok(content) => {
+                save_lyrics_file(audio_path, &content, "lrc", metadata.as_ref(), state, args)
+                    .await
+                    .map(|saved| written_lyrics_hash(&saved, &content))
+            }
+            Err(e) => Err(e),
+        };
+        match saved {
+            Ok(hash) => {
+                // Remembered like fetched lyrics, so later hand edits are told apart
+                state.lock().await.record_lyrics_hash(audio_path, hash);
+                imported += 1;
+                used.insert(lrc_path.clone());
+            }
+            Err(e) => eprintln!(
+                "{} {}",
+                "Failed:".red().bold(),
+                format!(
+                    "Importing {} for {}: {}",
+                    lrc_path.display(),
+                    audio_path.display(),
+                    e
+                )
+                .red()
+            ),
+        }
+    }
+
+    // Sources are only removed at the end, since one file may serve several releases
+    if move_files {
+        for lrc_path in &used {
+            if let Err(e) = fs::remove_file(lrc_path) {
+                eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!("Failed to remove {}: {}", lrc_path.display(), e).yellow()
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} {}",
+        "Imported:".green().bold(),
+        format!("{} lyrics files", imported).bright_green()
+    );
+    if move_files && index.len > used.len() {
+        println!(
+            "{} {}",
+            "Left:".yellow().bold(),
+            format!(
+                "{} LRC files in {} matched no track",
+                index.len - used.len(),
+                from.display()
+            )
+            .yellow()
+        );
+    }
+    if unmatched > 0 {
+        println!(
+            "{} {}",
+            "Unmatched:".yellow().bold(),
+            format!("{} tracks without lyrics had no counterpart", unmatched).yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Every `.lrc` file under a directory, without following symbolic links
+fn find_lrc_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut lrc_files = Vec::new();
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => lrc_files.extend(find_lrc_files(&path)),
+            Ok(file_type)
+                if file_type.is_file()
+                    && path.extension().is_some_and(|extension| {
+                        LRC_VARIANTS
+                            .iter()
+                            .any(|variant| extension.eq_ignore_ascii_case(variant))
+                    }) =>
+            {
+                lrc_files.push(path)
+            }
+            _ => {}
+        }
+    }
+    lrc_files
+}
+
+/// Title and artist of an LRC file, from its `[ti:]`/`[ar:]` header or else from the tags
+/// of an audio file next to it with the same stem
+async fn lrc_tags(lrc_path: &Path, args: &Cli) -> Option<(TagKey, Option<f64>)> {
+    if let Ok(content) = read_lyrics_file(lrc_path)
+        && let (Some(title), Some(artist)) = (
+            read_header_tag(&content, "ti"),
+            read_header_tag(&content, "ar"),
+        )
+        && !title.is_empty()
+        && !artist.is_empty()
+    {
+        let length = read_header_tag(&content, "length").and_then(parse_length);
+        return Some((tag_key(title, artist), length));
+    }
+
+    for extension in AUDIO_EXTENSIONS {
+        let audio_path = lrc_path.with_extension(extension);
+        if audio_path.exists() {
+            let metadata = read_metadata(&audio_path, args).await.ok()?;
+            return Some((
+                tag_key(&metadata.track_name, &metadata.artist_name),
+                Some(metadata.duration),
+            ));
+        }
+    }
+    None
+}
+
+fn tag_key(title: &str, artist: &str) -> TagKey {
+    (title.trim().to_lowercase(), artist.trim().to_lowercase())
+}
+
+fn normalized_stem(path: &Path) -> Option<String> {
+    Some(path.file_stem()?.to_string_lossy().trim().to_lowercase())
+}
+
+/// A file stem folded for fuzzy matching, without the track number downloads often start with
+fn fuzzy_name(path: &Path) -> Option<String> {
+    let name = fold(&path.file_stem()?.to_string_lossy());
+    let name = match name.split_once(' ') {
+        Some((number, rest)) if number.len() <= 3 && number.chars().all(|c| c.is_ascii_digit()) => {
+            rest.to_string()
+        }
+        _ => name,
+    };
+    (!name.is_empty()).then_some(name)
+}
@@ -0,0 +1,79 @@
+use crate::error::Result;
+use crate::state::StateDb;
+use crate::{Cli, Console, ProcessingStats, Throttle, process_file};
+use colored::Colorize;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::{env, path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Fetches lyrics for the files Lidarr just imported, as reported by its custom script environment
+pub async fn run(paths: &[PathBuf], args: &Cli, state: &Arc<Mutex<StateDb>>) -> Result<()> {
+    // Lidarr runs the script once with a test event when it's saved in the settings
+    if env::var("lidarr_eventtype").is_ok_and(|event| event == "Test") {
+        println!("{}", "Lidarr test event received.".green());
+        return Ok(());
+    }
+
+    let paths = if paths.is_empty() {
+        imported_paths()
+    } else {
+        paths.to_vec()
+    };
+    if paths.is_empty() {
+        println!("{}", "No imported files in this event.".yellow());
+        return Ok(());
+    }
+
+    if let (Ok(artist), Ok(album)) = (
+        env::var("lidarr_artist_name"),
+        env::var("lidarr_album_title"),
+    ) {
+        println!(
+            "{} {}",
+            "Imported:".green().bold(),
+            format!("{} - {} ({} files)", artist, album, paths.len()).bright_cyan()
+        );
+    }
+
+    let provider = args.lyrics_provider()?;
+    let throttle = Throttle::default();
+    let console = Console::default();
+    let mut stats = ProcessingStats::new(paths.len());
+
+    stream::iter(paths)
+        .map(|path| {
+            let provider = provider.clone();
+            let state = state.clone();
+            let throttle = &throttle;
+            let console = &console;
+            async move {
+                let outcome =
+                    process_file(&path, args, provider.as_ref(), throttle, state, console).await;
+                (path, outcome)
+            }
+        })
+        .buffer_unordered(args.concurrency())
+        .for_each(|(path, outcome)| {
+            stats.record(&path, outcome, args);
+            future::ready(())
+        })
+        .await;
+
+    stats.display_summary();
+    Ok(())
+}
+
+/// Track paths from Lidarr's import events, which separate them with `|`
+fn imported_paths() -> Vec<PathBuf> {
+    env::var("lidarr_addedtrackpaths")
+        .or_else(|_| env::var("lidarr_trackfile_path"))
+        .map(|paths| {
+            paths
+                .split('|')
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,175 @@
+use crate::config::Config;
+use crate::error::{LrcphileError, Result};
+use crate::{
+    Cli, DEFAULT_CONCURRENCY, PUBLIC_INSTANCE_HOSTS, PUBLIC_MAX_CONCURRENCY, default_music_dir,
+    prompt,
+};
+use colored::Colorize;
+use std::{fs, path::PathBuf};
+
+/// Profile the wizard writes its answers to, made the default so plain runs pick them up
+const PROFILE_NAME: &str = "default";
+
+/// Asks for the basics of a setup and writes them to the config file, so a first run needs
+/// no options at all
+pub fn run(args: &Cli, config: &Config) -> Result<()> {
+    let location = Config::default_location().ok_or_else(|| LrcphileError::InvalidPath {
+        path: PathBuf::from("config.toml"),
+        reason: "no user config directory on this system",
+    })?;
+    println!(
+        "{} {}",
+        "Config:".green().bold(),
+        location.display().to_string().bright_cyan()
+    );
+    if location.exists()
+        && !matches!(
+            prompt::ask("A config file already exists; replace it? [y/N]: ").as_deref(),
+            Some("y" | "yes")
+        )
+    {
+        return Ok(());
+    }
+
+    let default = default_music_dir(config).map(|dir| dir.display().to_string());
+    let music_dir = loop {
+        let answer = ask_or_default("Music directory", default.clone())?;
+        let dir = PathBuf::from(answer);
+        if dir.is_dir() {
+            break std::path::absolute(&dir).unwrap_or(dir);
+        }
+        println!("{}", "That isn't a directory; try again.".yellow());
+    };
+
+    let url = loop {
+        let answer = ask_or_default("LRCLIB instance URL", Some(args.url.clone()))?;
+        match reqwest::Url::parse(&answer) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => break answer,
+            _ => println!("{}", "Enter an http:// or https:// URL.".yellow()),
+        }
+    };
+    let public = reqwest::Url::parse(&url).is_ok_and(|url| {
+        url.host_str()
+            .is_some_and(|host| PUBLIC_INSTANCE_HOSTS.contains(&host))
+    });
+
+    println!("Where should lyrics files go?");
+    println!("  1) next to each audio file");
+    println!("  2) in a separate folder mirroring the library");
+    let output_dir = match choose(2)? {
+        1 => None,
+        _ => Some(loop {
+            let answer = ask_or_default("Lyrics folder", None)?;
+            if !answer.is_empty() {
+                let dir = PathBuf::from(answer);
+                break std::path::absolute(&dir).unwrap_or(dir);
+            }
+        }),
+    };
+
+    println!("Which lyrics should be saved?");
+    println!("  1) synced lyrics, or plain ones when a track has no synced lyrics");
+    println!("  2) synced lyrics only");
+    println!("  3) plain lyrics only");
+    // Written as `--lyrics-type` values
+    let lyrics_type = match choose(3)? {
+        1 => "any",
+        2 => "synced",
+        _ => "plain",
+    };
+
+    let default = if public {
+        DEFAULT_CONCURRENCY.min(PUBLIC_MAX_CONCURRENCY)
+    } else {
+        DEFAULT_CONCURRENCY
+    };
+    let concurrency = loop {
+        let answer = ask_or_default(
+            "Tracks to look up at the same time",
+            Some(default.to_string()),
+        )?;
+        match answer.parse::<u32>() {
+            Ok(concurrency) if concurrency > 0 => break concurrency,
+            _ => println!("{}", "Enter a number above 0.".yellow()),
+        }
+    };
+    if public && concurrency > PUBLIC_MAX_CONCURRENCY {
+        println!(
+            "{} {}",
+            "Note:".yellow().bold(),
+            format!(
+                "runs keep to {} at a time on the public instance",
+                PUBLIC_MAX_CONCURRENCY
+            )
+            .yellow()
+        );
+    }
+
+    let quote = |text: String| toml::Value::String(text).to_string();
+    let mut contents = format!(
+        "# Written by lrcphile init\n\
+         music_dir = {}\n\
+         default_profile = {}\n\
+         \n\
+         [profile.{}]\n\
+         url = {}\n",
+        quote(music_dir.display().to_string()),
+        quote(PROFILE_NAME.to_string()),
+        PROFILE_NAME,
+        quote(url)
+    );
+    if let Some(output_dir) = output_dir {
+        contents.push_str(&format!(
+            "output_dir = {}\n",
+            quote(output_dir.display().to_string())
+        ));
+    }
+    contents.push_str(&format!(
+        "lyrics_type = {}\nconcurrency = {}\n",
+        quote(lyrics_type.to_string()),
+        concurrency
+    ));
+
+    if let Some(parent) = location.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&location, contents)?;
+    println!(
+        "{} {}",
+        "Wrote:".green().bold(),
+        location.display().to_string().bright_green()
+    );
+    Ok(())
+}
+
+/// Asks one question, taking the default for an empty answer; input ending stops the wizard
+fn ask_or_default(question: &str, default: Option<String>) -> Result<String> {
+    let prompt = match &default {
+        Some(default) => format!("{} [{}]: ", question, default),
+        None => format!("{}: ", question),
+    };
+    let answer = prompt::ask_text(&prompt).ok_or_else(|| {
+        LrcphileError::Io(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "setup cancelled, nothing written",
+        ))
+    })?;
+    Ok(match default {
+        Some(default) if answer.is_empty() => default,
+        _ => answer,
+    })
+}
+
+/// Reads the number of one of the listed options, the first being the default
+fn choose(options: usize) -> Result<usize> {
+    loop {
+        let answer = ask_or_default("Choice", Some("1".to_string()))?;
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=options).contains(&choice) => return Ok(choice),
+            _ => println!(
+                "{}",
+                format!("Enter a number from 1 to {}.", options).yellow()
+            ),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use crate::Cli;
+use crate::error::Result;
+use clap::CommandFactory;
+use std::io;
+
+/// Renders the man page for the command line as defined in `Cli`
+pub fn run() -> Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(&mut io::stdout().lock())?;
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use crate::error::{LrcphileError, Result};
+use crate::lrc::{GAP_MARKER, Lrc, LrcLine, Timestamp};
+use crate::picker::similarity;
+use crate::provider::{Lrclib, LyricsProvider, LyricsResponse};
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, Throttle, TrackMetadata, lyrics_file_exists, read_lyrics_file, read_metadata,
+    save_lyrics_file, with_retry, written_lyrics_hash,
+};
+use colored::Colorize;
+use lofty::{file::AudioFile, prelude::TaggedFileExt, probe::Probe, tag::Accessor};
+use regex::Regex;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// How closely a record's title and artist must match a tracklist entry to be used
+const MIN_SEGMENT_MATCH: f64 = 0.8;
+
+/// One song of a mix, starting `start_ms` into the file
+#[derive(Debug)]
+struct Segment {
+    start_ms: u64,
+    artist: String,
+    title: String,
+}
+
+/// Builds one LRC file for a DJ mix from the lyrics of each song in its tracklist, each
+/// moved to where the song starts in the mix
+pub async fn run(
+    path: &Path,
+    tracklist: Option<&Path>,
+    args: &Cli,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    if lyrics_file_exists(&path, "lrc", args)? && !args.override_files {
+        return Err(LrcphileError::Metadata(format!(
+            "{} already has an LRC file (use -o to replace it)",
+            path.display()
+        )));
+    }
+
+    let tagged_file = Probe::open(&path)?.read()?;
+    let mix_ms = tagged_file.properties().duration().as_millis() as u64;
+    let comment = tagged_file
+        .primary_tag()
+        .and_then(|tag| tag.comment())
+        .map(|comment| comment.to_string());
+    let segments = read_tracklist(&path, tracklist, comment.as_deref())?;
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} songs in the tracklist", segments.len()).bright_cyan()
+    );
+
+    let provider = Lrclib::connect(&args.url, args.cached_only, &args.client_options())?;
+    let throttle = Throttle::default();
+    let console = Console::default();
+    let mut mix = Lrc::default();
+    let mut found = 0;
+    for (index, segment) in segments.iter().enumerate() {
+        let end_ms = segments
+            .get(index + 1)
+            .map_or(mix_ms, |next| next.start_ms)
+            .max(segment.start_ms);
+        let start = Timestamp::from_millis(segment.start_ms);
+        mix.lines.push(LrcLine::Lyric {
+            timestamps: vec![start],
+            text: format!("{} {} - {}", GAP_MARKER, segment.artist, segment.title),
+        });
+
+        let Some(record) = find_record(
+            &provider,
+            segment,
+            end_ms - segment.start_ms,
+            &throttle,
+            &console,
+        )
+        .await
+        else {
+            println!(
+                "{} {}",
+                "Missing:".yellow().bold(),
+                format!("{} - {}", segment.artist, segment.title).yellow()
+            );
+            continue;
+        };
+        found += 1;
+
+        // Mixes rarely play a song to its end, so lines past the next song's start are cut
+        let lyrics = Lrc::parse(record.synced_lyrics.as_deref().unwrap_or_default());
+        for (timestamp, text) in lyrics.timeline() {
+            let at = timestamp.millis + segment.start_ms;
+            if at > segment.start_ms && at < end_ms {
+                mix.lines.push(LrcLine::Lyric {
+                    timestamps: vec![timestamp.shifted(segment.start_ms as i64)],
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+
+    let content = mix.to_string();
+    let metadata = read_metadata(&path, args).await.ok();
+    let lrc_path = save_lyrics_file(&path, &content, "lrc", metadata.as_ref(), state, args).await?;
+    state
+        .lock()
+        .await
+        .record_lyrics_hash(&path, written_lyrics_hash(&lrc_path, &content));
+    println!(
+        "{} {}",
+        "Saved:".green().bold(),
+        format!(
+            "{} ({} of {} songs with synced lyrics)",
+            lrc_path.display(),
+            found,
+            segments.len()
+        )
+        .bright_green()
+    );
+    Ok(())
+}
+
+/// Searches for the song of a segment, taking the closest record with synced lyrics; the
+/// duration can't help since mixes cut songs short
+async fn find_record(
+    provider: &Lrclib,
+    segment: &Segment,
+    segment_ms: u64,
+    throttle: &Throttle,
+    console: &Console,
+) -> Option<LyricsResponse> {
+    let metadata = TrackMetadata {
+        track_name: segment.title.clone(),
+        artist_name: segment.artist.clone(),
+        album_name: String::new(),
+        genre: None,
+        duration: segment_ms as f64 / 1000.0,
+        disc_number: None,
+        track_number: None,
+        lrclib_id: None,
+        validators: None,
+    };
+    let results = with_retry(throttle, console, || provider.search(&metadata))
+        .await
+        .ok()?;
+    let score = |record: &LyricsResponse| {
+        0.6 * similarity(&record.track_name, &segment.title)
+            + 0.4 * similarity(&record.artist_name, &segment.artist)
+    };
+    results
+        .into_iter()
+        .filter(|record| record.synced_lyrics.is_some() && score(record) >= MIN_SEGMENT_MATCH)
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+}
+
+/// The mix's songs in order, from the given tracklist, a `.cue` sheet beside the mix, or the
+/// comment tag
+fn read_tracklist(
+    path: &Path,
+    tracklist: Option<&Path>,
+    comment: Option<&str>,
+) -> Result<Vec<Segment>> {
+    let cue_path = path.with_extension("cue");
+    let (source, segments) = match tracklist {
+        Some(tracklist) => (tracklist.to_path_buf(), read_tracklist_file(tracklist)?),
+        None if cue_path.exists() => (cue_path.clone(), read_tracklist_file(&cue_path)?),
+        None => (
+            PathBuf::from("the comment tag"),
+            parse_text_tracklist(comment.unwrap_or_default()),
+        ),
+    };
+    if segments.is_empty() {
+        return Err(LrcphileError::Parse(format!(
+            "No timed tracklist in {}",
+            source.display()
+        )));
+    }
+    Ok(segments)
+}
+
+fn read_tracklist_file(tracklist: &Path) -> Result<Vec<Segment>> {
+    let content = read_lyrics_file(tracklist)?;
+    let is_cue = tracklist
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"));
+    Ok(if is_cue {
+        parse_cue(&content)
+    } else {
+        parse_text_tracklist(&content)
+    })
+}
+
+/// Reads the tracks of a cue sheet: their `TITLE`, `PERFORMER` (or the sheet's own) and
+/// `INDEX 01` start in minutes, seconds and frames of 1/75 s
+fn parse_cue(content: &str) -> Vec<Segment> {
+    let unquote = |value: &str| value.trim().trim_matches('"').to_string();
+    let mut album_performer = String::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut current: Option<(String, String, Option<u64>)> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        let (command, value) = line.split_once(' ').unwrap_or((line, ""));
+        match (command, current.as_mut()) {
+            ("TRACK", _) => {
+                if let Some((artist, title, Some(start_ms))) = current.take() {
+                    segments.push(Segment {
+                        start_ms,
+                        artist,
+                        title,
+                    });
+                }
+                current = Some((album_performer.clone(), String::new(), None));
+            }
+            ("PERFORMER", None) => album_performer = unquote(value),
+            ("PERFORMER", Some((artist, _, _))) => *artist = unquote(value),
+            ("TITLE", Some((_, title, _))) => *title = unquote(value),
+            ("INDEX", Some((_, _, start))) => {
+                if let Some(("01", time)) = value.trim().split_once(' ') {
+                    let parts: Vec<u64> = time
+                        .split(':')
+                        .filter_map(|part| part.parse().ok())
+                        .collect();
+                    if let [minutes, seconds, frames] = parts[..] {
+                        *start = Some((minutes * 60 + seconds) * 1000 + frames * 1000 / 75);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((artist, title, Some(start_ms))) = current {
+        segments.push(Segment {
+            start_ms,
+            artist,
+            title,
+        });
+    }
+    segments.sort_by_key(|segment| segment.start_ms);
+    segments
+}
+
+/// Reads tracklist lines like `01. [12:34] Artist - Title` or `1:02:03 Artist – Title`;
+/// other lines are ignored
+fn parse_text_tracklist(content: &str) -> Vec<Segment> {
+    let Ok(pattern) = Regex::new(
+        r"^\s*(?:\d+[.)]\s*)?\[?(?:(\d+):)?(\d{1,2}):(\d{2})\]?\s*(?:[-–—]\s*)?(.+?)\s+[-–—]\s+(.+?)\s*$",
+    ) else {
+        return Vec::new();
+    };
+    let mut segments: Vec<Segment> = content
+        .lines()
+        .filter_map(|line| {
+            let captures = pattern.captures(line)?;
+            let number = |index: usize| {
+                captures
+                    .get(index)
+                    .and_then(|value| value.as_str().parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            Some(Segment {
+                start_ms: (number(1) * 3600 + number(2) * 60 + number(3)) * 1000,
+                artist: captures[4].to_string(),
+                title: captures[5].to_string(),
+            })
+        })
+        .collect();
+    segments.sort_by_key(|segment| segment.start_ms);
+    segments
+}
@@ -0,0 +1,159 @@
+mod beets;
+mod bench;
+mod booklet;
+mod cache;
+mod check;
+mod clean;
+mod dap;
+mod doctor;
+mod export;
+mod fix;
+mod flag;
+mod grep;
+mod identify;
+mod import;
+mod import_event;
+mod init;
+mod man;
+mod mix;
+mod mpv_sub;
+mod now_playing;
+mod prefetch;
+mod publish;
+mod review;
+mod shift;
+mod show;
+mod state;
+mod stats;
+mod subtitle;
+mod sync;
+mod tui;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::state::StateDb;
+use crate::{Cli, Command, default_music_dir};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The library a subcommand works on, which `--output-dir` and `--no-write-outside-root`
+/// are relative to
+pub fn library(command: &Command, args: &Cli, config: &Config) -> Option<PathBuf> {
+    match command {
+        Command::Clean { dir, .. }
+        | Command::Booklet { dir, .. }
+        | Command::Tui { dir }
+        | Command::Import { dir: Some(dir), .. }
+        | Command::Grep { dir: Some(dir), .. }
+        | Command::Prefetch { dir: Some(dir), .. }
+        | Command::Publish { dir: Some(dir), .. } => Some(dir.clone()),
+        _ => args.path.clone().or_else(|| default_music_dir(config)),
+    }
+}
+
+/// Runs a subcommand instead of the default lyrics fetching
+pub async fn run(
+    command: &Command,
+    args: &Cli,
+    config: &Config,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    match command {
+        Command::Beets { listing } => beets::run(listing, args, state).await,
+        Command::Bench { requests, search } => bench::run(*requests, *search, args).await,
+        Command::Booklet {
+            dir,
+            format,
+            output,
+        } => booklet::run(dir, *format, output.as_deref(), args),
+        Command::Cache { action } => cache::run(*action, args),
+        Command::Check { manifest, dir } => check::run(manifest, dir.as_deref()),
+        Command::Clean {
+            dir,
+            instrumentals,
+            embedded,
+            only_with_sidecar,
+        } => clean::run(dir, *instrumentals, *embedded, *only_with_sidecar, args),
+        Command::Dap {
+            mount,
+            tracks,
+            device,
+            to,
+        } => dap::run(mount, tracks.as_deref(), *device, to.as_deref(), args).await,
+        Command::Doctor => doctor::run(args, config).await,
+        Command::Export {
+            playlist,
+            to,
+            device,
+        } => export::run(playlist, to, *device, args, config),
+        Command::Flag { path, reason } => flag::run(path, reason, args, state).await,
+        Command::Grep {
+            query,
+            dir,
+            embedded,
+            context,
+        } => grep::run(query, dir.as_deref(), *embedded, *context, args, config),
+        Command::Identify {
+            text,
+            library,
+            limit,
+        } => identify::run(text, library.as_deref(), *limit, args).await,
+        Command::Import {
+            dir,
+            from,
+            move_files,
+        } => import::run(from, dir.as_deref(), *move_files, args, config, state).await,
+        Command::ImportEvent { paths } => import_event::run(paths, args, state).await,
+        Command::Init => init::run(args, config),
+        Command::Man => man::run(),
+        Command::Mix { path, tracklist } => mix::run(path, tracklist.as_deref(), args, state).await,
+        Command::MpvSub {
+            path,
+            format,
+            print_path,
+            install_script,
+        } => {
+            mpv_sub::run(
+                path.as_deref(),
+                *format,
+                *print_path,
+                *install_script,
+                args,
+                state,
+            )
+            .await
+        }
+        Command::NowPlaying { player } => now_playing::run(*player, args, state).await,
+        Command::Prefetch { dir, count } => {
+            prefetch::run(*count, dir.as_deref(), args, config, state).await
+        }
+        Command::Publish { path, dir, yes } => {
+            publish::run(path.as_deref(), dir.as_deref(), *yes, args).await
+        }
+        Command::Review => review::run(args, state).await,
+        Command::Fix { path } => fix::run(path, args, state).await,
+        Command::Shift {
+            path,
+            by,
+            offset_tag,
+            flatten,
+        } => shift::run(path, *by, *offset_tag, *flatten, args),
+        Command::Show {
+            path,
+            strip_timestamps,
+            no_pager,
+        } => show::run(path, *strip_timestamps, *no_pager, args, state).await,
+        Command::State { action } => state::run(action, state).await,
+        Command::Stats { dir, providers } => {
+            if *providers {
+                stats::run_providers(state).await
+            } else {
+                stats::run(dir.as_deref(), state).await
+            }
+        }
+        Command::Subtitle { path, output } => subtitle::run(path, output.as_deref(), args),
+        Command::Sync { src, dst } => sync::run(src, dst, args, state).await,
+        Command::Tui { dir } => tui::run(dir, args, state).await,
+    }
+}
@@ -0,0 +1,146 @@
+use super::subtitle::read_synced_lyrics;
+use crate::error::{LrcphileError, Result};
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, SubtitleFormat, Throttle, ass, find_lrc_file, lyrics_file_exists, process_file,
+    srt, write_lyrics_file,
+};
+use colored::Colorize;
+use directories::BaseDirs;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// mpv script that runs `mpv-sub` for each audio file mpv opens and loads the subtitles it
+/// prints; `{exe}` is replaced by the path of this executable
+const MPV_SCRIPT: &str = r#"-- Installed by `lrcphile mpv-sub --install-script`: shows the lyrics of audio files as subtitles
+local lrcphile = {exe}
+
+mp.register_event("file-loaded", function()
+    local path = mp.get_property("path")
+    for _, track in ipairs(mp.get_property_native("track-list", {})) do
+        -- Leave videos and files that already have subtitles alone
+        if track.type == "sub" or (track.type == "video" and not track.albumart) then
+            return
+        end
+    end
+    mp.command_native_async({
+        name = "subprocess",
+        args = { lrcphile, "mpv-sub", "--format", "{format}", "--print-path", path },
+        capture_stdout = true,
+        playback_only = false,
+    }, function(success, result)
+        if not success or result.status ~= 0 or mp.get_property("path") ~= path then
+            return
+        end
+        local subtitles = result.stdout:match("([^\n]+)%s*$")
+        if subtitles then
+            mp.commandv("sub-add", subtitles, "select", "Lyrics")
+        end
+    end)
+end)
+"#;
+
+/// Writes a track's synced lyrics as subtitles beside it, where mpv loads them on its own,
+/// fetching the lyrics first when the track has none
+pub async fn run(
+    path: Option<&Path>,
+    format: SubtitleFormat,
+    print_path: bool,
+    install_script: bool,
+    args: &Cli,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    if install_script {
+        return install_mpv_script(format);
+    }
+    let Some(path) = path else {
+        return Err(LrcphileError::InvalidPath {
+            path: PathBuf::new(),
+            reason: "no audio file given",
+        });
+    };
+
+    let audio_path = path.to_path_buf();
+    if !lyrics_file_exists(&audio_path, "lrc", args)? {
+        let provider = args.lyrics_provider()?;
+        process_file(
+            &audio_path,
+            args,
+            provider.as_ref(),
+            &Throttle::default(),
+            state.clone(),
+            &Console::default(),
+        )
+        .await;
+    }
+
+    let lrc_path = find_lrc_file(path, args)?;
+    let (lrc, title) = read_synced_lyrics(&lrc_path)?;
+    let (extension, subtitles) = match format {
+        SubtitleFormat::Srt => ("srt", srt::to_srt(&lrc)),
+        SubtitleFormat::Ass => ("ass", ass::to_ass(&lrc, &title, &args.ass_style())),
+    };
+    let subtitle_path = audio_path.with_extension(extension);
+    write_lyrics_file(&subtitle_path, subtitles, args)?;
+
+    if print_path {
+        println!("{}", subtitle_path.display());
+    } else {
+        println!(
+            "{} {}",
+            "Wrote:".green().bold(),
+            subtitle_path.display().to_string().bright_green()
+        );
+    }
+    Ok(())
+}
+
+/// Installs the mpv script into mpv's `scripts` folder, so every audio file mpv plays gets
+/// its lyrics as subtitles
+fn install_mpv_script(format: SubtitleFormat) -> Result<()> {
+    let scripts_dir = mpv_config_dir()
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: PathBuf::new(),
+            reason: "could not determine mpv's configuration directory; set MPV_HOME",
+        })?
+        .join("scripts");
+    fs::create_dir_all(&scripts_dir)?;
+
+    let exe = env::current_exe()?;
+    let script = MPV_SCRIPT
+        .replace("{exe}", &format!("{:?}", exe.to_string_lossy()))
+        .replace(
+            "{format}",
+            match format {
+                SubtitleFormat::Srt => "srt",
+                SubtitleFormat::Ass => "ass",
+            },
+        );
+    let script_path = scripts_dir.join("lrcphile.lua");
+    fs::write(&script_path, script)?;
+    println!(
+        "{} {}",
+        "Wrote:".green().bold(),
+        script_path.display().to_string().bright_green()
+    );
+    Ok(())
+}
+
+/// Where mpv reads its configuration: `MPV_HOME`, or else `mpv` in the user's configuration
+/// directory, which mpv takes from `~/.config` on macOS too
+fn mpv_config_dir() -> Option<PathBuf> {
+    if let Some(mpv_home) = env::var_os("MPV_HOME") {
+        return Some(PathBuf::from(mpv_home));
+    }
+    let dirs = BaseDirs::new()?;
+    let config_dir = if cfg!(target_os = "macos") {
+        dirs.home_dir().join(".config")
+    } else {
+        dirs.config_dir().to_path_buf()
+    };
+    Some(config_dir.join("mpv"))
+}
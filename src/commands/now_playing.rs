@@ -0,0 +1,112 @@
+use crate::error::{LrcphileError, Result};
+use crate::lrc::{Lrc, strip_word_timings};
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, ConsolePlayer, Throttle, find_lrc_file, lyrics_file_exists, process_file,
+    read_lyrics_file,
+};
+use colored::Colorize;
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// The track a console player has loaded
+struct NowPlaying {
+    path: PathBuf,
+    /// How far into the track playback is, in seconds
+    position: Option<u64>,
+}
+
+/// Fetches lyrics for the track a console player is playing and shows the line being sung
+pub async fn run(
+    player: Option<ConsolePlayer>,
+    args: &Cli,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    let players = match player {
+        Some(player) => vec![player],
+        None => vec![ConsolePlayer::Cmus, ConsolePlayer::Moc],
+    };
+    let Some((player, playing)) = players
+        .iter()
+        .find_map(|player| query(*player).map(|playing| (*player, playing)))
+    else {
+        return Err(LrcphileError::Metadata(
+            "No console player is playing a file (is cmus or moc running?)".to_string(),
+        ));
+    };
+    println!(
+        "{} {}",
+        format!("{}:", player.name()).green().bold(),
+        playing.path.display().to_string().bright_cyan()
+    );
+
+    if !lyrics_file_exists(&playing.path, "lrc", args)?
+        && !lyrics_file_exists(&playing.path, "txt", args)?
+    {
+        let provider = args.lyrics_provider()?;
+        process_file(
+            &playing.path,
+            args,
+            provider.as_ref(),
+            &Throttle::default(),
+            state.clone(),
+            &Console::default(),
+        )
+        .await;
+    }
+
+    let Ok(lrc_path) = find_lrc_file(&playing.path, args) else {
+        return Ok(());
+    };
+    let lrc = Lrc::parse(&read_lyrics_file(&lrc_path)?);
+    if let Some(position) = playing.position
+        && let Some((_, line)) = lrc
+            .timeline()
+            .into_iter()
+            .rev()
+            .find(|(timestamp, _)| timestamp.millis <= position * 1000)
+    {
+        println!(
+            "{} {}",
+            "Now:".green().bold(),
+            strip_word_timings(line).trim().bright_white().bold()
+        );
+    }
+    Ok(())
+}
+
+/// Asks a player which file it is playing; `None` when it isn't running or is stopped
+fn query(player: ConsolePlayer) -> Option<NowPlaying> {
+    let (program, flag) = match player {
+        ConsolePlayer::Cmus => ("cmus-remote", "-Q"),
+        ConsolePlayer::Moc => ("mocp", "-i"),
+    };
+    let output = Command::new(program)
+        .arg(flag)
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let status = String::from_utf8_lossy(&output.stdout);
+
+    // cmus prints `file /path` and `position 52`, moc prints `File: /path` and `CurrentSec: 52`
+    let (file_key, position_key) = match player {
+        ConsolePlayer::Cmus => ("file ", "position "),
+        ConsolePlayer::Moc => ("File: ", "CurrentSec: "),
+    };
+    let value = |key: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    Some(NowPlaying {
+        path: PathBuf::from(value(file_key)?),
+        position: value(position_key).and_then(|position| position.parse().ok()),
+    })
+}
@@ -0,0 +1,143 @@
+use crate::config::Config;
+use crate::error::{LrcphileError, Result};
+use crate::state::StateDb;
+use crate::{Cli, Console, ProcessingStats, Throttle, default_music_dir, process_file};
+use colored::Colorize;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+/// MPD's address when `MPD_HOST` and `MPD_PORT` aren't set
+const DEFAULT_MPD_HOST: &str = "localhost";
+const DEFAULT_MPD_PORT: &str = "6600";
+
+/// Fetches lyrics for the song MPD is playing and the next ones in its queue, so they are
+/// on disk before the songs start
+pub async fn run(
+    count: usize,
+    dir: Option<&Path>,
+    args: &Cli,
+    config: &Config,
+    state: &Arc<Mutex<StateDb>>,
+) -> Result<()> {
+    let music_dir = dir
+        .map(Path::to_path_buf)
+        .or_else(|| default_music_dir(config))
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: PathBuf::new(),
+            reason: "could not determine MPD's music directory; pass it explicitly",
+        })?;
+
+    let queue = mpd_queue(count).await?;
+    // MPD names songs relative to its music directory; streams have no file to go beside
+    let paths: Vec<PathBuf> = queue
+        .iter()
+        .filter(|song| !song.contains("://"))
+        .map(|song| music_dir.join(song))
+        .filter(|path| path.exists())
+        .collect();
+    if paths.is_empty() {
+        println!("{}", "No upcoming songs in the MPD queue.".yellow());
+        return Ok(());
+    }
+    println!(
+        "{} {}",
+        "Queued:".green().bold(),
+        format!("{} upcoming songs", paths.len()).bright_cyan()
+    );
+
+    let provider = args.lyrics_provider()?;
+    let throttle = Throttle::default();
+    let console = Console::default();
+    let mut stats = ProcessingStats::new(paths.len());
+
+    stream::iter(paths)
+        .map(|path| {
+            let provider = provider.clone();
+            let state = state.clone();
+            let throttle = &throttle;
+            let console = &console;
+            async move {
+                let outcome =
+                    process_file(&path, args, provider.as_ref(), throttle, state, console).await;
+                (path, outcome)
+            }
+        })
+        .buffer_unordered(args.concurrency())
+        .for_each(|(path, outcome)| {
+            stats.record(&path, outcome, args);
+            future::ready(())
+        })
+        .await;
+
+    stats.display_summary();
+    Ok(())
+}
+
+/// The song MPD is on and up to `count` after it, as paths relative to its music directory.
+/// MPD is found through `MPD_HOST` and `MPD_PORT` like its own clients, with a password given
+/// as `password@host`
+async fn mpd_queue(count: usize) -> Result<Vec<String>> {
+    let host = env::var("MPD_HOST").unwrap_or_else(|_| DEFAULT_MPD_HOST.to_string());
+    let port = env::var("MPD_PORT").unwrap_or_else(|_| DEFAULT_MPD_PORT.to_string());
+    let (password, host) = match host.rsplit_once('@') {
+        Some((password, host)) => (Some(password.to_string()), host.to_string()),
+        None => (None, host),
+    };
+
+    let stream = TcpStream::connect(format!("{}:{}", host, port))
+        .await
+        .map_err(|e| LrcphileError::Provider(format!("Could not reach MPD at {}: {}", host, e)))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // MPD greets each connection with `OK MPD <version>`
+    lines.next_line().await?;
+
+    let mut command = async |command: &str| -> Result<Vec<(String, String)>> {
+        writer
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        let mut pairs = Vec::new();
+        while let Some(line) = lines.next_line().await? {
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if let Some(error) = line.strip_prefix("ACK ") {
+                return Err(LrcphileError::Provider(format!("MPD: {}", error)));
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+        Err(LrcphileError::Provider(
+            "MPD closed the connection".to_string(),
+        ))
+    };
+
+    if let Some(password) = password {
+        command(&format!("password \"{}\"", password.replace('"', "\\\""))).await?;
+    }
+    // Stopped players have no current song, and the queue is played from the start
+    let current = command("status")
+        .await?
+        .into_iter()
+        .find(|(key, _)| key == "song")
+        .and_then(|(_, position)| position.parse::<usize>().ok())
+        .unwrap_or(0);
+    let queue: Vec<String> = command("playlistinfo")
+        .await?
+        .into_iter()
+        .filter(|(key, _)| key == "file")
+        .map(|(_, file)| file)
+        .collect();
+    Ok(queue.into_iter().skip(current).take(count + 1).collect())
+}
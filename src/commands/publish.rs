@@ -0,0 +1,148 @@
+use crate::error::{LrcphileError, Result};
+use crate::lrc::{Lrc, LrcLine};
+use crate::provider::{Lrclib, PublishRequest};
+use crate::{
+    Cli, TrackMetadata, get_lyrics_file_path, process_directory, prompt, read_lyrics_file,
+    read_metadata,
+};
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+
+/// Number of lyrics lines shown when reviewing a file before upload
+const PREVIEW_LINES: usize = 4;
+
+/// Uploads LRC files that were synced by hand rather than downloaded from LRCLIB
+pub async fn run(path: Option<&Path>, dir: Option<&Path>, yes: bool, args: &Cli) -> Result<()> {
+    let audio_files = match (path, dir) {
+        (_, Some(dir)) => {
+            let mut scan_args = args.clone();
+            scan_args.recursive = true;
+            process_directory(dir, &scan_args)?
+        }
+        (Some(path), None) => vec![path.to_path_buf()],
+        (None, None) => Vec::new(),
+    };
+
+    let candidates: Vec<(PathBuf, PathBuf, String)> = audio_files
+        .into_iter()
+        .filter_map(|audio_path| {
+            let lrc_path = get_lyrics_file_path(&audio_path, "lrc", args).ok()?;
+            let content = read_lyrics_file(&lrc_path).ok()?;
+            // Files carrying our marker came from LRCLIB in the first place
+            (!content.contains("[by: lrcphile]")).then_some((audio_path, lrc_path, content))
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        println!("{}", "No unpublished LRC files found.".yellow());
+        return Ok(());
+    }
+
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} unpublished LRC files", candidates.len()).bright_cyan()
+    );
+
+    let lrclib = Lrclib::connect(&args.url, false, &args.client_options())?;
+    let mut published = 0;
+    let mut failed = 0;
+
+    for (audio_path, lrc_path, content) in candidates {
+        let metadata = match read_metadata(&audio_path, args).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!("{}: {}", audio_path.display(), e).yellow()
+                );
+                continue;
+            }
+        };
+
+        let request = match build_request(&metadata, &content) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!("{}: {}", lrc_path.display(), e).yellow()
+                );
+                continue;
+            }
+        };
+        print_review(&metadata, &lrc_path, &request);
+
+        if !yes {
+            match prompt::ask("Publish? [y/N/q] ").as_deref() {
+                Some("y") | Some("yes") => {}
+                Some("q") | None => break,
+                _ => continue,
+            }
+        }
+
+        println!("{}", "Solving challenge and publishing...".cyan());
+        match lrclib.publish(&request).await {
+            Ok(()) => {
+                published += 1;
+                println!("{}", "Published.".green().bold());
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{} {}", "Failed:".red().bold(), e.to_string().red());
+            }
+        }
+    }
+
+    println!(
+        "\n{} {} {}",
+        "Published:".green().bold(),
+        published.to_string().bright_green().bold(),
+        format!("files ({} failed)", failed).green()
+    );
+    Ok(())
+}
+
+fn build_request(metadata: &TrackMetadata, content: &str) -> Result<PublishRequest> {
+    // LRCLIB wants one timestamp per line, so repeated lines are spelled out in order
+    let lrc = Lrc::parse(content).expanded();
+    let mut synced_lines = Vec::new();
+    let mut plain_lines = Vec::new();
+    for line in &lrc.lines {
+        if let LrcLine::Lyric { text, .. } = line {
+            synced_lines.push(line.to_string().trim().to_string());
+            plain_lines.push(text.trim());
+        }
+    }
+
+    if synced_lines.is_empty() {
+        return Err(LrcphileError::Parse(
+            "LRC file: no timestamped lines".to_string(),
+        ));
+    }
+
+    Ok(PublishRequest {
+        track_name: metadata.track_name.clone(),
+        artist_name: metadata.artist_name.clone(),
+        album_name: metadata.album_name.clone(),
+        duration: metadata.duration,
+        plain_lyrics: plain_lines.join("\n"),
+        synced_lyrics: synced_lines.join("\n"),
+    })
+}
+
+fn print_review(metadata: &TrackMetadata, lrc_path: &Path, request: &PublishRequest) {
+    println!(
+        "\n{} - {} ({}, {}:{:02})",
+        metadata.track_name.bright_white().bold(),
+        metadata.artist_name.bright_white(),
+        metadata.album_name,
+        metadata.duration as u32 / 60,
+        metadata.duration as u32 % 60
+    );
+    println!("  {}", lrc_path.display().to_string().dimmed());
+    for line in request.synced_lyrics.lines().take(PREVIEW_LINES) {
+        println!("  {}", line);
+    }
+}
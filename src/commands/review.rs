@@ -0,0 +1,99 @@
+use crate::error::Result;
+use crate::state::StateDb;
+use crate::{
+    Cli, fix_tags, prompt, read_header_tag, read_metadata, save_lyrics_file, written_lyrics_hash,
+};
+use colored::Colorize;
+use tokio::sync::Mutex;
+
+/// Number of lines shown of each held-back match, enough to compare its header with the track
+const PREVIEW_LINES: usize = 15;
+
+/// Steps through the doubtful matches held back during fetching, writing the accepted ones
+pub async fn run(args: &Cli, state: &Mutex<StateDb>) -> Result<()> {
+    let quarantined = state.lock().await.quarantined();
+    if quarantined.is_empty() {
+        println!("{}", "No matches waiting for review.".green());
+        return Ok(());
+    }
+
+    let mut accepted = 0;
+    let mut rejected = 0;
+    'review: for (index, (audio_path, candidate)) in quarantined.iter().enumerate() {
+        println!(
+            "\n{} {}",
+            format!("[{}/{}]", index + 1, quarantined.len())
+                .bright_cyan()
+                .bold(),
+            audio_path.display().to_string().bright_white()
+        );
+        println!("  {} {}", "Doubts:".yellow(), candidate.reason.yellow());
+        for line in candidate.content.lines().take(PREVIEW_LINES) {
+            println!("  {}", line);
+        }
+
+        loop {
+            match prompt::ask("Use these lyrics? [a]ccept/[r]eject/[s]kip/[q]uit: ").as_deref() {
+                Some("a" | "accept") => {
+                    if args.fix_tags
+                        && let (Some(title), Some(artist), Some(album)) = (
+                            read_header_tag(&candidate.content, "ti"),
+                            read_header_tag(&candidate.content, "ar"),
+                            read_header_tag(&candidate.content, "al"),
+                        )
+                        && let Err(e) = fix_tags(audio_path, title, artist, album)
+                    {
+                        println!(
+                            "{} {}",
+                            "Warning:".yellow().bold(),
+                            format!("Failed to fix tags: {}", e).yellow()
+                        );
+                    }
+                    // Read after any tag fix, so the record goes with the tags the track has now
+                    let metadata = read_metadata(audio_path, args).await.ok();
+                    let lyrics_path = save_lyrics_file(
+                        audio_path,
+                        &candidate.content,
+                        &candidate.extension,
+                        metadata.as_ref(),
+                        state,
+                        args,
+                    )
+                    .await?;
+                    let mut state = state.lock().await;
+                    if let Some(metadata) = &metadata {
+                        state.record_lrclib_id(audio_path, candidate.lrclib_id, metadata);
+                    }
+                    state.record_lyrics_hash(
+                        audio_path,
+                        written_lyrics_hash(&lyrics_path, &candidate.content),
+                    );
+                    state.release(audio_path, false);
+                    accepted += 1;
+                    break;
+                }
+                Some("r" | "reject") => {
+                    state.lock().await.release(audio_path, true);
+                    rejected += 1;
+                    break;
+                }
+                Some("s" | "skip" | "") => break,
+                Some("q" | "quit") | None => break 'review,
+                _ => println!("{}", "Enter a, r, s or q.".yellow()),
+            }
+        }
+    }
+
+    println!(
+        "\n{} {}",
+        "Reviewed:".green().bold(),
+        format!(
+            "{} accepted, {} rejected, {} still waiting",
+            accepted,
+            rejected,
+            quarantined.len() - accepted - rejected
+        )
+        .bright_green()
+    );
+    Ok(())
+}
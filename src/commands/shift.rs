@@ -0,0 +1,52 @@
+use crate::error::{LrcphileError, Result};
+use crate::lrc::Lrc;
+use crate::{Cli, encode_lyrics, find_lrc_file, read_lyrics_file, write_lyrics_file};
+use colored::Colorize;
+use std::path::Path;
+
+/// Retimes a track's LRC file in place, by rewriting its timestamps or its `[offset:]` tag
+pub fn run(
+    path: &Path,
+    by: Option<i64>,
+    offset_tag: bool,
+    flatten: bool,
+    args: &Cli,
+) -> Result<()> {
+    let lrc_path = find_lrc_file(path, args)?;
+    let mut lrc = Lrc::parse(&read_lyrics_file(&lrc_path)?);
+    if !lrc.is_synced() {
+        return Err(LrcphileError::Parse(format!(
+            "{}: no timestamped lines",
+            lrc_path.display()
+        )));
+    }
+
+    let mut changes = Vec::new();
+    if flatten {
+        match lrc.offset() {
+            Some(offset) if lrc.flatten_offset() => {
+                changes.push(format!("applied offset of {:+} ms", offset))
+            }
+            _ => changes.push("no offset tag to apply".to_string()),
+        }
+    }
+    if let Some(by) = by {
+        if offset_tag {
+            // A positive offset shows lyrics sooner, so delaying them lowers it
+            let offset = lrc.offset().unwrap_or(0) - by;
+            lrc.set_offset(offset);
+            changes.push(format!("set [offset:{:+}]", offset));
+        } else {
+            lrc.shift(by);
+            changes.push(format!("moved timestamps by {:+} ms", by));
+        }
+    }
+
+    write_lyrics_file(&lrc_path, encode_lyrics(&lrc.to_string(), args), args)?;
+    println!(
+        "{} {}",
+        "Shifted:".green().bold(),
+        format!("{} ({})", lrc_path.display(), changes.join(", ")).bright_green()
+    );
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use crate::error::{LrcphileError, Result};
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, Throttle, fetch_with_retry, get_lyrics_file_path, known_lrclib_id, lrc,
+    read_embedded_lyrics, read_lyrics_file, read_metadata,
+};
+use colored::Colorize;
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tokio::sync::Mutex;
+
+/// Pager used when `$PAGER` is not set
+const DEFAULT_PAGER: &str = "less";
+
+/// Prints a track's lyrics, preferring what's already on disk over a network lookup
+pub async fn run(
+    path: &Path,
+    strip_timestamps: bool,
+    no_pager: bool,
+    args: &Cli,
+    state: &Mutex<StateDb>,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    let (lyrics, source) = find_lyrics(&path, args, state).await?;
+    let lyrics = if strip_timestamps {
+        lrc::to_plain_text(&lyrics)
+    } else {
+        lyrics
+    };
+
+    let output = format!("{}\n\n{}\n", source.dimmed(), lyrics.trim());
+    if no_pager || !io::stdout().is_terminal() || !page(&output) {
+        print!("{}", output);
+    }
+    Ok(())
+}
+
+/// Looks for lyrics in the sidecar files, then the audio file's tags, then the LRCLIB instance
+async fn find_lyrics(
+    path: &PathBuf,
+    args: &Cli,
+    state: &Mutex<StateDb>,
+) -> Result<(String, String)> {
+    for extension in ["lrc", "txt"] {
+        let lyrics_path = get_lyrics_file_path(path, extension, args)?;
+        if lyrics_path.exists() {
+            return Ok((
+                read_lyrics_file(&lyrics_path)?,
+                lyrics_path.display().to_string(),
+            ));
+        }
+    }
+
+    if let Some(lyrics) = read_embedded_lyrics(path)? {
+        return Ok((lyrics, format!("{} (embedded)", path.display())));
+    }
+
+    let mut metadata = read_metadata(path, args).await?;
+    metadata.lrclib_id = known_lrclib_id(path, Some(&metadata), state, args).await;
+    let provider = args.lyrics_provider()?;
+    let lyrics = fetch_with_retry(
+        provider.as_ref(),
+        &metadata,
+        &Throttle::default(),
+        &Console::default(),
+        args.search_fallback,
+        args.interactive,
+    )
+    .await?;
+
+    let content = if lyrics.instrumental {
+        "[instrumental]".to_string()
+    } else {
+        lyrics
+            .synced_lyrics
+            .clone()
+            .or_else(|| lyrics.plain_lyrics.clone())
+            .ok_or(LrcphileError::NotFound)?
+    };
+    Ok((content, format!("LRCLIB record {} (not saved)", lyrics.id)))
+}
+
+/// Pipes the text through `$PAGER`, returning false if no pager could be started
+fn page(text: &str) -> bool {
+    let pager = env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    let mut parts = pager.split_whitespace();
+    let Some(program) = parts.next() else {
+        return false;
+    };
+
+    let mut command = Command::new(program);
+    command.args(parts).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        // Keep colors, and don't page lyrics that fit on one screen
+        command.env("LESS", "FRX");
+    }
+
+    let Ok(mut child) = command.spawn() else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let _ = child.wait();
+    true
+}
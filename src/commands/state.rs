@@ -0,0 +1,47 @@
+use crate::StateAction;
+use crate::error::{LrcphileError, Result};
+use crate::state::StateDb;
+use colored::Colorize;
+use std::{fs, io::BufWriter};
+use tokio::sync::Mutex;
+
+/// Exports the state, or merges an exported one in; it's saved once the command ends
+pub async fn run(action: &StateAction, state: &Mutex<StateDb>) -> Result<()> {
+    let mut state = state.lock().await;
+    match action {
+        StateAction::Export { file: Some(file) } => {
+            state.export(BufWriter::new(fs::File::create(file)?))?;
+            println!(
+                "{} {}",
+                "Exported:".green().bold(),
+                format!("state to {}", file.display()).bright_green()
+            );
+        }
+        StateAction::Export { file: None } => state.export(std::io::stdout().lock())?,
+        StateAction::Import {
+            file,
+            relocate,
+            replace,
+        } => {
+            // Loading a missing state file would quietly import nothing
+            if !file.is_file() {
+                return Err(LrcphileError::InvalidPath {
+                    path: file.clone(),
+                    reason: "no exported state there",
+                });
+            }
+            let imported = StateDb::load(file)?;
+            let tracks = imported.records().count();
+            if *replace {
+                state.clear();
+            }
+            state.merge(imported, relocate);
+            println!(
+                "{} {}",
+                "Imported:".green().bold(),
+                format!("{} tracks from {}", tracks, file.display()).bright_green()
+            );
+        }
+    }
+    Ok(())
+}
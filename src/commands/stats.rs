@@ -0,0 +1,196 @@
+use crate::error::Result;
+use crate::state::{FileRecord, ProviderStats, StateDb};
+use colored::Colorize;
+use std::{collections::HashMap, path::Path};
+use tokio::sync::Mutex;
+
+/// How many languages and missing artists to list
+const TOP_ENTRIES: usize = 10;
+
+/// Reports what the library's lyrics are like, from what the state file recorded about each
+/// track when it was last processed, so nothing is read from the library itself
+pub async fn run(dir: Option<&Path>, state: &Mutex<StateDb>) -> Result<()> {
+    let dir = dir.map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()));
+    let state = state.lock().await;
+    let records: Vec<&FileRecord> = state
+        .records()
+        .filter(|(path, _)| dir.as_ref().is_none_or(|dir| path.starts_with(dir)))
+        .map(|(_, record)| record)
+        .filter(|record| record.track_secs.is_some())
+        .collect();
+    if records.is_empty() {
+        println!(
+            "{}",
+            "No tracks recorded yet; run lrcphile over the library first.".yellow()
+        );
+        return Ok(());
+    }
+
+    let total = records.len();
+    let with_lyrics: Vec<_> = records
+        .iter()
+        .filter_map(|record| record.content.as_ref())
+        .collect();
+    let synced = with_lyrics.iter().filter(|content| content.synced).count();
+    let instrumental = with_lyrics
+        .iter()
+        .filter(|content| content.instrumental)
+        .count();
+    let plain = with_lyrics.len() - synced - instrumental;
+    let missing = total - with_lyrics.len();
+
+    println!("{}", "Tracks".bright_cyan().bold());
+    println!("  {:<14} {}", "Total:", total);
+    for (label, count) in [
+        ("Synced:", synced),
+        ("Plain:", plain),
+        ("Instrumental:", instrumental),
+        ("Missing:", missing),
+    ] {
+        println!("  {:<14} {} ({})", label, count, percentage(count, total));
+    }
+
+    let playtime: u64 = records.iter().filter_map(|record| record.track_secs).sum();
+    let synced_playtime: u64 = records
+        .iter()
+        .filter(|record| {
+            record
+                .content
+                .as_ref()
+                .is_some_and(|content| content.synced)
+        })
+        .filter_map(|record| record.track_secs)
+        .sum();
+    println!(
+        "  {:<14} {} of {} ({})",
+        "Sync coverage:",
+        hours(synced_playtime),
+        hours(playtime),
+        percentage(synced_playtime as usize, playtime as usize)
+    );
+
+    let sung: Vec<_> = with_lyrics
+        .iter()
+        .filter(|content| !content.instrumental)
+        .collect();
+    if !sung.is_empty() {
+        let lines: usize = sung.iter().map(|content| content.lines).sum();
+        println!(
+            "  {:<14} {:.1} per track",
+            "Lines:",
+            lines as f64 / sung.len() as f64
+        );
+
+        let languages = most_common(sung.iter().map(|content| {
+            content
+                .language
+                .as_deref()
+                .and_then(whatlang::Lang::from_code)
+                .map_or("Unknown", whatlang::Lang::eng_name)
+                .to_string()
+        }));
+        println!("\n{}", "Languages".bright_cyan().bold());
+        for (language, count) in languages {
+            println!(
+                "  {:<14} {} ({})",
+                language,
+                count,
+                percentage(count, sung.len())
+            );
+        }
+    }
+
+    let missing_artists = most_common(
+        records
+            .iter()
+            .filter(|record| record.content.is_none())
+            .filter_map(|record| record.artist.clone()),
+    );
+    if !missing_artists.is_empty() {
+        println!(
+            "\n{}",
+            "Artists missing the most lyrics".bright_cyan().bold()
+        );
+        for (artist, count) in missing_artists {
+            println!("  {} {}", format!("{:>5}", count).yellow(), artist);
+        }
+    }
+    Ok(())
+}
+
+/// Reports how every provider answered lookups across all runs, to tell which ones are worth
+/// keeping in `--providers`
+pub async fn run_providers(state: &Mutex<StateDb>) -> Result<()> {
+    let state = state.lock().await;
+    let mut providers: Vec<(&String, &ProviderStats)> = state.provider_stats().iter().collect();
+    if providers.is_empty() {
+        println!(
+            "{}",
+            "No lookups recorded yet; run lrcphile over the library first.".yellow()
+        );
+        return Ok(());
+    }
+    providers.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then_with(|| a.0.cmp(b.0)));
+
+    let mut total = ProviderStats::default();
+    for (_, stats) in &providers {
+        total.add(stats);
+    }
+
+    println!(
+        "{}",
+        format!(
+            "  {:<14} {:>9} {:>13} {:>13} {:>13}",
+            "Provider", "Requests", "Hits", "Misses", "Errors"
+        )
+        .bright_cyan()
+        .bold()
+    );
+    let row = |name: &str, stats: &ProviderStats| {
+        let count = |part: u64| {
+            format!(
+                "{} ({})",
+                part,
+                percentage(part as usize, stats.requests as usize)
+            )
+        };
+        format!(
+            "  {:<14} {:>9} {:>13} {:>13} {:>13}",
+            name,
+            stats.requests,
+            count(stats.hits),
+            count(stats.misses),
+            count(stats.errors)
+        )
+    };
+    for (name, stats) in &providers {
+        println!("{}", row(name, stats));
+    }
+    if providers.len() > 1 {
+        println!("{}", row("All", &total).bold());
+    }
+    Ok(())
+}
+
+/// The most frequent values with their counts, most frequent first and ties alphabetically
+fn most_common(values: impl Iterator<Item = String>) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(TOP_ENTRIES);
+    counts
+}
+
+fn percentage(part: usize, whole: usize) -> String {
+    if whole == 0 {
+        return "0%".to_string();
+    }
+    format!("{:.0}%", part as f64 * 100.0 / whole as f64)
+}
+
+fn hours(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+}
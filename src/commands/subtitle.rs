@@ -0,0 +1,50 @@
+use crate::ass;
+use crate::error::{LrcphileError, Result};
+use crate::lrc::Lrc;
+use crate::{Cli, find_lrc_file, read_header_tag, read_lyrics_file, write_lyrics_file};
+use colored::Colorize;
+use std::path::Path;
+
+/// Converts a track's synced lyrics into an ASS subtitle file styled by the `--ass-*` options
+pub fn run(path: &Path, output: Option<&Path>, args: &Cli) -> Result<()> {
+    let lrc_path = find_lrc_file(path, args)?;
+    let (lrc, title) = read_synced_lyrics(&lrc_path)?;
+    let ass_path = output.map_or_else(|| lrc_path.with_extension("ass"), Path::to_path_buf);
+    write_lyrics_file(
+        &ass_path,
+        ass::to_ass(&lrc, &title, &args.ass_style()),
+        args,
+    )?;
+    println!(
+        "{} {}",
+        "Wrote:".green().bold(),
+        ass_path.display().to_string().bright_green()
+    );
+    Ok(())
+}
+
+/// Reads an LRC file for conversion into subtitles, with its offset applied, along with a
+/// title from its `[ti:]` tag or file name
+pub(super) fn read_synced_lyrics(lrc_path: &Path) -> Result<(Lrc, String)> {
+    let content = read_lyrics_file(lrc_path)?;
+    let mut lrc = Lrc::parse(&content);
+    if !lrc.is_synced() {
+        return Err(LrcphileError::Parse(format!(
+            "{}: no timestamped lines",
+            lrc_path.display()
+        )));
+    }
+    // Subtitle players know nothing of the offset tag
+    lrc.flatten_offset();
+
+    let title = read_header_tag(&content, "ti")
+        .filter(|title| !title.is_empty())
+        .map(str::to_string)
+        .or_else(|| {
+            lrc_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+    Ok((lrc, title))
+}
@@ -0,0 +1,208 @@
+use crate::error::Result;
+use crate::state::StateDb;
+use crate::{
+    Cli, INSTRUMENTAL_EXTENSION, apply_file_policy, get_lyrics_file_path, is_edited_since_fetch,
+    lyrics_hash, process_directory, read_metadata, write_lyrics_file,
+};
+use colored::Colorize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::Mutex;
+
+/// Sidecar files mirrored between the libraries
+const SIDECAR_EXTENSIONS: [&str; 3] = ["lrc", "txt", INSTRUMENTAL_EXTENSION];
+
+/// What happened to the sidecars of one pair of tracks
+#[derive(Default)]
+struct SyncReport {
+    copied: usize,
+    conflicts: usize,
+}
+
+/// Mirrors lyrics sidecars between two copies of a library, in both directions
+pub async fn run(src: &Path, dst: &Path, args: &Cli, state: &Mutex<StateDb>) -> Result<()> {
+    let pairs = match_tracks(src, dst, args).await?;
+    println!(
+        "{} {}",
+        "Found:".green().bold(),
+        format!("{} tracks present in both libraries", pairs.len()).bright_cyan()
+    );
+
+    let mut report = SyncReport::default();
+    for (src_track, dst_track) in &pairs {
+        for extension in SIDECAR_EXTENSIONS {
+            if let Err(e) =
+                sync_sidecar(src_track, dst_track, extension, args, state, &mut report).await
+            {
+                eprintln!(
+                    "{} {}",
+                    "Failed:".red().bold(),
+                    format!("Syncing {}: {}", src_track.display(), e).red()
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} {}",
+        "Copied:".green().bold(),
+        format!("{} lyrics files", report.copied).bright_green()
+    );
+    if report.conflicts > 0 {
+        println!(
+            "{} {}",
+            "Conflicts:".yellow().bold(),
+            format!(
+                "{} lyrics files were changed in both libraries since they were last synced and left as they are",
+                report.conflicts
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Pairs up the tracks of both libraries by their path without extension, since the copy
+/// may be transcoded, and then by tags for tracks that were moved or renamed
+async fn match_tracks(src: &Path, dst: &Path, args: &Cli) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let src_tracks = process_directory(src, &scan_args)?;
+    let mut dst_tracks: HashMap<PathBuf, PathBuf> = process_directory(dst, &scan_args)?
+        .into_iter()
+        .filter_map(|track| Some((relative_stem(&track, dst)?, track)))
+        .collect();
+
+    let mut pairs = Vec::new();
+    let mut unmatched = Vec::new();
+    for track in src_tracks {
+        match relative_stem(&track, src).and_then(|stem| dst_tracks.remove(&stem)) {
+            Some(counterpart) => pairs.push((track, counterpart)),
+            None => unmatched.push(track),
+        }
+    }
+
+    if !unmatched.is_empty() {
+        let mut dst_by_tags = HashMap::new();
+        for track in dst_tracks.into_values() {
+            if let Some(key) = tag_key(&track, args).await {
+                dst_by_tags.insert(key, track);
+            }
+        }
+        for track in unmatched {
+            if let Some(key) = tag_key(&track, args).await
+                && let Some(counterpart) = dst_by_tags.remove(&key)
+            {
+                pairs.push((track, counterpart));
+            }
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// Copies one kind of sidecar to whichever side lacks it or changed since the last sync.
+/// When both changed it's a conflict; before a pair's first sync, when there is nothing to
+/// compare with, only copies edited by hand on both sides are, and otherwise the newer wins
+async fn sync_sidecar(
+    src_track: &PathBuf,
+    dst_track: &PathBuf,
+    extension: &str,
+    args: &Cli,
+    state: &Mutex<StateDb>,
+    report: &mut SyncReport,
+) -> Result<()> {
+    let src_file = get_lyrics_file_path(src_track, extension, args)?;
+    let dst_file = get_lyrics_file_path(dst_track, extension, args)?;
+
+    let (from_track, from_file, to_track, to_file) = match (src_file.exists(), dst_file.exists()) {
+        (false, false) => return Ok(()),
+        (true, false) => (src_track, &src_file, dst_track, &dst_file),
+        (false, true) => (dst_track, &dst_file, src_track, &src_file),
+        (true, true) => {
+            let (src_content, dst_content) = (fs::read(&src_file)?, fs::read(&dst_file)?);
+            let src_hash = content_hash(&src_content);
+            if src_content == dst_content {
+                state
+                    .lock()
+                    .await
+                    .record_sync_baseline(&src_file, &dst_file, src_hash);
+                return Ok(());
+            }
+
+            let baseline = state
+                .lock()
+                .await
+                .sync_baseline(&src_file, &dst_file)
+                .map(str::to_string);
+            let (src_changed, dst_changed) = match &baseline {
+                Some(baseline) => (
+                    src_hash != *baseline,
+                    content_hash(&dst_content) != *baseline,
+                ),
+                // Both copies were changed by hand, so neither can be assumed to be the right one
+                None => (
+                    is_edited_since_fetch(src_track, state, args).await,
+                    is_edited_since_fetch(dst_track, state, args).await,
+                ),
+            };
+            if src_changed && dst_changed {
+                eprintln!(
+                    "{} {}",
+                    "Conflict:".yellow().bold(),
+                    format!("{} and {}", src_file.display(), dst_file.display()).yellow()
+                );
+                report.conflicts += 1;
+                return Ok(());
+            }
+
+            let src_wins = if baseline.is_some() {
+                src_changed
+            } else {
+                fs::metadata(&src_file)?.modified()? >= fs::metadata(&dst_file)?.modified()?
+            };
+            if src_wins {
+                (src_track, &src_file, dst_track, &dst_file)
+            } else {
+                (dst_track, &dst_file, src_track, &src_file)
+            }
+        }
+    };
+
+    let content = fs::read(from_file)?;
+    write_lyrics_file(to_file, &content, args)?;
+    apply_file_policy(to_file, to_track, args)?;
+    report.copied += 1;
+
+    let mut state = state.lock().await;
+    state.record_sync_baseline(&src_file, &dst_file, content_hash(&content));
+    // The copy is exactly what the other side holds, so it shares its edit tracking
+    if let Some(hash) = state
+        .get(from_track)
+        .and_then(|record| record.lyrics_hash.clone())
+    {
+        state.record_lyrics_hash(to_track, hash);
+    }
+    Ok(())
+}
+
+/// Hash of a sidecar's bytes, as kept for the sync baseline
+fn content_hash(content: &[u8]) -> String {
+    lyrics_hash(&String::from_utf8_lossy(content))
+}
+
+fn relative_stem(track: &Path, root: &Path) -> Option<PathBuf> {
+    Some(track.strip_prefix(root).ok()?.with_extension(""))
+}
+
+async fn tag_key(track: &PathBuf, args: &Cli) -> Option<(String, String, String)> {
+    let metadata = read_metadata(track, args).await.ok()?;
+    Some((
+        metadata.track_name.to_lowercase(),
+        metadata.artist_name.to_lowercase(),
+        metadata.album_name.to_lowercase(),
+    ))
+}
@@ -0,0 +1,513 @@
+use crate::error::{LrcphileError, Result};
+use crate::provider::LyricsProvider;
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, INSTRUMENTAL_EXTENSION, LyricsStatus, Throttle, TrackMetadata, fetch_with_retry,
+    get_lyrics_file_path, known_lrclib_id, prepare_lyrics_file, process_directory,
+    read_lyrics_file, read_metadata, save_lyrics_file, written_lyrics_hash,
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Wrap},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{
+    Mutex,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+/// Number of log lines kept for the log pane
+const LOG_CAPACITY: usize = 200;
+
+impl LyricsStatus {
+    fn glyph(self) -> Span<'static> {
+        match self {
+            LyricsStatus::Synced => "●".fg(Color::Green),
+            LyricsStatus::Plain => "◐".fg(Color::Yellow),
+            LyricsStatus::Instrumental => "♪".fg(Color::Cyan),
+            LyricsStatus::Missing => "○".fg(Color::Red),
+        }
+    }
+}
+
+struct Track {
+    path: PathBuf,
+    status: LyricsStatus,
+    busy: bool,
+}
+
+/// A line of the library tree: an album directory or one of its tracks
+enum Row {
+    Directory(PathBuf),
+    Track(usize),
+}
+
+/// Results sent back to the UI by background fetches
+enum Update {
+    Log(String),
+    Refresh(usize),
+    Candidate(usize, String),
+}
+
+/// Everything a background fetch needs, cloned into each task
+#[derive(Clone)]
+struct Worker {
+    args: Cli,
+    provider: Arc<dyn LyricsProvider>,
+    throttle: Arc<Throttle>,
+    state: Arc<Mutex<StateDb>>,
+    console: Console,
+    updates: UnboundedSender<Update>,
+}
+
+struct App {
+    args: Cli,
+    root: PathBuf,
+    tracks: Vec<Track>,
+    rows: Vec<Row>,
+    list_state: ListState,
+    metadata: HashMap<usize, Option<TrackMetadata>>,
+    candidates: HashMap<usize, String>,
+    log: Vec<String>,
+}
+
+/// Full-screen library browser for inspecting and curating lyrics
+pub async fn run(dir: &Path, args: &Cli, state: &Arc<Mutex<StateDb>>) -> Result<()> {
+    let mut scan_args = args.clone();
+    scan_args.recursive = true;
+    let audio_files = process_directory(dir, &scan_args)?;
+
+    let (updates, mut receiver) = mpsc::unbounded_channel();
+    let (log_sender, mut log_receiver) = mpsc::unbounded_channel();
+    let worker = Worker {
+        args: args.clone(),
+        provider: args.lyrics_provider()?,
+        throttle: Arc::new(Throttle::default()),
+        state: state.clone(),
+        console: Console::with_log(log_sender),
+        updates,
+    };
+
+    let mut app = App::new(dir, audio_files, args);
+
+    // Log lines are plain text in the TUI, so drop terminal color codes while it runs
+    colored::control::set_override(false);
+    let mut terminal = ratatui::init();
+    let result = app
+        .event_loop(&mut terminal, &worker, &mut receiver, &mut log_receiver)
+        .await;
+    ratatui::restore();
+    colored::control::unset_override();
+
+    result
+}
+
+impl App {
+    fn new(root: &Path, audio_files: Vec<PathBuf>, args: &Cli) -> Self {
+        let tracks: Vec<Track> = audio_files
+            .into_iter()
+            .map(|path| Track {
+                status: LyricsStatus::of(&path, args),
+                path,
+                busy: false,
+            })
+            .collect();
+
+        let mut rows = Vec::new();
+        let mut current_dir = None;
+        for (index, track) in tracks.iter().enumerate() {
+            let dir = track.path.parent().map(Path::to_path_buf);
+            if dir != current_dir {
+                if let Some(dir) = &dir {
+                    rows.push(Row::Directory(dir.clone()));
+                }
+                current_dir = dir;
+            }
+            rows.push(Row::Track(index));
+        }
+
+        let mut list_state = ListState::default();
+        if !rows.is_empty() {
+            list_state.select(Some(0));
+        }
+
+        Self {
+            args: args.clone(),
+            root: root.to_path_buf(),
+            tracks,
+            rows,
+            list_state,
+            metadata: HashMap::new(),
+            candidates: HashMap::new(),
+            log: vec![
+                "Keys: ↑/↓ move, f fetch, o override, p preview, d delete lyrics, q quit"
+                    .to_string(),
+            ],
+        }
+    }
+
+    async fn event_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        worker: &Worker,
+        receiver: &mut UnboundedReceiver<Update>,
+        log_receiver: &mut UnboundedReceiver<String>,
+    ) -> Result<()> {
+        loop {
+            self.load_selected_metadata().await;
+            terminal.draw(|frame| self.draw(frame))?;
+
+            while let Ok(line) = log_receiver.try_recv() {
+                self.push_log(line);
+            }
+            while let Ok(update) = receiver.try_recv() {
+                self.apply(update);
+            }
+
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.list_state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.list_state.select_previous(),
+                KeyCode::PageDown => self.list_state.scroll_down_by(10),
+                KeyCode::PageUp => self.list_state.scroll_up_by(10),
+                KeyCode::Char('f') => self.fetch_selected(worker, false, false),
+                KeyCode::Char('o') => self.fetch_selected(worker, true, false),
+                KeyCode::Char('p') => self.fetch_selected(worker, true, true),
+                KeyCode::Char('d') => self.delete_selected(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Tracks affected by an action: the selected track, or every track of a selected album
+    fn selected_tracks(&self) -> Vec<usize> {
+        match self
+            .list_state
+            .selected()
+            .and_then(|row| self.rows.get(row))
+        {
+            Some(Row::Track(index)) => vec![*index],
+            Some(Row::Directory(dir)) => (0..self.tracks.len())
+                .filter(|&index| self.tracks[index].path.parent() == Some(dir.as_path()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn selected_track(&self) -> Option<usize> {
+        match self
+            .list_state
+            .selected()
+            .and_then(|row| self.rows.get(row))
+        {
+            Some(Row::Track(index)) => Some(*index),
+            _ => None,
+        }
+    }
+
+    async fn load_selected_metadata(&mut self) {
+        if let Some(index) = self.selected_track()
+            && !self.metadata.contains_key(&index)
+        {
+            let metadata = read_metadata(&self.tracks[index].path, &self.args)
+                .await
+                .ok();
+            self.metadata.insert(index, metadata);
+        }
+    }
+
+    fn fetch_selected(&mut self, worker: &Worker, override_files: bool, preview: bool) {
+        for index in self.selected_tracks() {
+            let track = &mut self.tracks[index];
+            if track.busy {
+                continue;
+            }
+            if !override_files && track.status != LyricsStatus::Missing {
+                self.log
+                    .push(format!("{}: lyrics already exist", file_name(&track.path)));
+                continue;
+            }
+
+            track.busy = true;
+            let path = track.path.clone();
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                worker.fetch(index, path, preview).await;
+            });
+        }
+    }
+
+    fn delete_selected(&mut self) {
+        for index in self.selected_tracks() {
+            let path = self.tracks[index].path.clone();
+            for extension in ["lrc", "txt", INSTRUMENTAL_EXTENSION] {
+                if let Ok(lyrics_path) = get_lyrics_file_path(&path, extension, &self.args)
+                    && lyrics_path.exists()
+                {
+                    let message = match fs::remove_file(&lyrics_path) {
+                        Ok(()) => format!("Deleted {}", file_name(&lyrics_path)),
+                        Err(e) => format!("Failed to delete {}: {}", file_name(&lyrics_path), e),
+                    };
+                    self.push_log(message);
+                }
+            }
+            self.candidates.remove(&index);
+            self.tracks[index].status = LyricsStatus::of(&path, &self.args);
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Log(line) => self.push_log(line),
+            Update::Refresh(index) => {
+                let track = &mut self.tracks[index];
+                track.busy = false;
+                track.status = LyricsStatus::of(&track.path, &self.args);
+                self.candidates.remove(&index);
+            }
+            Update::Candidate(index, lyrics) => {
+                self.tracks[index].busy = false;
+                self.candidates.insert(index, lyrics);
+            }
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        self.log.push(line);
+        if self.log.len() > LOG_CAPACITY {
+            self.log.remove(0);
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, log_area] =
+            Layout::vertical([Constraint::Min(10), Constraint::Length(8)]).areas(frame.area());
+        let [tree_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main_area);
+
+        let items: Vec<ListItem> = self.rows.iter().map(|row| self.row_item(row)).collect();
+        let with_lyrics = self
+            .tracks
+            .iter()
+            .filter(|track| track.status != LyricsStatus::Missing)
+            .count();
+        let tree = List::new(items)
+            .block(Block::bordered().title(format!(
+                " {} ({}/{} with lyrics) ",
+                self.root.display(),
+                with_lyrics,
+                self.tracks.len()
+            )))
+            .highlight_style(Style::new().reversed());
+        // The list borrows the rows, so render against a copy of the selection state
+        let mut list_state = self.list_state.clone();
+        frame.render_stateful_widget(tree, tree_area, &mut list_state);
+        self.list_state = list_state;
+
+        let detail = Paragraph::new(self.detail_lines())
+            .block(Block::bordered().title(" Details "))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(detail, detail_area);
+
+        let visible_log = log_area.height.saturating_sub(2) as usize;
+        let log_lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible_log))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(log_lines).block(Block::bordered().title(" Log ")),
+            log_area,
+        );
+    }
+
+    fn row_item(&self, row: &Row) -> ListItem<'_> {
+        match row {
+            Row::Directory(dir) => {
+                let tracks: Vec<&Track> = self
+                    .tracks
+                    .iter()
+                    .filter(|track| track.path.parent() == Some(dir.as_path()))
+                    .collect();
+                let covered = tracks
+                    .iter()
+                    .filter(|track| track.status != LyricsStatus::Missing)
+                    .count();
+                let name = dir.strip_prefix(&self.root).unwrap_or(dir);
+                ListItem::new(Line::from(vec![
+                    format!("▾ {} ", name.display()).bold(),
+                    format!("({}/{})", covered, tracks.len()).dim(),
+                ]))
+            }
+            Row::Track(index) => {
+                let track = &self.tracks[*index];
+                let mut spans = vec![
+                    "  ".into(),
+                    track.status.glyph(),
+                    format!(" {}", file_name(&track.path)).into(),
+                ];
+                if track.busy {
+                    spans.push(" …".dim());
+                }
+                ListItem::new(Line::from(spans))
+            }
+        }
+    }
+
+    fn detail_lines(&self) -> Vec<Line<'_>> {
+        let Some(index) = self.selected_track() else {
+            return vec![Line::raw(
+                "Select a track, or press f/o/d to act on a whole album",
+            )];
+        };
+        let track = &self.tracks[index];
+
+        let mut lines = vec![Line::raw(track.path.display().to_string()).dim()];
+        match self.metadata.get(&index) {
+            Some(Some(metadata)) => {
+                lines.push(Line::from(vec![
+                    "Title:  ".bold(),
+                    metadata.track_name.clone().into(),
+                ]));
+                lines.push(Line::from(vec![
+                    "Artist: ".bold(),
+                    metadata.artist_name.clone().into(),
+                ]));
+                lines.push(Line::from(vec![
+                    "Album:  ".bold(),
+                    metadata.album_name.clone().into(),
+                ]));
+                lines.push(Line::from(vec![
+                    "Length: ".bold(),
+                    format!(
+                        "{}:{:02}",
+                        metadata.duration as u32 / 60,
+                        metadata.duration as u32 % 60
+                    )
+                    .into(),
+                ]));
+            }
+            _ => lines.push(Line::raw("Missing required metadata (title, artist, or album)").red()),
+        }
+        lines.push(Line::raw(""));
+
+        if let Some(candidate) = self.candidates.get(&index) {
+            lines.push(
+                Line::raw("Fetched candidate (press o to save):")
+                    .bold()
+                    .cyan(),
+            );
+            lines.extend(candidate.lines().map(|line| Line::raw(line.to_string())));
+            return lines;
+        }
+
+        let existing = ["lrc", "txt"].iter().find_map(|extension| {
+            let path = get_lyrics_file_path(&track.path, extension, &self.args).ok()?;
+            read_lyrics_file(&path).ok()
+        });
+        match existing {
+            Some(content) => {
+                lines.push(Line::raw("Current lyrics:").bold());
+                lines.extend(content.lines().map(|line| Line::raw(line.to_string())));
+            }
+            None => lines.push(Line::raw("No lyrics yet (press f to fetch)").dim()),
+        }
+        lines
+    }
+}
+
+impl Worker {
+    async fn fetch(&self, index: usize, path: PathBuf, preview: bool) {
+        let name = file_name(&path);
+        let update = match self.fetch_lyrics(&path, preview).await {
+            Ok(Some(lyrics)) => {
+                let _ = self
+                    .updates
+                    .send(Update::Log(format!("{}: fetched candidate", name)));
+                Update::Candidate(index, lyrics)
+            }
+            Ok(None) => {
+                let _ = self
+                    .updates
+                    .send(Update::Log(format!("{}: saved lyrics", name)));
+                Update::Refresh(index)
+            }
+            Err(e) => {
+                let _ = self.updates.send(Update::Log(format!("{}: {}", name, e)));
+                Update::Refresh(index)
+            }
+        };
+        let _ = self.updates.send(update);
+    }
+
+    /// Looks up lyrics for a track, returning them for preview or saving them in place
+    async fn fetch_lyrics(&self, path: &PathBuf, preview: bool) -> Result<Option<String>> {
+        let mut metadata = read_metadata(path, &self.args).await?;
+        metadata.lrclib_id = known_lrclib_id(path, Some(&metadata), &self.state, &self.args).await;
+
+        let lyrics = fetch_with_retry(
+            self.provider.as_ref(),
+            &metadata,
+            &self.throttle,
+            &self.console,
+            self.args.search_fallback,
+            false,
+        )
+        .await?;
+        let lyrics_file = prepare_lyrics_file(
+            &lyrics,
+            self.args.instrumental_placeholder,
+            self.args.lyrics_type,
+        )
+        .ok_or(LrcphileError::NotFound)?;
+
+        if preview {
+            return Ok(Some(lyrics_file.content));
+        }
+
+        let lyrics_path = save_lyrics_file(
+            path,
+            &lyrics_file.content,
+            lyrics_file.extension,
+            Some(&metadata),
+            &self.state,
+            &self.args,
+        )
+        .await?;
+        let mut state = self.state.lock().await;
+        state.record_lrclib_id(path, lyrics.lrclib_id(), &metadata);
+        state.record_lyrics_hash(
+            path,
+            written_lyrics_hash(&lyrics_path, &lyrics_file.content),
+        );
+        Ok(None)
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
@@ -0,0 +1,149 @@
+use crate::error::{LrcphileError, Result};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// User defaults read from `config.toml` in the user config directory
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Library processed when no path is given
+    pub music_dir: Option<PathBuf>,
+    /// Never override hand-made lyrics files unless `--force` is given
+    pub preserve_manual: Option<bool>,
+    /// Regular expressions for the lines `--clean-lyrics` removes, replacing the built-in ones
+    #[serde(default)]
+    pub clean_patterns: Vec<String>,
+    /// Words `--censored-copy` masks, replacing the built-in list
+    #[serde(default)]
+    pub censor_words: Vec<String>,
+    /// Profile used when `--profile` isn't given
+    pub default_profile: Option<String>,
+    /// Named sets of defaults selected with `--profile`
+    #[serde(default, rename = "profile")]
+    pub profiles: HashMap<String, Profile>,
+    /// Extra lyrics APIs, written as `[[provider]]` tables and selected with `--providers`
+    #[serde(default, rename = "provider")]
+    pub providers: Vec<ProviderSpec>,
+}
+
+/// A lyrics API described declaratively, so it can be queried without code of its own
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSpec {
+    /// Name `--providers` selects it by, recorded in the header of the files it provides
+    pub name: String,
+    /// Lookup URL; `{title}`, `{artist}`, `{album}` and `{duration}` are filled in per track
+    /// and `${VAR}` with an environment variable, e.g. for an API key
+    pub url: String,
+    /// Request headers, e.g. `Authorization = "Bearer ${GENIUS_TOKEN}"`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Dot-separated paths into the JSON response, like `message.body.lyrics`, with numbers
+    /// indexing arrays; at least one of `synced` and `plain` is required
+    pub synced: Option<String>,
+    pub plain: Option<String>,
+    pub instrumental: Option<String>,
+    /// Where the record's own details are, so confidence scoring can compare them with the
+    /// tags; the tags stand in for any not given
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// In seconds
+    pub duration: Option<String>,
+}
+
+/// Defaults for one library, used for any option not given on the command line
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub music_dir: Option<PathBuf>,
+    pub url: Option<String>,
+    /// Provider names in `--providers` order
+    #[serde(default)]
+    pub providers: Vec<String>,
+    /// DNS overrides in `--resolve` syntax
+    #[serde(default)]
+    pub resolve: Vec<String>,
+    pub ipv4: Option<bool>,
+    pub ipv6: Option<bool>,
+    pub recursive: Option<bool>,
+    #[serde(rename = "override")]
+    pub override_files: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    pub max_depth: Option<usize>,
+    /// Tag filters in `--filter` syntax
+    #[serde(default)]
+    pub filters: Vec<String>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub cached_only: Option<bool>,
+    pub preserve_manual: Option<bool>,
+    pub state_file: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub order: Option<String>,
+    /// `any`, `synced` or `plain`, as with `--lyrics-type`
+    pub lyrics_type: Option<String>,
+    pub concurrency: Option<u32>,
+    /// Requests per second
+    pub rate_limit: Option<f64>,
+    pub max_requests: Option<u64>,
+    /// In megabytes
+    pub max_memory: Option<u64>,
+    /// Runtime budget in `--max-runtime` syntax
+    pub max_runtime: Option<String>,
+    pub min_confidence: Option<u8>,
+    pub normalize_encoding: Option<bool>,
+    pub bom: Option<bool>,
+    pub crlf: Option<bool>,
+    pub lrc_extension: Option<String>,
+    pub txt_extension: Option<String>,
+    pub expand_timestamps: Option<bool>,
+    pub flatten_offset: Option<bool>,
+    pub repair_timing: Option<bool>,
+    pub gap_marker: Option<u64>,
+    pub normalize_text: Option<bool>,
+    pub quote_style: Option<String>,
+    pub clean_lyrics: Option<bool>,
+    pub censored_copy: Option<bool>,
+    pub max_line_length: Option<u64>,
+    pub split_synced: Option<bool>,
+    pub match_mtime: Option<bool>,
+    /// Octal permissions in `--file-mode` syntax, e.g. "644"
+    pub file_mode: Option<String>,
+    pub copy_owner: Option<bool>,
+    pub fix_tags: Option<bool>,
+    pub sidecar_metadata: Option<bool>,
+    pub output_filter: Option<String>,
+    pub verbose: Option<bool>,
+    /// `text` or `table`, as with `--summary`
+    pub summary: Option<String>,
+    pub ass_font: Option<String>,
+    pub ass_font_size: Option<u32>,
+    /// Colors as `#RRGGBB` or `#RRGGBBAA`
+    pub ass_color: Option<String>,
+    pub ass_karaoke_color: Option<String>,
+    pub ass_outline_color: Option<String>,
+    pub ass_position: Option<String>,
+    pub ass_karaoke: Option<bool>,
+}
+
+impl Config {
+    pub fn default_location() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "lrcphile").map(|dirs| dirs.config_dir().join("config.toml"))
+    }
+
+    /// Loads the config file, using defaults when it does not exist
+    pub fn load(location: &Path) -> Result<Self> {
+        if !location.exists() {
+            return Ok(Config::default());
+        }
+
+        toml::from_str(&fs::read_to_string(location)?)
+            .map_err(|e| LrcphileError::Parse(format!("{}: {}", location.display(), e)))
+    }
+}
@@ -0,0 +1,183 @@
+use crate::TrackMetadata;
+use crate::config::ProviderSpec;
+use crate::error::{LrcphileError, Result};
+use crate::provider::{
+    ClientOptions, HttpResponse, LRCLIB_PROVIDER, LyricsProvider, LyricsResponse, MatchKind,
+    ReqwestTransport, Transport, wrap_transport,
+};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// A lyrics API configured with a `[[provider]]` table: one GET per track, with the lyrics
+/// picked out of the JSON response by path
+pub struct CustomProvider {
+    name: String,
+    /// URL template with `${VAR}` already expanded
+    url: String,
+    headers: Vec<(String, String)>,
+    spec: ProviderSpec,
+    transport: Arc<dyn Transport>,
+}
+
+impl CustomProvider {
+    pub fn new(spec: &ProviderSpec, options: &ClientOptions) -> Result<Self> {
+        let invalid = |reason: &str| {
+            LrcphileError::Provider(format!(
+                "[[provider]] {} in the config file: {}",
+                spec.name, reason
+            ))
+        };
+        if spec.name.eq_ignore_ascii_case(LRCLIB_PROVIDER) {
+            return Err(invalid("the name lrclib is taken by the built-in provider"));
+        }
+        if spec.synced.is_none() && spec.plain.is_none() {
+            return Err(invalid("needs a synced or plain path to find the lyrics"));
+        }
+        if !spec.url.starts_with("http://") && !spec.url.starts_with("https://") {
+            return Err(invalid("url must start with http:// or https://"));
+        }
+
+        let headers = spec
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), expand_env(value).map_err(|e| invalid(&e))?)))
+            .collect::<Result<_>>()?;
+        let transport = wrap_transport(Arc::new(ReqwestTransport::new(options)?), options)?;
+
+        Ok(Self {
+            name: spec.name.clone(),
+            url: expand_env(&spec.url).map_err(|e| invalid(&e))?,
+            headers,
+            spec: spec.clone(),
+            transport,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request_url(&self, metadata: &TrackMetadata) -> String {
+        self.url
+            .replace("{title}", &urlencoding::encode(&metadata.track_name))
+            .replace("{artist}", &urlencoding::encode(&metadata.artist_name))
+            .replace("{album}", &urlencoding::encode(&metadata.album_name))
+            .replace(
+                "{duration}",
+                &(metadata.duration.round() as u64).to_string(),
+            )
+    }
+
+    /// Picks the lyrics out of a response. Title, artist or album the spec doesn't locate are
+    /// left empty, as unknown, rather than taken from the track, which would make any answer
+    /// look like a perfect match
+    fn parse_response(
+        &self,
+        response: HttpResponse,
+        metadata: &TrackMetadata,
+    ) -> Result<LyricsResponse> {
+        match response.status {
+            200..=299 => {}
+            404 => return Err(LrcphileError::NotFound),
+            429 => {
+                return Err(LrcphileError::RateLimited {
+                    retry_after: response.retry_after(),
+                });
+            }
+            status => {
+                return Err(LrcphileError::Provider(format!(
+                    "{} request failed with status: {}",
+                    self.name, status
+                )));
+            }
+        }
+
+        let body: Value = serde_json::from_slice(&response.body)
+            .map_err(|e| LrcphileError::Parse(format!("{} response: {}", self.name, e)))?;
+        let field = |path: &Option<String>| path.as_deref().and_then(|path| lookup(&body, path));
+        let text = |path: &Option<String>| {
+            field(path)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+        };
+
+        let synced_lyrics = text(&self.spec.synced);
+        let plain_lyrics = text(&self.spec.plain);
+        let instrumental = field(&self.spec.instrumental).is_some_and(|value| {
+            value.as_bool().unwrap_or(false) || value.as_u64().is_some_and(|flag| flag != 0)
+        });
+        if synced_lyrics.is_none() && plain_lyrics.is_none() && !instrumental {
+            return Err(LrcphileError::NotFound);
+        }
+
+        Ok(LyricsResponse {
+            id: 0,
+            track_name: text(&self.spec.title).unwrap_or_default(),
+            artist_name: text(&self.spec.artist).unwrap_or_default(),
+            album_name: text(&self.spec.album).unwrap_or_default(),
+            duration: field(&self.spec.duration)
+                .and_then(|value| value.as_f64().or_else(|| value.as_str()?.parse().ok()))
+                .unwrap_or(metadata.duration),
+            instrumental,
+            plain_lyrics,
+            synced_lyrics,
+            provider: self.name.clone().into(),
+            validators: Default::default(),
+            matched: MatchKind::Exact,
+            confidence: None,
+            namesakes: 0,
+        })
+    }
+}
+
+impl LyricsProvider for CustomProvider {
+    fn fetch<'a>(&'a self, metadata: &'a TrackMetadata) -> BoxFuture<'a, Result<LyricsResponse>> {
+        Box::pin(async move {
+            let response = self
+                .transport
+                .get(&self.request_url(metadata), self.headers.clone())
+                .await?;
+            self.parse_response(response, metadata)
+        })
+    }
+
+    /// These APIs answer a lookup with one record, so there are no candidates to search
+    fn search<'a>(
+        &'a self,
+        _metadata: &'a TrackMetadata,
+    ) -> BoxFuture<'a, Result<Vec<LyricsResponse>>> {
+        Box::pin(async { Err(LrcphileError::NotFound) })
+    }
+}
+
+/// Follows a path like `message.body.lyrics.0.text` into a JSON value
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| match value {
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => value.get(key),
+        })
+}
+
+/// Replaces each `${VAR}` with the environment variable, so secrets stay out of the config file
+fn expand_env(template: &str) -> std::result::Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + end];
+        let value =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
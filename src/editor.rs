@@ -0,0 +1,121 @@
+use crate::error::{LrcphileError, Result};
+use crate::{lrc, prompt};
+use colored::Colorize;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Editor used when neither `$VISUAL` nor `$EDITOR` is set
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Most syntax problems listed after an edit; the others are only counted
+const MAX_LISTED_ERRORS: usize = 5;
+
+/// Opens a file in the user's editor and waits for it to be closed
+pub fn edit_file(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // Editors are often given with options, like `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or(DEFAULT_EDITOR);
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .map_err(|e| {
+            LrcphileError::Io(std::io::Error::new(
+                e.kind(),
+                format!("could not start editor {}: {}", program, e),
+            ))
+        })?;
+    if !status.success() {
+        return Err(LrcphileError::Io(std::io::Error::other(format!(
+            "editor {} exited with {}",
+            program, status
+        ))));
+    }
+    Ok(())
+}
+
+/// Lets the user edit some text in their editor, through a temporary file named with the
+/// extension so the editor highlights it, and returns what they saved
+pub fn edit_text(text: &str, extension: &str) -> Result<String> {
+    let (path, mut file) = create_temp_file(extension)?;
+    let _temp_file = RemoveOnDrop(&path);
+    file.write_all(text.as_bytes())?;
+    // Closed before the editor opens it, which Windows requires
+    drop(file);
+
+    edit_file(&path)?;
+    crate::read_lyrics_file(&path)
+}
+
+/// Creates a new file in the temporary directory under a random name, so another user can't
+/// have put a file or symlink there first
+fn create_temp_file(extension: &str) -> Result<(PathBuf, fs::File)> {
+    loop {
+        let path = env::temp_dir().join(format!(
+            "lrcphile-{:016x}.{}",
+            rand::random::<u64>(),
+            extension
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Removes the file when dropped, so it goes away however editing ends
+struct RemoveOnDrop<'a>(&'a Path);
+
+impl Drop for RemoveOnDrop<'_> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.0);
+    }
+}
+
+/// Lets the user edit lyrics, checking the LRC syntax of what they saved and offering to edit
+/// again until it's valid; `None` when they discard the edit
+pub fn edit_lyrics(text: &str, extension: &str) -> Result<Option<String>> {
+    let mut edited = text.to_string();
+    loop {
+        edited = edit_text(&edited, extension)?;
+        let errors = if extension == "lrc" {
+            lrc::syntax_errors(&edited)
+        } else {
+            Vec::new()
+        };
+        if errors.is_empty() {
+            return Ok(Some(edited));
+        }
+
+        println!("{}", "Invalid LRC:".yellow().bold());
+        for error in errors.iter().take(MAX_LISTED_ERRORS) {
+            println!("  {}", error.yellow());
+        }
+        if errors.len() > MAX_LISTED_ERRORS {
+            println!("  … and {} more", errors.len() - MAX_LISTED_ERRORS);
+        }
+        loop {
+            match prompt::ask("[e]dit again, [k]eep it anyway or [d]iscard the edit? ").as_deref() {
+                Some("e" | "edit" | "") => break,
+                Some("k" | "keep") => return Ok(Some(edited)),
+                Some("d" | "discard") | None => return Ok(None),
+                _ => println!("{}", "Enter e, k or d.".yellow()),
+            }
+        }
+    }
+}
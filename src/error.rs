@@ -0,0 +1,73 @@
+use std::{path::PathBuf, time::Duration};
+use thiserror::Error;
+
+/// Errors that can occur while looking up and saving lyrics
+#[derive(Debug, Error)]
+pub enum LrcphileError {
+    /// The audio file's tags couldn't be read or lack the fields needed for a lookup
+    #[error("{0}")]
+    Metadata(String),
+
+    /// The provider has no lyrics for the track
+    #[error("No lyrics found")]
+    NotFound,
+
+    /// A conditional lookup found the record unchanged since the lyrics were last fetched
+    #[error("Lyrics unchanged since they were fetched")]
+    NotModified,
+
+    /// The provider rejected the request because too many were made
+    #[error("Rate limited by the lyrics provider")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// A response or local file couldn't be decoded
+    #[error("Failed to parse {0}")]
+    Parse(String),
+
+    /// The provider failed to answer or answered with an unexpected status
+    #[error("{0}")]
+    Provider(String),
+
+    /// The `--output-filter` command refused the lyrics, or couldn't be run
+    #[error("Rejected by the output filter: {0}")]
+    Rejected(String),
+
+    /// `--preserve-manual` keeps the track's hand-made lyrics file from being replaced
+    #[error("Keeping hand-made lyrics file {} (use --force to override it)", .0.display())]
+    ManualLyrics(PathBuf),
+
+    /// The track's lyrics file was edited since lrcphile wrote it, so it isn't replaced
+    #[error("Keeping lyrics file {} edited since it was fetched (use --force to override it)", .0.display())]
+    EditedSinceFetch(PathBuf),
+
+    /// A lyrics file location couldn't be derived from the audio path
+    #[error("Invalid path {}: {reason}", path.display())]
+    InvalidPath { path: PathBuf, reason: &'static str },
+}
+
+pub type Result<T, E = LrcphileError> = std::result::Result<T, E>;
+
+impl From<lofty::error::LoftyError> for LrcphileError {
+    fn from(error: lofty::error::LoftyError) -> Self {
+        LrcphileError::Metadata(format!("Failed to read tags: {}", error))
+    }
+}
+
+impl From<reqwest::Error> for LrcphileError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            LrcphileError::Parse(format!("provider response: {}", error))
+        } else {
+            LrcphileError::Provider(format!("Request failed: {}", error))
+        }
+    }
+}
+
+impl From<serde_json::Error> for LrcphileError {
+    fn from(error: serde_json::Error) -> Self {
+        LrcphileError::Parse(error.to_string())
+    }
+}
@@ -0,0 +1,126 @@
+use crate::error::{LrcphileError, Result};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Name of the lock file kept in the root of a library while a run processes it
+pub const LOCK_FILE_NAME: &str = ".lrcphile.lock";
+
+/// How long a lock file may stay empty before it's taken as left behind by a crash
+const UNWRITTEN_LOCK_AGE: Duration = Duration::from_secs(60);
+
+/// Keeps other lrcphile runs, on this machine or others sharing the library over the
+/// network, from processing the same library at the same time; released when dropped
+pub struct LibraryLock {
+    path: PathBuf,
+}
+
+impl LibraryLock {
+    /// Takes the library's lock, replacing one left behind by a run that's no longer going
+    /// or, with `break_lock`, any existing one. `None` when the library can't be written to,
+    /// since then no run can change it either
+    pub fn acquire(library: &Path, break_lock: bool) -> Result<Option<Self>> {
+        let path = library.join(LOCK_FILE_NAME);
+        let owner = format!(
+            "pid={} host={} started={}\n",
+            std::process::id(),
+            hostname(),
+            chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        );
+
+        // A lock found stale is removed and taken again, once
+        for _ in 0..2 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    file.write_all(owner.as_bytes())?;
+                    return Ok(Some(Self { path }));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if !break_lock && !is_stale(&path) {
+                        return Err(LrcphileError::InvalidPath {
+                            path,
+                            reason: "another lrcphile run is processing this library (remove the lock file or pass --break-lock if it crashed)",
+                        });
+                    }
+                    match fs::remove_file(&path) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+                    ) =>
+                {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(LrcphileError::InvalidPath {
+            path,
+            reason: "the lock file keeps coming back; another run is starting at the same time",
+        })
+    }
+
+    /// Who holds the lock of a library, as written in its lock file
+    pub fn holder(library: &Path) -> Option<String> {
+        fs::read_to_string(library.join(LOCK_FILE_NAME))
+            .ok()
+            .map(|holder| holder.trim().to_string())
+    }
+}
+
+impl Drop for LibraryLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a lock was left behind by a process on this machine that has since exited; locks
+/// of other machines can't be checked, so they're only broken with `--break-lock`
+fn is_stale(path: &Path) -> bool {
+    let holder = fs::read_to_string(path).unwrap_or_default();
+    let field = |name: &str| {
+        holder
+            .split_whitespace()
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+    };
+    let (Some(pid), Some(host)) = (field("pid"), field("host")) else {
+        // Another run may be about to write its details; past that, the file was cut short
+        return fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > UNWRITTEN_LOCK_AGE);
+    };
+    host == hostname() && !is_running(pid)
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: &str) -> bool {
+    Path::new("/proc").join(pid).exists()
+}
+
+/// Without `/proc` there is no portable way to look a process up, so it's assumed alive
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: &str) -> bool {
+    true
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
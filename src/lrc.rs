@@ -0,0 +1,779 @@
+use clap::ValueEnum;
+use regex::Regex;
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+/// Text of the line put into long instrumental gaps
+pub const GAP_MARKER: &str = "♪";
+
+/// How long the line before a long gap stays up before the gap marker replaces it
+const GAP_LINE_HOLD_MS: u64 = 5000;
+
+/// A point in a track, as written in an LRC timestamp like `[01:10.00]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub millis: u64,
+    /// Digits after the decimal point in the original, kept so untouched lines round-trip
+    precision: u8,
+}
+
+impl Timestamp {
+    /// Parses the inside of a timestamp tag, e.g. `01:10.00`
+    pub fn parse(tag: &str) -> Option<Self> {
+        let (minutes, seconds) = tag.split_once(':')?;
+        if minutes.is_empty() || !minutes.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+        if whole.is_empty()
+            || !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+            || fraction.len() > 3
+        {
+            return None;
+        }
+
+        let fraction_millis = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+        Some(Self {
+            millis: (minutes.parse::<u64>().ok()? * 60 + whole.parse::<u64>().ok()?) * 1000
+                + fraction_millis,
+            precision: fraction.len() as u8,
+        })
+    }
+}
+
+impl Timestamp {
+    /// A timestamp written with hundredths of a second, as most LRC files are
+    pub fn from_millis(millis: u64) -> Self {
+        Self {
+            millis,
+            precision: 2,
+        }
+    }
+
+    /// Moves the timestamp later by `millis`, or earlier when negative, stopping at zero
+    pub fn shifted(self, millis: i64) -> Self {
+        Self {
+            millis: self.millis.saturating_add_signed(millis),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = self.millis / 60_000;
+        let seconds = self.millis / 1000 % 60;
+        let millis = self.millis % 1000;
+        match self.precision {
+            0 => write!(f, "[{:02}:{:02}]", minutes, seconds),
+            1 => write!(f, "[{:02}:{:02}.{}]", minutes, seconds, millis / 100),
+            2 => write!(f, "[{:02}:{:02}.{:02}]", minutes, seconds, millis / 10),
+            _ => write!(f, "[{:02}:{:02}.{:03}]", minutes, seconds, millis),
+        }
+    }
+}
+
+/// How `--normalize-text` writes quotes, apostrophes and dashes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Straight quotes and hyphens, which every player and font can show
+    Ascii,
+    /// Curly quotes and apostrophes, and en dashes between words
+    Curly,
+}
+
+/// Invisible characters some providers leave in lyrics; joiners are kept since some scripts
+/// and emoji need them
+const ZERO_WIDTH: [char; 4] = ['\u{200B}', '\u{2060}', '\u{FEFF}', '\u{180E}'];
+
+/// Composes the text into NFC, drops zero-width characters and writes quotes and dashes in
+/// one style, so lyrics from different sources compare and display alike
+pub fn normalize_text(text: &str, style: QuoteStyle) -> String {
+    let text: String = text.nfc().filter(|c| !ZERO_WIDTH.contains(c)).collect();
+    match style {
+        QuoteStyle::Ascii => text
+            .replace(['‘', '’', '‚', '‛', '′'], "'")
+            .replace(['“', '”', '„', '‟', '″'], "\"")
+            .replace(['‐', '‑', '‒', '–', '—', '―'], "-")
+            .replace('…', "..."),
+        QuoteStyle::Curly => {
+            let mut curly = String::with_capacity(text.len());
+            let mut previous: Option<char> = None;
+            for c in text.chars() {
+                // A quote opens at the start of a word, anything else closes or is an apostrophe
+                let opening = previous.is_none_or(|p| p.is_whitespace() || "([{".contains(p));
+                match c {
+                    '\'' if opening => curly.push('‘'),
+                    '\'' => curly.push('’'),
+                    '"' if opening => curly.push('“'),
+                    '"' => curly.push('”'),
+                    c => curly.push(c),
+                }
+                previous = Some(c);
+            }
+            curly.replace(" - ", " – ").replace(" -- ", " — ")
+        }
+    }
+}
+
+/// One line of an LRC file
+#[derive(Debug, Clone, PartialEq)]
+pub enum LrcLine {
+    /// A metadata tag such as `[ar:Artist]`, or a `[#: ...]` comment
+    Tag { name: String, value: String },
+    /// Lyrics sung at each of its timestamps; a repeated chorus may carry several
+    Lyric {
+        timestamps: Vec<Timestamp>,
+        text: String,
+    },
+    /// Anything else, such as blank lines or plain text, kept as is
+    Other(String),
+}
+
+impl LrcLine {
+    pub fn parse(line: &str) -> Self {
+        let (timestamps, text) = split_timestamps(line);
+        if !timestamps.is_empty() {
+            return LrcLine::Lyric {
+                timestamps,
+                text: text.to_string(),
+            };
+        }
+
+        let trimmed = line.trim();
+        if let Some(inner) = trimmed
+            .strip_prefix('[')
+            .and_then(|inner| inner.strip_suffix(']'))
+            && let Some((name, value)) = inner.split_once(':')
+        {
+            return LrcLine::Tag {
+                name: name.to_string(),
+                value: value.to_string(),
+            };
+        }
+        LrcLine::Other(line.trim_end().to_string())
+    }
+}
+
+impl fmt::Display for LrcLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LrcLine::Tag { name, value } => write!(f, "[{}:{}]", name, value),
+            LrcLine::Lyric { timestamps, text } => {
+                for timestamp in timestamps {
+                    write!(f, "{}", timestamp)?;
+                }
+                write!(f, "{}", text)
+            }
+            LrcLine::Other(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+/// LRC content parsed line by line, so lyrics can be reordered or retimed without losing
+/// the tags and text around them
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Lrc {
+    pub lines: Vec<LrcLine>,
+    /// Whether the content ended with a line break, kept when it's written back
+    trailing_newline: bool,
+}
+
+impl Lrc {
+    pub fn parse(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(LrcLine::parse).collect(),
+            trailing_newline: content.ends_with('\n'),
+        }
+    }
+
+    /// Whether any line carries a timestamp
+    pub fn is_synced(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|line| matches!(line, LrcLine::Lyric { .. }))
+    }
+
+    /// The `[offset:]` tag in milliseconds; a positive offset makes the lyrics appear sooner
+    pub fn offset(&self) -> Option<i64> {
+        self.lines.iter().find_map(|line| match line {
+            LrcLine::Tag { name, value } if name.trim().eq_ignore_ascii_case("offset") => {
+                value.trim().trim_start_matches('+').parse().ok()
+            }
+            _ => None,
+        })
+    }
+
+    /// Sets the `[offset:]` tag, adding it to the header when missing and removing it when zero
+    pub fn set_offset(&mut self, millis: i64) {
+        let is_offset = |line: &LrcLine| matches!(line, LrcLine::Tag { name, .. } if name.trim().eq_ignore_ascii_case("offset"));
+        let position = self.lines.iter().position(is_offset);
+        self.lines.retain(|line| !is_offset(line));
+        if millis == 0 {
+            return;
+        }
+
+        let tag = LrcLine::Tag {
+            name: "offset".to_string(),
+            value: format!("{:+}", millis),
+        };
+        // Right after the last header tag, unless there was an offset tag to take the place of
+        let position = position.unwrap_or_else(|| {
+            let header = self
+                .lines
+                .iter()
+                .position(|line| matches!(line, LrcLine::Lyric { .. }))
+                .unwrap_or(self.lines.len());
+            self.lines[..header]
+                .iter()
+                .rposition(|line| matches!(line, LrcLine::Tag { .. }))
+                .map_or(0, |index| index + 1)
+        });
+        self.lines.insert(position, tag);
+    }
+
+    /// Moves every timestamp later by `millis`, or earlier when negative
+    pub fn shift(&mut self, millis: i64) {
+        for line in &mut self.lines {
+            if let LrcLine::Lyric { timestamps, .. } = line {
+                for timestamp in timestamps {
+                    *timestamp = timestamp.shifted(millis);
+                }
+            }
+        }
+    }
+
+    /// Applies the `[offset:]` tag to the timestamps themselves and removes it, since players
+    /// disagree on whether to honor it; returns whether there was an offset
+    pub fn flatten_offset(&mut self) -> bool {
+        let Some(offset) = self.offset() else {
+            return false;
+        };
+        self.shift(-offset);
+        self.set_offset(0);
+        true
+    }
+
+    /// Puts lyrics lines in time order and merges consecutive lines sharing one timestamp,
+    /// which players show as a single line anyway; returns whether anything changed
+    pub fn repair_timing(&mut self) -> bool {
+        let original = self.lines.clone();
+
+        let slots: Vec<usize> = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(line, LrcLine::Lyric { .. }))
+            .map(|(index, _)| index)
+            .collect();
+        let mut lyrics: Vec<LrcLine> = slots
+            .iter()
+            .map(|&index| self.lines[index].clone())
+            .collect();
+        for line in &mut lyrics {
+            if let LrcLine::Lyric { timestamps, .. } = line {
+                timestamps.sort_by_key(|timestamp| timestamp.millis);
+                timestamps.dedup_by_key(|timestamp| timestamp.millis);
+            }
+        }
+        // Stable, so lines sharing a timestamp keep their order
+        lyrics.sort_by_key(|line| match line {
+            LrcLine::Lyric { timestamps, .. } => timestamps[0].millis,
+            _ => 0,
+        });
+        for (&index, line) in slots.iter().zip(lyrics) {
+            self.lines[index] = line;
+        }
+
+        let mut merged: Vec<LrcLine> = Vec::with_capacity(self.lines.len());
+        for line in std::mem::take(&mut self.lines) {
+            if let (
+                Some(LrcLine::Lyric {
+                    timestamps: previous_timestamps,
+                    text: previous_text,
+                }),
+                LrcLine::Lyric { timestamps, text },
+            ) = (merged.last_mut(), &line)
+                && previous_timestamps.len() == 1
+                && timestamps.len() == 1
+                && previous_timestamps[0].millis == timestamps[0].millis
+            {
+                let text = text.trim();
+                if previous_text.trim().is_empty() {
+                    *previous_text = text.to_string();
+                } else if !text.is_empty() {
+                    previous_text.push(' ');
+                    previous_text.push_str(text);
+                }
+                continue;
+            }
+            merged.push(line);
+        }
+        self.lines = merged;
+
+        self.lines != original
+    }
+
+    /// Adds a `♪` line into every gap between sung lines longer than `min_gap_ms`, so players
+    /// don't keep showing the last line through an instrumental break; returns how many
+    pub fn fill_gaps(&mut self, min_gap_ms: u64) -> usize {
+        let timeline: Vec<(u64, bool)> = self
+            .timeline()
+            .into_iter()
+            .map(|(timestamp, text)| {
+                (
+                    timestamp.millis,
+                    text.trim().is_empty() || text.trim() == GAP_MARKER,
+                )
+            })
+            .collect();
+        let markers: Vec<u64> = timeline
+            .windows(2)
+            .filter(|pair| {
+                let ((start, blank), (end, _)) = (pair[0], pair[1]);
+                // A blank line already clears the screen
+                !blank && end.saturating_sub(start) > min_gap_ms.max(GAP_LINE_HOLD_MS)
+            })
+            .map(|pair| pair[0].0 + GAP_LINE_HOLD_MS)
+            .collect();
+
+        for &millis in &markers {
+            let position = self
+                .lines
+                .iter()
+                .position(|line| matches!(line, LrcLine::Lyric { timestamps, .. } if timestamps[0].millis > millis))
+                .unwrap_or(self.lines.len());
+            self.lines.insert(
+                position,
+                LrcLine::Lyric {
+                    timestamps: vec![Timestamp {
+                        millis,
+                        precision: 2,
+                    }],
+                    text: GAP_MARKER.to_string(),
+                },
+            );
+        }
+        markers.len()
+    }
+
+    /// Drops lyrics lines whose text matches any of the patterns, such as songwriter credits;
+    /// tags are kept. Returns how many lines were removed
+    pub fn remove_matching(&mut self, patterns: &[Regex]) -> usize {
+        let before = self.lines.len();
+        self.lines.retain(|line| {
+            let text = match line {
+                LrcLine::Tag { .. } => return true,
+                LrcLine::Lyric { text, .. } => text,
+                LrcLine::Other(text) => text,
+            };
+            !patterns.iter().any(|pattern| pattern.is_match(text))
+        });
+        before - self.lines.len()
+    }
+
+    /// Drops the lyrics lines matching any of the patterns from the top of the lyrics, up to
+    /// the first line that doesn't, so credits go without touching lines of the song that
+    /// happen to look like them; tags and blank lines are kept. Returns how many lines were
+    /// removed
+    pub fn remove_leading_matching(&mut self, patterns: &[Regex]) -> usize {
+        let before = self.lines.len();
+        let mut in_credits = true;
+        self.lines.retain(|line| {
+            let text = match line {
+                LrcLine::Tag { .. } => return true,
+                LrcLine::Lyric { text, .. } => text,
+                LrcLine::Other(text) => text,
+            };
+            if !in_credits || text.trim().is_empty() {
+                return true;
+            }
+            if patterns.iter().any(|pattern| pattern.is_match(text)) {
+                return false;
+            }
+            in_credits = false;
+            true
+        });
+        before - self.lines.len()
+    }
+
+    /// Breaks plain text lines longer than `max_chars` at word boundaries; with `split_synced`,
+    /// synced lines too, preferably at punctuation, each part keeping the line's timestamps.
+    /// Returns how many lines were broken up
+    pub fn wrap(&mut self, max_chars: usize, split_synced: bool) -> usize {
+        let mut wrapped = 0;
+        let mut lines = Vec::with_capacity(self.lines.len());
+        for line in std::mem::take(&mut self.lines) {
+            match line {
+                LrcLine::Other(text) if text.chars().count() > max_chars => {
+                    wrapped += 1;
+                    lines.extend(
+                        wrap_text(&text, max_chars, false)
+                            .into_iter()
+                            .map(LrcLine::Other),
+                    );
+                }
+                LrcLine::Lyric { timestamps, text }
+                    if split_synced && text.trim().chars().count() > max_chars =>
+                {
+                    wrapped += 1;
+                    lines.extend(
+                        wrap_text(text.trim(), max_chars, true)
+                            .into_iter()
+                            .map(|text| LrcLine::Lyric {
+                                timestamps: timestamps.clone(),
+                                text,
+                            }),
+                    );
+                }
+                line => lines.push(line),
+            }
+        }
+        self.lines = lines;
+        wrapped
+    }
+
+    /// Rewrites the text of every lyrics line, leaving timestamps and tags alone
+    pub fn map_text(&mut self, f: impl Fn(&str) -> String) {
+        for line in &mut self.lines {
+            match line {
+                LrcLine::Lyric { text, .. } | LrcLine::Other(text) => *text = f(text),
+                LrcLine::Tag { .. } => {}
+            }
+        }
+    }
+
+    /// Collapses runs of blank untimed lines into one; blank synced lines are kept since they
+    /// clear the screen at their timestamp
+    pub fn collapse_blank_lines(&mut self) {
+        let is_blank =
+            |line: &LrcLine| matches!(line, LrcLine::Other(text) if text.trim().is_empty());
+        let mut lines: Vec<LrcLine> = Vec::with_capacity(self.lines.len());
+        for line in std::mem::take(&mut self.lines) {
+            if is_blank(&line) && lines.last().is_some_and(is_blank) {
+                continue;
+            }
+            lines.push(line);
+        }
+        self.lines = lines;
+    }
+
+    /// Every sung line with a single timestamp, in the order they are sung
+    pub fn timeline(&self) -> Vec<(Timestamp, &str)> {
+        let mut timeline: Vec<(Timestamp, &str)> = self
+            .lines
+            .iter()
+            .flat_map(|line| match line {
+                LrcLine::Lyric { timestamps, text } => timestamps
+                    .iter()
+                    .map(|timestamp| (*timestamp, text.as_str()))
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        // Stable, so lines sharing a timestamp keep their order
+        timeline.sort_by_key(|(timestamp, _)| *timestamp);
+        timeline
+    }
+
+    /// Splits lines carrying several timestamps into one line per timestamp, for players that
+    /// only read the first; lyrics are reordered by time, tags stay at the top
+    pub fn expanded(&self) -> Self {
+        if !self
+            .lines
+            .iter()
+            .any(|line| matches!(line, LrcLine::Lyric { timestamps, .. } if timestamps.len() > 1))
+        {
+            return self.clone();
+        }
+
+        let first_lyric = self
+            .lines
+            .iter()
+            .position(|line| matches!(line, LrcLine::Lyric { .. }))
+            .unwrap_or(self.lines.len());
+        let mut lines = self.lines[..first_lyric].to_vec();
+        // Blank lines between the lyrics would lose their place once lines are reordered
+        lines.extend(
+            self.lines[first_lyric..]
+                .iter()
+                .filter(|line| match line {
+                    LrcLine::Tag { .. } => true,
+                    LrcLine::Lyric { .. } => false,
+                    LrcLine::Other(line) => !line.trim().is_empty(),
+                })
+                .cloned(),
+        );
+        lines.extend(
+            self.timeline()
+                .into_iter()
+                .map(|(timestamp, text)| LrcLine::Lyric {
+                    timestamps: vec![timestamp],
+                    text: text.to_string(),
+                }),
+        );
+        Self {
+            lines,
+            trailing_newline: self.trailing_newline,
+        }
+    }
+
+    /// The lyrics as plain text in the order they are sung, without timestamps or tags
+    pub fn to_plain_text(&self) -> String {
+        self.expanded()
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                LrcLine::Tag { .. } => None,
+                LrcLine::Lyric { text, .. } => Some(text.trim().to_string()),
+                LrcLine::Other(line) => Some(line.clone()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for Lrc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", line)?;
+        }
+        if self.trailing_newline {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Punctuation a long line can be broken after without splitting a phrase
+const BREAK_PUNCTUATION: [char; 12] = [
+    ',', ';', ':', '.', '!', '?', '，', '；', '：', '。', '！', '？',
+];
+
+/// Splits a line at its enhanced LRC word timings into each word's start and text; empty
+/// when the line has none. Text before the first timing starts with the line
+pub fn word_timings(text: &str) -> Vec<(u64, String)> {
+    let mut words: Vec<(u64, String)> = Vec::new();
+    let mut rest = text;
+    let mut timed = false;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>').map(|close| open + close) else {
+            break;
+        };
+        let Some(timestamp) = Timestamp::parse(&rest[open + 1..close]) else {
+            break;
+        };
+        match words.last_mut() {
+            Some((_, word)) => word.push_str(&rest[..open]),
+            None if !rest[..open].trim().is_empty() => words.push((0, rest[..open].to_string())),
+            None => {}
+        }
+        words.push((timestamp.millis, String::new()));
+        timed = true;
+        rest = &rest[close + 1..];
+    }
+    if !timed {
+        return Vec::new();
+    }
+    if let Some((_, word)) = words.last_mut() {
+        word.push_str(rest);
+    }
+    words.retain(|(_, word)| !word.is_empty());
+    words
+}
+
+/// The line without its enhanced LRC word timings
+pub fn strip_word_timings(text: &str) -> String {
+    let words = word_timings(text);
+    if words.is_empty() {
+        return text.to_string();
+    }
+    words.into_iter().map(|(_, word)| word).collect()
+}
+
+/// Breaks text into parts of at most `max_chars` characters where it can: after punctuation
+/// if `at_punctuation` and that doesn't leave a short fragment, otherwise at the last space
+/// that fits. A single word longer than the limit stays whole
+fn wrap_text(text: &str, max_chars: usize, at_punctuation: bool) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+    while rest.chars().count() > max_chars {
+        // Byte offset just past the last character that still fits
+        let limit = rest
+            .char_indices()
+            .nth(max_chars)
+            .map_or(rest.len(), |(index, _)| index);
+        let head = &rest[..limit];
+        let punctuation = at_punctuation
+            .then(|| {
+                head.char_indices()
+                    .filter(|(_, c)| BREAK_PUNCTUATION.contains(c))
+                    .map(|(index, c)| index + c.len_utf8())
+                    .rfind(|&end| end < rest.len())
+            })
+            .flatten()
+            // Breaking off a fragment much shorter than the line looks worse than a plain wrap
+            .filter(|&end| head[..end].chars().count() >= max_chars / 2);
+        let space = if rest[limit..].starts_with(char::is_whitespace) {
+            Some(limit)
+        } else {
+            head.rfind(char::is_whitespace)
+        };
+        let split = punctuation
+            .or(space)
+            .or_else(|| rest.find(char::is_whitespace))
+            .filter(|&split| split > 0);
+        let Some(split) = split else {
+            break;
+        };
+        parts.push(rest[..split].trim_end().to_string());
+        rest = rest[split..].trim_start();
+    }
+    parts.push(rest.to_string());
+    parts
+}
+
+/// Splits the leading `[mm:ss.xx]` timestamps off a line, returning them with the rest
+fn split_timestamps(line: &str) -> (Vec<Timestamp>, &str) {
+    let mut rest = line.trim_start();
+    let mut timestamps = Vec::new();
+
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(end) = inner.find(']') else {
+            break;
+        };
+        let Some(timestamp) = Timestamp::parse(&inner[..end]) else {
+            break;
+        };
+        timestamps.push(timestamp);
+        rest = &inner[end + 1..];
+    }
+
+    (timestamps, rest)
+}
+
+/// Turns LRC content into plain text, dropping timestamps and metadata tags like `[ar: Artist]`
+pub fn to_plain_text(content: &str) -> String {
+    Lrc::parse(content).to_plain_text()
+}
+
+/// Lines of LRC content that don't read as LRC, each with its line number: brackets that are
+/// neither a tag nor a timestamp, like `[01:2O.00]`, and text without a timestamp among
+/// synced lyrics
+pub fn syntax_errors(content: &str) -> Vec<String> {
+    let lrc = Lrc::parse(content);
+    let synced = lrc.is_synced();
+    lrc.lines
+        .iter()
+        .zip(content.lines())
+        .enumerate()
+        .filter_map(|(index, (line, text))| {
+            let problem = match line {
+                // A timestamp with a typo reads as a tag named by its minutes
+                LrcLine::Tag { name, .. } if name.trim().chars().all(|c| c.is_ascii_digit()) => {
+                    "malformed timestamp"
+                }
+                LrcLine::Other(text) if text.trim_start().starts_with('[') => {
+                    "neither a tag nor a timestamp"
+                }
+                LrcLine::Other(text) if synced && !text.trim().is_empty() => "no timestamp",
+                _ => return None,
+            };
+            Some(format!("line {}: {}: {}", index + 1, problem, text.trim()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lyric(timestamps: &[u64], text: &str) -> LrcLine {
+        LrcLine::Lyric {
+            timestamps: timestamps
+                .iter()
+                .map(|&millis| Timestamp::from_millis(millis))
+                .collect(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_lines_with_several_timestamps() {
+        let content = "[00:10.00][01:20.50]Chorus\n[00:15.00]Verse\n";
+        let lrc = Lrc::parse(content);
+        assert_eq!(lrc.lines[0], lyric(&[10_000, 80_500], "Chorus"));
+
+        let timeline: Vec<(u64, &str)> = lrc
+            .timeline()
+            .into_iter()
+            .map(|(timestamp, text)| (timestamp.millis, text))
+            .collect();
+        assert_eq!(
+            timeline,
+            [(10_000, "Chorus"), (15_000, "Verse"), (80_500, "Chorus")]
+        );
+        assert_eq!(lrc.to_string(), content);
+        assert_eq!(
+            lrc.expanded().to_string(),
+            "[00:10.00]Chorus\n[00:15.00]Verse\n[01:20.50]Chorus\n"
+        );
+    }
+
+    #[test]
+    fn reads_and_flattens_the_offset_tag() {
+        let mut lrc = Lrc::parse("[ar: Band]\n[offset:+500]\n[00:10.00]Line");
+        assert_eq!(lrc.offset(), Some(500));
+        assert!(lrc.flatten_offset());
+        assert_eq!(lrc.offset(), None);
+        assert_eq!(lrc.to_string(), "[ar: Band]\n[00:09.50]Line");
+
+        let lrc = Lrc::parse("[offset: -250]\n[00:10.00]Line");
+        assert_eq!(lrc.offset(), Some(-250));
+    }
+
+    #[test]
+    fn reports_malformed_tags() {
+        let content = "[ar: Band]\n[00:01.00]Fine\n[01:2O.00]\n[00:1O.00]Typo\nNo time";
+        assert_eq!(
+            syntax_errors(content),
+            [
+                "line 3: malformed timestamp: [01:2O.00]",
+                "line 4: neither a tag nor a timestamp: [00:1O.00]Typo",
+                "line 5: no timestamp: No time",
+            ]
+        );
+
+        assert_eq!(Timestamp::parse("1:2x"), None);
+        assert_eq!(Timestamp::parse(":05.00"), None);
+        assert_eq!(Timestamp::parse("00:05.1234"), None);
+        // The unparsed bracket stays part of the text rather than being dropped
+        assert_eq!(
+            LrcLine::parse("[00:01.00][bad]Text"),
+            lyric(&[1_000], "[bad]Text")
+        );
+    }
+
+    #[test]
+    fn parses_crlf_input() {
+        let lrc = Lrc::parse("[ti: Song]\r\n[00:01.00]Hello\r\n[00:02.5]World\r\n");
+        assert_eq!(
+            lrc.lines[0],
+            LrcLine::Tag {
+                name: "ti".to_string(),
+                value: " Song".to_string()
+            }
+        );
+        assert_eq!(lrc.lines[1], lyric(&[1_000], "Hello"));
+        assert_eq!(lrc.to_plain_text(), "Hello\nWorld");
+        assert_eq!(
+            lrc.to_string(),
+            "[ti: Song]\n[00:01.00]Hello\n[00:02.5]World\n"
+        );
+        assert!(syntax_errors("[00:01.00]Hello\r\n[00:02.00]World\r\n").is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::Result;
+use crate::state::modified_secs;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Plain text of a track's lyrics as `lrcphile grep` last read them, so unchanged files
+/// aren't parsed again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexedLyrics {
+    /// File the lyrics were read from: a lyrics file, or the audio file for embedded lyrics
+    source: PathBuf,
+    /// Modification time of the source when it was read
+    mtime: u64,
+    text: String,
+}
+
+/// Lyrics text searched by `lrcphile grep`, by audio file. Kept in the cache directory rather
+/// than the state file, since it grows with the library and can always be rebuilt
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LyricsIndex {
+    #[serde(default)]
+    tracks: HashMap<String, IndexedLyrics>,
+    #[serde(skip)]
+    location: Option<PathBuf>,
+}
+
+impl LyricsIndex {
+    /// `lrcphile/lyrics-index.json` under the user cache directory (`$XDG_CACHE_HOME` on Linux)
+    pub fn default_location() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "lrcphile").map(|dirs| dirs.cache_dir().join("lyrics-index.json"))
+    }
+
+    /// Loads the index, starting empty when it doesn't exist or can't be read, since every
+    /// entry can be read again from the library
+    pub fn load(location: &Path) -> Self {
+        let index = fs::File::open(location)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default();
+        Self {
+            location: Some(location.to_path_buf()),
+            ..index
+        }
+    }
+
+    /// Writes the index back to where it was loaded from
+    pub fn save(&self) -> Result<()> {
+        let Some(location) = &self.location else {
+            return Ok(());
+        };
+
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_location = location.with_extension("json.tmp");
+        let mut writer = BufWriter::new(fs::File::create(&temp_location)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&temp_location, location)?;
+        Ok(())
+    }
+
+    /// Indexed lyrics of the track, if they were read from this source and it hasn't been
+    /// modified since
+    pub fn get(&self, audio_path: &Path, source: &Path) -> Option<&str> {
+        let indexed = self.tracks.get(&index_key(audio_path))?;
+        (indexed.source == source && Some(indexed.mtime) == modified_secs(source))
+            .then_some(indexed.text.as_str())
+    }
+
+    pub fn insert(&mut self, audio_path: &Path, source: &Path, text: String) {
+        let Some(mtime) = modified_secs(source) else {
+            return;
+        };
+        self.tracks.insert(
+            index_key(audio_path),
+            IndexedLyrics {
+                source: source.to_path_buf(),
+                mtime,
+                text,
+            },
+        );
+    }
+}
+
+fn index_key(audio_path: &Path) -> String {
+    std::path::absolute(audio_path)
+        .unwrap_or_else(|_| audio_path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
@@ -1,474 +1,5540 @@
-use clap::Parser;
+mod ass;
+mod cache;
+mod commands;
+mod config;
+mod custom_provider;
+mod editor;
+mod error;
+mod lock;
+mod lrc;
+mod lyrics_index;
+mod manifest;
+mod picker;
+mod progress;
+mod prompt;
+mod provider;
+mod serve;
+mod srt;
+mod state;
+mod trace;
+
+use ass::{AssStyle, SubtitlePosition};
+use cache::ResponseCache;
+use chardetng::EncodingDetector;
+use chrono::{DateTime, Local, NaiveDate};
+use clap::{
+    ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    parser::ValueSource,
+};
 use colored::Colorize;
-use directories::UserDirs;
+use config::{Config, Profile};
+use custom_provider::CustomProvider;
+use directories::{ProjectDirs, UserDirs};
+use encoding_rs::{Encoding, UTF_8};
+use error::{LrcphileError, Result};
+use futures::future::{self, BoxFuture};
 use futures::stream::{self, StreamExt};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use indicatif::{ProgressBar, ProgressStyle};
-use lofty::{file::AudioFile, prelude::TaggedFileExt, probe::Probe, tag::Accessor};
-use serde::Deserialize;
-use std::{fs, path::PathBuf, sync::Arc};
-use tokio::sync::Mutex;
+use lock::LibraryLock;
+use lofty::{
+    config::{ParseOptions, WriteOptions},
+    file::{AudioFile, FileType},
+    id3::v2::FrameId,
+    mpeg::MpegFile,
+    prelude::TaggedFileExt,
+    probe::Probe,
+    tag::{Accessor, ItemKey, TagExt, TagType},
+};
+use lrc::{Lrc, QuoteStyle};
+use manifest::Manifest;
+use progress::{ProgressCounts, ProgressEvent, ProgressEvents, ProgressTarget};
+use provider::{
+    CacheValidators, ClientOptions, CountedProvider, IpVersion, LRCLIB_PROVIDER, LookupStats,
+    Lrclib, LyricsProvider, LyricsResponse, MatchKind, ProviderChain,
+};
+use rand::seq::SliceRandom;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use state::{LyricsContent, QuarantinedMatch, StateDb};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::IsTerminal,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, mpsc::UnboundedSender},
+    time::Instant,
+};
 
 #[derive(Parser, Clone)]
 #[command(name = "lrcphile")]
 #[command(about = "CLI liblrc Client")]
 #[command(version = "0.1.0")]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the audio file or directory (defaults to music directory)
     #[arg(help = "Path to the audio file or directory (defaults to music directory)")]
     path: Option<PathBuf>,
 
     /// Automatically override existing lyrics files without prompting
-    #[arg(short, long = "override", help = "Override existing lyrics files")]
+    #[arg(
+        short,
+        long = "override",
+        env = "LRCPHILE_OVERRIDE",
+        help = "Override existing lyrics files"
+    )]
     override_files: bool,
 
+    /// Never override lyrics files that lrcphile didn't write, even with --override
+    #[arg(
+        long,
+        env = "LRCPHILE_PRESERVE_MANUAL",
+        help = "Keep hand-made lyrics files even when overriding"
+    )]
+    preserve_manual: bool,
+
+    /// Override hand-made lyrics files despite --preserve-manual
+    #[arg(
+        long,
+        requires = "override_files",
+        env = "LRCPHILE_FORCE",
+        help = "Override hand-made lyrics files too (with --override)"
+    )]
+    force: bool,
+
+    /// Ask which record to use when a search turns up several plausible matches
+    #[arg(
+        short,
+        long,
+        env = "LRCPHILE_INTERACTIVE",
+        help = "Choose between matches interactively when a search finds several"
+    )]
+    interactive: bool,
+
+    /// Fall back to LRCLIB's search when a track has no exact match
+    #[arg(
+        long,
+        env = "LRCPHILE_SEARCH_FALLBACK",
+        help = "Search for the track when there is no exact match (implied by --interactive)"
+    )]
+    search_fallback: bool,
+
+    /// Title to look the track up by instead of its tag (single file only)
+    #[arg(
+        long,
+        value_name = "TITLE",
+        env = "LRCPHILE_SET_TITLE",
+        help = "Look up a single file by this title instead of its tag"
+    )]
+    set_title: Option<String>,
+
+    /// Artist to look the track up by instead of its tag (single file only)
+    #[arg(
+        long,
+        value_name = "ARTIST",
+        env = "LRCPHILE_SET_ARTIST",
+        help = "Look up a single file by this artist instead of its tag"
+    )]
+    set_artist: Option<String>,
+
+    /// Album to look the track up by instead of its tag (single file only)
+    #[arg(
+        long,
+        value_name = "ALBUM",
+        env = "LRCPHILE_SET_ALBUM",
+        help = "Look up a single file by this album instead of its tag"
+    )]
+    set_album: Option<String>,
+
+    /// Fall back to a media server's `.nfo` or `.json` sidecar for title, artist and album
+    /// missing from the tags
+    #[arg(
+        long,
+        env = "LRCPHILE_SIDECAR_METADATA",
+        help = "Read missing title, artist and album from .nfo/.json files next to the audio"
+    )]
+    sidecar_metadata: bool,
+
+    /// Duration to look the track up by instead of the file's own (single file only)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "LRCPHILE_SET_DURATION",
+        help = "Look up a single file by this duration in seconds instead of its own"
+    )]
+    set_duration: Option<f64>,
+
+    /// Open each track's lyrics file in `$EDITOR` after fetching, or when it already exists,
+    /// checking its LRC syntax before it's kept
+    #[arg(
+        long,
+        env = "LRCPHILE_EDIT",
+        help = "Edit lyrics files in $EDITOR after fetching, checking their syntax"
+    )]
+    edit: bool,
+
+    /// Show the first lines of fetched lyrics and ask before writing them
+    #[arg(
+        long,
+        env = "LRCPHILE_PREVIEW",
+        help = "Preview fetched lyrics and confirm before saving them"
+    )]
+    preview: bool,
+
+    /// How many lines of lyrics, including the header, `--preview` shows
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 15,
+        requires = "preview",
+        env = "LRCPHILE_PREVIEW_LINES",
+        help = "Number of lines shown by --preview"
+    )]
+    preview_lines: usize,
+
     /// Recursively process subdirectories
-    #[arg(short, long, help = "Recursively process subdirectories")]
+    #[arg(
+        short,
+        long,
+        env = "LRCPHILE_RECURSIVE",
+        help = "Recursively process subdirectories"
+    )]
     recursive: bool,
 
     /// URL for lyrics database instance
     #[arg(
         short,
         long,
+        global = true,
         default_value = "https://lrclib.net",
+        env = "LRCPHILE_URL",
         help = "URL for the lyrics database instance (e.g., self-hosted LRCLIB)"
     )]
     url: String,
-}
 
-#[derive(Deserialize, Debug)]
-#[allow(dead_code)]
-struct LyricsResponse {
-    id: u64,
-    #[serde(rename = "trackName")]
-    track_name: String,
-    #[serde(rename = "artistName")]
-    artist_name: String,
-    #[serde(rename = "albumName")]
-    album_name: String,
-    duration: f64,
-    instrumental: bool,
-    #[serde(rename = "plainLyrics")]
-    plain_lyrics: Option<String>,
-    #[serde(rename = "syncedLyrics")]
-    synced_lyrics: Option<String>,
+    /// Lyrics sources asked in turn for each track: `lrclib` for the instance at `--url`, or the
+    /// name of a `[[provider]]` table in the config file
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        default_value = "lrclib",
+        env = "LRCPHILE_PROVIDERS",
+        help = "Comma-separated lyrics providers to try in order (lrclib or config [[provider]] names)"
+    )]
+    providers: Vec<String>,
+
+    /// Address to connect to for a host name instead of asking DNS, e.g. for split-horizon setups
+    #[arg(
+        long,
+        global = true,
+        value_name = "HOST:IP",
+        value_parser = parse_resolve,
+        value_delimiter = ',',
+        env = "LRCPHILE_RESOLVE",
+        help = "Connect to IP for HOST instead of resolving it (repeatable)"
+    )]
+    resolve: Vec<(String, IpAddr)>,
+
+    /// Only connect to the instance over IPv4
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "ipv6",
+        env = "LRCPHILE_IPV4",
+        help = "Only connect over IPv4"
+    )]
+    ipv4: bool,
+
+    /// Only connect to the instance over IPv6
+    #[arg(
+        long,
+        global = true,
+        env = "LRCPHILE_IPV6",
+        help = "Only connect over IPv6"
+    )]
+    ipv6: bool,
+
+    /// Write lyrics files under this directory, mirroring the library's layout, instead of
+    /// beside the audio files
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        env = "LRCPHILE_OUTPUT_DIR",
+        help = "Write lyrics files to DIR instead of beside the audio files"
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// Take over the library's lock file even when another run may still hold it, after a
+    /// run crashed on another machine
+    #[arg(
+        long,
+        env = "LRCPHILE_BREAK_LOCK",
+        help = "Remove the library's lock file left by another run before starting"
+    )]
+    break_lock: bool,
+
+    /// Print how long each file's tag reading, lookup and writing took, and percentiles of
+    /// each in the summary
+    #[arg(
+        short,
+        long,
+        env = "LRCPHILE_VERBOSE",
+        help = "Print per-file timings and timing percentiles in the summary"
+    )]
+    verbose: bool,
+
+    /// How a directory run is reported at its end; `table` also holds back per-file warnings
+    /// and failures to show them in the table
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = SummaryFormat::Text,
+        env = "LRCPHILE_SUMMARY",
+        help = "End-of-run summary: totals only (text) or an aligned table of every track (table)"
+    )]
+    summary: SummaryFormat,
+
+    /// Append every request sent to the instance to this file, with its status and timing
+    #[arg(
+        long,
+        value_name = "FILE",
+        global = true,
+        env = "LRCPHILE_TRACE_HTTP",
+        help = "Log each request's URL, status and timing to FILE, with secrets redacted"
+    )]
+    trace_http: Option<PathBuf>,
+
+    /// Don't answer lookups from the response cache or add to it
+    #[arg(
+        long,
+        global = true,
+        env = "LRCPHILE_NO_CACHE",
+        help = "Bypass the response cache"
+    )]
+    no_cache: bool,
+
+    /// Size the response cache is kept under, least recently used entries going first
+    #[arg(
+        long,
+        global = true,
+        value_name = "MB",
+        default_value_t = 100,
+        env = "LRCPHILE_CACHE_SIZE",
+        help = "Maximum size of the response cache in megabytes"
+    )]
+    cache_size: u64,
+
+    /// Pick up symlinked audio files and descend into symlinked directories, which are
+    /// skipped by default
+    #[arg(
+        long,
+        env = "LRCPHILE_FOLLOW_SYMLINKS",
+        help = "Follow symbolic links to audio files and directories (skipped by default)"
+    )]
+    follow_symlinks: bool,
+
+    /// Refuse to write lyrics files whose real location, symlinks resolved, is outside the
+    /// library or `--output-dir`
+    #[arg(
+        long,
+        env = "LRCPHILE_NO_WRITE_OUTSIDE_ROOT",
+        help = "Never write lyrics files that resolve to a location outside the library"
+    )]
+    no_write_outside_root: bool,
+
+    /// Maximum number of subdirectory levels to descend into
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "recursive",
+        env = "LRCPHILE_MAX_DEPTH",
+        help = "Limit recursion to N levels of subdirectories"
+    )]
+    max_depth: Option<usize>,
+
+    /// Only process tracks whose tags match (e.g. genre=Rock, artist~=Beatles)
+    #[arg(
+        long = "filter",
+        value_name = "TAG=VALUE",
+        value_parser = parse_tag_filter,
+        env = "LRCPHILE_FILTERS",
+        help = "Only process tracks matching a tag filter (TAG=VALUE exact, TAG~=VALUE contains)"
+    )]
+    filters: Vec<TagFilter>,
+
+    /// Skip tracks shorter than this many seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "LRCPHILE_MIN_DURATION",
+        help = "Skip tracks shorter than SECONDS"
+    )]
+    min_duration: Option<f64>,
+
+    /// Skip tracks longer than this many seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "LRCPHILE_MAX_DURATION",
+        help = "Skip tracks longer than SECONDS"
+    )]
+    max_duration: Option<f64>,
+
+    /// Only process files modified on or after this date
+    #[arg(
+        long,
+        value_name = "YYYY-MM-DD",
+        value_parser = parse_date,
+        env = "LRCPHILE_NEWER_THAN",
+        help = "Only process audio files modified on or after the given date"
+    )]
+    newer_than: Option<NaiveDate>,
+
+    /// Skip files that haven't changed since the last run, according to the state file
+    #[arg(
+        long,
+        env = "LRCPHILE_CHANGED_ONLY",
+        help = "Only process audio files changed since they were last processed"
+    )]
+    changed_only: bool,
+
+    /// Only look up lyrics LRCLIB already has, without it querying external sources
+    #[arg(
+        long,
+        env = "LRCPHILE_CACHED_ONLY",
+        help = "Only query lyrics already stored on the instance (/api/get-cached)"
+    )]
+    cached_only: bool,
+
+    /// Named profile from the config file supplying defaults for this run
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        env = "LRCPHILE_PROFILE",
+        help = "Use defaults from a [profile.NAME] section of the config file"
+    )]
+    profile: Option<String>,
+
+    /// Location of the state file (defaults to the user data directory)
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        env = "LRCPHILE_STATE_FILE",
+        help = "Path to the state file"
+    )]
+    state_file: Option<PathBuf>,
+
+    /// Order in which files found in a directory are processed
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ProcessingOrder::Path,
+        env = "LRCPHILE_ORDER",
+        help = "Order in which to process files"
+    )]
+    order: ProcessingOrder,
+
+    /// How many files are looked up at the same time
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        env = "LRCPHILE_CONCURRENCY",
+        help = "Number of files to look up at the same time (default: 4, 2 on lrclib.net)"
+    )]
+    concurrency: Option<u32>,
+
+    /// Most requests sent to the instance per second; lookups answered from the cache don't count
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = parse_rate_limit,
+        env = "LRCPHILE_RATE_LIMIT",
+        help = "Send at most N requests per second to the instance"
+    )]
+    rate_limit: Option<f64>,
+
+    /// Allow more concurrency and a higher rate than the public instance's limits
+    #[arg(
+        long,
+        env = "LRCPHILE_EXCEED_PUBLIC_LIMITS",
+        help = "Exceed the concurrency and rate limits applied to lrclib.net"
+    )]
+    exceed_public_limits: bool,
+
+    /// Stop starting new lookups once this many requests were sent over the network; answers
+    /// from the response cache don't count
+    #[arg(
+        long,
+        value_name = "N",
+        env = "LRCPHILE_MAX_REQUESTS",
+        help = "Stop after sending N requests to the instance"
+    )]
+    max_requests: Option<u64>,
+
+    /// Stop starting new lookups once the run has taken this long
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_runtime,
+        env = "LRCPHILE_MAX_RUNTIME",
+        help = "Stop after running for DURATION (e.g. 45s, 30m, 1h30m)"
+    )]
+    max_runtime: Option<Duration>,
+
+    /// Stop starting new lookups once the process has grown by this much memory
+    #[arg(
+        long,
+        value_name = "MB",
+        env = "LRCPHILE_MAX_MEMORY",
+        help = "Stop once lrcphile's memory has grown by MB megabytes since it started (Linux only)"
+    )]
+    max_memory: Option<u64>,
+
+    /// Hold search matches rated below this confidence for review instead of writing them
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        env = "LRCPHILE_MIN_CONFIDENCE",
+        help = "Hold matches with a confidence below N (0-100) for review"
+    )]
+    min_confidence: Option<u8>,
+
+    /// CSV file listing each processed track with its outcome and match confidence
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "LRCPHILE_REPORT",
+        help = "Write a CSV report of the run's tracks, outcomes and match confidence to FILE"
+    )]
+    report: Option<PathBuf>,
+
+    /// Side channel for programs wrapping lrcphile: a JSON line when the run starts, as each
+    /// file starts and finishes, and when the run ends
+    #[arg(
+        long,
+        value_name = "TARGET",
+        value_parser = progress::parse_target,
+        env = "LRCPHILE_PROGRESS_JSON",
+        help = "Write JSONL progress events to fd:N, unix:SOCKET or a file"
+    )]
+    progress_json: Option<ProgressTarget>,
+
+    /// Answer JSON-RPC requests on stdin and stdout instead of processing a path, for editor
+    /// plugins and GUIs running lrcphile as a child process
+    #[arg(
+        long,
+        conflicts_with_all = ["path", "interactive", "preview", "edit"],
+        env = "LRCPHILE_SERVE_STDIO",
+        help = "Serve JSON-RPC fetch and lookup requests on stdin/stdout"
+    )]
+    serve_stdio: bool,
+
+    /// JSON file listing the library's lyrics files with a hash of each, for `lrcphile check`
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "LRCPHILE_MANIFEST",
+        help = "Write a manifest of the library's lyrics files and their hashes to FILE"
+    )]
+    manifest: Option<PathBuf>,
+
+    /// Only process tracks whose existing lyrics were fetched from this provider
+    #[arg(
+        long,
+        value_name = "PROVIDER",
+        env = "LRCPHILE_ONLY_FROM",
+        help = "Only process tracks whose lyrics files came from PROVIDER (e.g. lrclib)"
+    )]
+    only_from: Option<String>,
+
+    /// Override existing lyrics, but only those fetched from this provider
+    #[arg(
+        long,
+        value_name = "PROVIDER",
+        env = "LRCPHILE_REFETCH_FROM",
+        help = "Re-fetch lyrics files that came from PROVIDER, keeping all others"
+    )]
+    refetch_from: Option<String>,
+
+    /// What to write for tracks LRCLIB marks as instrumental
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InstrumentalPlaceholder::Lrc,
+        env = "LRCPHILE_INSTRUMENTAL_PLACEHOLDER",
+        help = "Placeholder written for instrumental tracks"
+    )]
+    instrumental_placeholder: InstrumentalPlaceholder,
+
+    /// Which kind of lyrics to write when a record has both, or to insist on
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = LyricsType::Any,
+        env = "LRCPHILE_LYRICS_TYPE",
+        help = "Kind of lyrics to write: synced when available, only synced, or only plain"
+    )]
+    lyrics_type: LyricsType,
+
+    /// Look instrumental tracks up again once their placeholder is this many days old
+    #[arg(
+        long,
+        value_name = "DAYS",
+        env = "LRCPHILE_INSTRUMENTAL_EXPIRY",
+        help = "Re-check instrumental tracks whose placeholder is older than DAYS"
+    )]
+    instrumental_expiry: Option<u64>,
+
+    /// Remember tracks without lyrics in the state file, and only look them up again once
+    /// that is this many days old
+    #[arg(
+        long,
+        value_name = "DAYS",
+        env = "LRCPHILE_MISS_EXPIRY",
+        help = "Don't look up tracks without lyrics again until DAYS after the last try"
+    )]
+    miss_expiry: Option<u64>,
+
+    /// Rewrite existing lyrics files in legacy encodings as UTF-8
+    #[arg(
+        long,
+        env = "LRCPHILE_NORMALIZE_ENCODING",
+        help = "Re-encode existing lyrics files as UTF-8"
+    )]
+    normalize_encoding: bool,
+
+    /// Prefix written lyrics files with a UTF-8 byte order mark
+    #[arg(
+        long,
+        env = "LRCPHILE_BOM",
+        help = "Write lyrics files with a UTF-8 byte order mark"
+    )]
+    bom: bool,
+
+    /// Use Windows line endings in written lyrics files
+    #[arg(
+        long,
+        env = "LRCPHILE_CRLF",
+        help = "Write lyrics files with CRLF line endings"
+    )]
+    crlf: bool,
+
+    /// Extension of synced lyrics files, e.g. `elrc` for players that want enhanced LRC marked
+    #[arg(
+        long,
+        value_name = "EXT",
+        default_value = "lrc",
+        value_parser = parse_extension,
+        env = "LRCPHILE_LRC_EXTENSION",
+        help = "Extension of synced lyrics files (e.g. elrc)"
+    )]
+    lrc_extension: String,
+
+    /// Extension of plain lyrics files
+    #[arg(
+        long,
+        value_name = "EXT",
+        default_value = "txt",
+        value_parser = parse_extension,
+        env = "LRCPHILE_TXT_EXTENSION",
+        help = "Extension of plain lyrics files"
+    )]
+    txt_extension: String,
+
+    /// Apply `[offset:]` tags to the timestamps of written lyrics, for players that ignore them
+    #[arg(
+        long,
+        env = "LRCPHILE_FLATTEN_OFFSET",
+        help = "Fold [offset:] tags into the timestamps of written lyrics"
+    )]
+    flatten_offset: bool,
+
+    /// NFC-normalize written lyrics, drop zero-width characters, unify quotes and dashes, and
+    /// collapse repeated blank lines
+    #[arg(
+        long,
+        env = "LRCPHILE_NORMALIZE_TEXT",
+        help = "Normalize Unicode, quotes, dashes and blank lines in written lyrics"
+    )]
+    normalize_text: bool,
+
+    /// Quotes and dashes --normalize-text writes
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = QuoteStyle::Ascii,
+        env = "LRCPHILE_QUOTE_STYLE",
+        help = "Quote and dash style of --normalize-text"
+    )]
+    quote_style: QuoteStyle,
+
+    /// Remove credit lines like "Lyrics by X" or "作词 : Y" before saving lyrics
+    #[arg(
+        long,
+        env = "LRCPHILE_CLEAN_LYRICS",
+        help = "Remove songwriter credits and similar lines from written lyrics"
+    )]
+    clean_lyrics: bool,
+
+    /// Font of `lrcphile subtitle` files
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "Arial",
+        env = "LRCPHILE_ASS_FONT",
+        help = "Font of ASS subtitles"
+    )]
+    ass_font: String,
+
+    /// Font size of `lrcphile subtitle` files, on a 1080-line canvas
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 64,
+        env = "LRCPHILE_ASS_FONT_SIZE",
+        help = "Font size of ASS subtitles"
+    )]
+    ass_font_size: u32,
+
+    /// Text color of `lrcphile subtitle` files, and of sung words with karaoke effects
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "#FFFFFF",
+        value_parser = ass::parse_color,
+        env = "LRCPHILE_ASS_COLOR",
+        help = "Text color of ASS subtitles (#RRGGBB or #RRGGBBAA)"
+    )]
+    ass_color: String,
+
+    /// Color of the words not sung yet with karaoke effects
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "#A0A0A0",
+        value_parser = ass::parse_color,
+        env = "LRCPHILE_ASS_KARAOKE_COLOR",
+        help = "Color of words not sung yet with --ass-karaoke"
+    )]
+    ass_karaoke_color: String,
+
+    /// Outline color of `lrcphile subtitle` files
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "#000000",
+        value_parser = ass::parse_color,
+        env = "LRCPHILE_ASS_OUTLINE_COLOR",
+        help = "Outline color of ASS subtitles"
+    )]
+    ass_outline_color: String,
+
+    /// Where `lrcphile subtitle` places the lyrics
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SubtitlePosition::Bottom,
+        env = "LRCPHILE_ASS_POSITION",
+        help = "Position of ASS subtitles on screen"
+    )]
+    ass_position: SubtitlePosition,
+
+    /// Sweep a highlight over each word as it is sung (`\kf` tags), using enhanced LRC word
+    /// timings when the lyrics have them and the whole line otherwise
+    #[arg(
+        long,
+        env = "LRCPHILE_ASS_KARAOKE",
+        help = "Add karaoke highlighting to ASS subtitles"
+    )]
+    ass_karaoke: bool,
+
+    /// Command that receives each lyrics file before it is written and prints what to write
+    /// instead, or exits with an error to keep the file from being written
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        env = "LRCPHILE_OUTPUT_FILTER",
+        help = "Pipe lyrics through COMMAND before writing them; a non-zero exit skips the file"
+    )]
+    output_filter: Option<String>,
+
+    /// Next to each lyrics file, write a copy with the words of `censor_words` masked, for
+    /// shared and family libraries or in-store playback
+    #[arg(
+        long,
+        env = "LRCPHILE_CENSORED_COPY",
+        help = "Also write a censored NAME.censored.lrc copy of written lyrics"
+    )]
+    censored_copy: bool,
+
+    /// Re-wrap plain lyrics lines longer than this many characters, for head units and players
+    /// that cut long lines off
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(10..),
+        env = "LRCPHILE_MAX_LINE_LENGTH",
+        help = "Wrap plain lyrics lines longer than N characters"
+    )]
+    max_line_length: Option<u64>,
+
+    /// With --max-line-length, also split long synced lines, each part keeping the timestamp
+    #[arg(
+        long,
+        requires = "max_line_length",
+        env = "LRCPHILE_SPLIT_SYNCED",
+        help = "Split long synced lines at punctuation too (with --max-line-length)"
+    )]
+    split_synced: bool,
+
+    /// Sort out-of-order timestamps and merge consecutive lines sharing a timestamp
+    #[arg(
+        long,
+        env = "LRCPHILE_REPAIR_TIMING",
+        help = "Sort out-of-order lines and merge duplicate timestamps in written lyrics"
+    )]
+    repair_timing: bool,
+
+    /// With --repair-timing, mark instrumental gaps longer than this with a `♪` line
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        requires = "repair_timing",
+        env = "LRCPHILE_GAP_MARKER",
+        help = "Insert a ♪ line into gaps longer than SECONDS (with --repair-timing)"
+    )]
+    gap_marker: Option<u64>,
+
+    /// Write lines like `[01:10.00][02:30.00]chorus` as one line per timestamp
+    #[arg(
+        long,
+        env = "LRCPHILE_EXPAND_TIMESTAMPS",
+        help = "Split lines with several timestamps into one line per timestamp"
+    )]
+    expand_timestamps: bool,
+
+    /// Give written lyrics files the modification time of their audio file, so media server
+    /// watchers and rsync backups don't see them as new
+    #[arg(
+        long,
+        env = "LRCPHILE_MATCH_MTIME",
+        help = "Set lyrics files' modification time to their audio file's"
+    )]
+    match_mtime: bool,
+
+    /// Permissions of written lyrics files, in octal (Unix only)
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = parse_file_mode,
+        env = "LRCPHILE_FILE_MODE",
+        help = "Permissions of written lyrics files in octal, e.g. 644 (Unix only)"
+    )]
+    file_mode: Option<u32>,
+
+    /// Hand written lyrics files to the owner and group of their audio file (Unix only)
+    #[arg(
+        long,
+        env = "LRCPHILE_COPY_OWNER",
+        help = "Give lyrics files the owner and group of their audio file; needs root (Unix only)"
+    )]
+    copy_owner: bool,
+
+    /// Write the title, artist and album of a search match back into the audio file's tags
+    #[arg(
+        long,
+        env = "LRCPHILE_FIX_TAGS",
+        help = "Correct the audio tags from the record a search matched"
+    )]
+    fix_tags: bool,
+
+    /// Library root and the directory its lyrics files are redirected to, set by
+    /// `--output-dir` or when the library turns out to be read-only
+    #[arg(skip)]
+    lyrics_redirect: Option<(Option<PathBuf>, PathBuf)>,
+
+    /// Real location lyrics files must stay under, set by `--no-write-outside-root`
+    #[arg(skip)]
+    write_root: Option<PathBuf>,
+
+    /// Whole-word, case-insensitive pattern of the words `--censored-copy` masks
+    #[arg(skip)]
+    censor_pattern: Option<Regex>,
+
+    /// The `[[provider]]` tables of the config file, which `--providers` picks from
+    #[arg(skip)]
+    custom_providers: Vec<config::ProviderSpec>,
+
+    /// Requests sent to the network so far, counted by the transports for `--max-requests`;
+    /// clones of the options share it
+    #[arg(skip)]
+    network_requests: Arc<AtomicU64>,
+
+    /// How each provider answered this run's lookups, saved to the state file at the end
+    #[arg(skip)]
+    lookup_stats: LookupStats,
+
+    /// Compiled patterns of `--clean-lyrics`
+    #[arg(skip)]
+    cleanup_patterns: Vec<Regex>,
+
+    /// Whether the cleanup patterns are the built-in ones, which only remove the credits at
+    /// the top of the lyrics
+    #[arg(skip)]
+    cleanup_leading_only: bool,
+}
+
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Report a track's lyrics as incorrect to the LRCLIB instance
+    Flag {
+        /// Audio file, or its lyrics file, whose LRCLIB lyrics are wrong
+        #[arg(help = "Audio or lyrics file whose lyrics are wrong")]
+        path: PathBuf,
+
+        /// Explanation sent along with the report
+        #[arg(
+            long,
+            default_value = "Incorrect lyrics",
+            help = "Explanation sent with the report"
+        )]
+        reason: String,
+    },
+
+    /// Browse a library full-screen and fetch, preview, or delete lyrics per track or album
+    Tui {
+        /// Library directory to browse
+        #[arg(help = "Library directory to browse")]
+        dir: PathBuf,
+    },
+
+    /// Print a track's lyrics through a pager
+    Show {
+        /// Audio file whose lyrics to show
+        #[arg(help = "Audio file whose lyrics to show")]
+        path: PathBuf,
+
+        /// Print synced lyrics as plain text
+        #[arg(long, help = "Remove timestamps and LRC tags from synced lyrics")]
+        strip_timestamps: bool,
+
+        /// Write straight to stdout even when it is a terminal
+        #[arg(long, help = "Don't pipe the lyrics through a pager")]
+        no_pager: bool,
+    },
+
+    /// Fetch lyrics for files just imported by Lidarr, run as an "On Import" custom script
+    ImportEvent {
+        /// Imported files, read from Lidarr's environment variables when not given
+        #[arg(help = "Imported audio files (defaults to the files in Lidarr's import event)")]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Print a man page generated from these command line definitions
+    Man,
+
+    /// Fetch lyrics for a beets library, using beets' metadata instead of file tags
+    Beets {
+        /// `beet ls -f` output with tab-separated $path, $title, $artist, $album and $length,
+        /// optionally followed by $disc and $track
+        #[arg(
+            value_name = "LISTING",
+            help = "File with tab-separated `beet ls -f` output ($path, $title, $artist, $album, $length[, $disc, $track]), or - for stdin"
+        )]
+        listing: PathBuf,
+    },
+
+    /// Remove files written by lrcphile from a library
+    #[command(group(ArgGroup::new("action").required(true).multiple(true).args(["instrumentals", "embedded"])))]
+    Clean {
+        /// Library directory to clean
+        #[arg(help = "Library directory to clean")]
+        dir: PathBuf,
+
+        /// Remove instrumental placeholders of either style
+        #[arg(long, help = "Remove instrumental placeholder files")]
+        instrumentals: bool,
+
+        /// Remove lyrics stored in the audio files' tags, for libraries that keep lyrics in
+        /// sidecar files only
+        #[arg(long, help = "Remove lyrics embedded in audio files' tags")]
+        embedded: bool,
+
+        /// Leave embedded lyrics alone in tracks that don't have a lyrics file to replace them
+        #[arg(
+            long,
+            requires = "embedded",
+            help = "Only remove embedded lyrics from tracks with an LRC or TXT file"
+        )]
+        only_with_sidecar: bool,
+    },
+
+    /// Verify the lyrics files recorded in a manifest are still there and unchanged
+    Check {
+        /// Manifest written by a run with `--manifest`
+        #[arg(long, value_name = "FILE", help = "Manifest to verify against")]
+        manifest: PathBuf,
+
+        /// Where the library is now, if it moved since the manifest was written
+        #[arg(help = "Library to check (defaults to the one the manifest was written for)")]
+        dir: Option<PathBuf>,
+    },
+
+    /// Check the configuration, the instance, and the music directory for problems
+    Doctor,
+
+    /// Ask for the basics of a setup and write them to the config file
+    Init,
+
+    /// Copy lyrics from another directory tree onto matching tracks instead of fetching them
+    Import {
+        /// Library to import into
+        #[arg(help = "Library to import lyrics into (defaults to the music directory)")]
+        dir: Option<PathBuf>,
+
+        /// Tree holding the lyrics collected by other tools or an older layout
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Directory tree to take LRC files from"
+        )]
+        from: PathBuf,
+
+        /// Remove each LRC file from the tree once it's imported, to sort out a folder of
+        /// loose downloads
+        #[arg(
+            long = "move",
+            help = "Move the LRC files into place instead of copying them"
+        )]
+        move_files: bool,
+    },
+
+    /// Copy the lyrics of a playlist's tracks to a portable player, named the way it expects
+    Export {
+        /// Playlist whose tracks' lyrics are exported
+        #[arg(
+            long,
+            value_name = "M3U",
+            help = "M3U playlist whose tracks' lyrics to export"
+        )]
+        playlist: PathBuf,
+
+        /// Directory on the device to write the lyrics to
+        #[arg(long, value_name = "DIR", help = "Directory to write the lyrics to")]
+        to: PathBuf,
+
+        /// Naming convention of the device the lyrics are for
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = DeviceLayout::Rockbox,
+            help = "Naming convention of the target device"
+        )]
+        device: DeviceLayout,
+    },
+
+    /// Fetch lyrics for the tracks on a mounted portable player, into the folder it reads
+    /// lyrics from
+    Dap {
+        /// Where the player is mounted
+        #[arg(help = "Mount point of the player")]
+        mount: PathBuf,
+
+        /// Track list exported from the player, one path per line, for players without a
+        /// Rockbox database
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Track list to use instead of the player's Rockbox database"
+        )]
+        tracks: Option<PathBuf>,
+
+        /// Naming convention of the player
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = DeviceLayout::Rockbox,
+            help = "Naming convention of the player"
+        )]
+        device: DeviceLayout,
+
+        /// Lyrics folder on the player, if not where the device layout puts it
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Folder to write lyrics to (default: Lyrics for Rockbox, beside each track for HiBy)"
+        )]
+        to: Option<PathBuf>,
+    },
+
+    /// Find the tracks whose lyrics contain a line, ignoring case and punctuation
+    Grep {
+        /// Words or line to look for
+        #[arg(help = "Lyrics line or words to search for")]
+        query: String,
+
+        /// Library to search
+        #[arg(help = "Library to search (defaults to the music directory)")]
+        dir: Option<PathBuf>,
+
+        /// Also search lyrics embedded in the audio files' tags
+        #[arg(long, help = "Also search lyrics embedded in audio files")]
+        embedded: bool,
+
+        /// Lines shown around each match
+        #[arg(
+            short = 'C',
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            help = "Show N lines of context around each match"
+        )]
+        context: usize,
+    },
+
+    /// Look a lyrics snippet up on the LRCLIB instance to find out which song it is from
+    Identify {
+        /// Remembered line of the song
+        #[arg(help = "Lyrics snippet to identify")]
+        text: String,
+
+        /// Library to check the candidates against
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Mark the candidates already in the library at DIR"
+        )]
+        library: Option<PathBuf>,
+
+        /// Most candidate tracks to list
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 10,
+            help = "List at most N candidates"
+        )]
+        limit: usize,
+    },
+
+    /// Build one LRC file for a DJ mix from the lyrics of each song in its tracklist
+    Mix {
+        /// Single-file mix to write lyrics for
+        #[arg(help = "Audio file of the mix")]
+        path: PathBuf,
+
+        /// Cue sheet or text tracklist with the start time of each song
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Cue sheet or timed tracklist (defaults to NAME.cue, then the comment tag)"
+        )]
+        tracklist: Option<PathBuf>,
+    },
+
+    /// Fetch lyrics for the song MPD is playing and the next ones in its queue
+    Prefetch {
+        /// MPD's music directory, which the queue's paths are relative to
+        #[arg(help = "MPD's music directory (defaults to the configured music directory)")]
+        dir: Option<PathBuf>,
+
+        /// How many songs after the current one to fetch lyrics for
+        #[arg(
+            short = 'n',
+            long,
+            value_name = "N",
+            default_value_t = 10,
+            help = "Number of upcoming songs to fetch lyrics for"
+        )]
+        count: usize,
+    },
+
+    /// Fetch lyrics for the track a console player like cmus is playing and show the current line
+    NowPlaying {
+        /// Player to ask, instead of trying each in turn
+        #[arg(
+            long,
+            value_enum,
+            help = "Player to ask (default: the first one playing)"
+        )]
+        player: Option<ConsolePlayer>,
+    },
+
+    /// Write a track's lyrics as subtitles beside it for mpv to load, fetching them if needed
+    MpvSub {
+        /// Audio file to write subtitles for
+        #[arg(
+            required_unless_present = "install_script",
+            help = "Audio file to write subtitles for"
+        )]
+        path: Option<PathBuf>,
+
+        /// Subtitle format to write
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = SubtitleFormat::Srt,
+            help = "Format of the subtitles"
+        )]
+        format: SubtitleFormat,
+
+        /// Print only the path of the subtitles, for scripts
+        #[arg(long, help = "Print only the path of the written subtitles")]
+        print_path: bool,
+
+        /// Install an mpv script that does this for every audio file mpv plays
+        #[arg(
+            long,
+            help = "Install an mpv script loading lyrics as subtitles for every audio file"
+        )]
+        install_script: bool,
+    },
+
+    /// Convert a track's synced lyrics into an ASS subtitle file, for lyric videos or mpv
+    Subtitle {
+        /// Audio file whose LRC file to convert, or the LRC file itself
+        #[arg(help = "Audio file, or its LRC file, whose lyrics to convert")]
+        path: PathBuf,
+
+        /// Where to write the subtitles instead of beside the LRC file
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Write the subtitles to FILE instead of NAME.ass"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    /// Measure the latency and throughput of a self-hosted instance with synthetic lookups
+    Bench {
+        /// Number of lookups to send
+        #[arg(
+            short = 'n',
+            long,
+            value_name = "N",
+            default_value_t = 200,
+            help = "Number of lookups to send"
+        )]
+        requests: usize,
+
+        /// Use the search endpoint, which is heavier, instead of exact lookups
+        #[arg(long, help = "Benchmark searches instead of exact lookups")]
+        search: bool,
+    },
+
+    /// Collect an album's lyrics into one booklet, in track order with titles
+    Booklet {
+        /// Album directory, discs in subdirectories included
+        #[arg(help = "Album directory whose lyrics to collect")]
+        dir: PathBuf,
+
+        /// Document format of the booklet
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = BookletFormat::Markdown,
+            help = "Format of the booklet"
+        )]
+        format: BookletFormat,
+
+        /// File to write the booklet to instead of stdout
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Write the booklet to FILE instead of stdout"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    /// Mirror lyrics files between two copies of a library, such as a NAS and a phone
+    Sync {
+        /// One copy of the library
+        #[arg(help = "One copy of the library")]
+        src: PathBuf,
+
+        /// The other copy, possibly transcoded but with the same file names or tags
+        #[arg(help = "The other copy of the library")]
+        dst: PathBuf,
+    },
+
+    /// Accept or reject the doubtful search matches held back instead of being written
+    Review,
+
+    /// Compare a track's lyrics with what every provider has, then pick, edit or shift them
+    /// before saving
+    Fix {
+        /// Audio file whose lyrics to fix
+        #[arg(help = "Audio file whose lyrics to fix")]
+        path: PathBuf,
+    },
+
+    /// Move a track's synced lyrics earlier or later, or fold their `[offset:]` tag into the timestamps
+    #[command(group(ArgGroup::new("change").required(true).multiple(true).args(["by", "flatten"])))]
+    Shift {
+        /// Audio file whose LRC file to retime, or the LRC file itself
+        #[arg(help = "Audio file, or its LRC file, whose lyrics to retime")]
+        path: PathBuf,
+
+        /// Milliseconds to move the lyrics by; negative values make them appear sooner
+        #[arg(
+            long,
+            value_name = "MS",
+            allow_hyphen_values = true,
+            help = "Delay the lyrics by MS milliseconds (negative to show them sooner)"
+        )]
+        by: Option<i64>,
+
+        /// Record the shift in the `[offset:]` tag instead of rewriting every timestamp
+        #[arg(
+            long,
+            requires = "by",
+            conflicts_with = "flatten",
+            help = "Adjust the [offset:] tag instead of the timestamps"
+        )]
+        offset_tag: bool,
+
+        /// Apply the `[offset:]` tag to the timestamps and remove it
+        #[arg(long, help = "Fold the [offset:] tag into the timestamps")]
+        flatten: bool,
+    },
+
+    /// Summarize the library's lyrics: coverage, languages, line counts and missing artists
+    Stats {
+        /// Only count tracks under this directory
+        #[arg(help = "Only count tracks under DIR (defaults to every recorded track)")]
+        dir: Option<PathBuf>,
+
+        /// Report how each provider answered lookups instead
+        #[arg(
+            long,
+            conflicts_with = "dir",
+            help = "Show each provider's requests, hits, misses and errors across all runs"
+        )]
+        providers: bool,
+    },
+
+    /// Inspect or empty the response cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Back up the state file or move it to another machine
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+
+    /// Upload hand-synced LRC files to the LRCLIB instance
+    #[command(group(ArgGroup::new("target").required(true).args(["path", "dir"])))]
+    Publish {
+        /// Audio file whose LRC file should be published
+        #[arg(help = "Audio file whose LRC file should be published")]
+        path: Option<PathBuf>,
+
+        /// Library to search for LRC files that didn't come from LRCLIB
+        #[arg(long, help = "Find and publish unpublished LRC files in a library")]
+        dir: Option<PathBuf>,
+
+        /// Publish without reviewing each file
+        #[arg(short, long, help = "Publish without asking for confirmation")]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Clone, Copy)]
+enum CacheAction {
+    /// Show where the cache is and how much it holds
+    Stats,
+    /// Remove every cached response
+    Clear,
+    /// Remove expired responses, and the least recently used ones beyond the size limit
+    Prune,
+}
+
+#[derive(Subcommand, Clone)]
+enum StateAction {
+    /// Write the state as JSON to a file, or to stdout
+    Export {
+        #[arg(help = "File to write (defaults to stdout)")]
+        file: Option<PathBuf>,
+    },
+    /// Merge a state exported elsewhere into this one, its records winning for the same tracks
+    Import {
+        #[arg(help = "State exported with `lrcphile state export`")]
+        file: PathBuf,
+
+        /// Library locations that differ between the machines
+        #[arg(
+            long,
+            value_name = "OLD=NEW",
+            value_parser = parse_relocation,
+            help = "Move tracks under OLD to NEW, for a library mounted elsewhere (repeatable)"
+        )]
+        relocate: Vec<(PathBuf, PathBuf)>,
+
+        /// Drop the current state instead of merging into it
+        #[arg(long, help = "Replace the current state instead of merging into it")]
+        replace: bool,
+    },
+}
+
+/// Where a portable player looks for the lyrics of a track
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DeviceLayout {
+    /// One flat lyrics directory, files named after the audio file
+    Rockbox,
+    /// Folders mirroring the library, so each file lands beside its synced track
+    Hiby,
+}
+
+/// Terminal music player `lrcphile now-playing` asks for the current track
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ConsolePlayer {
+    /// cmus, through `cmus-remote -Q`
+    Cmus,
+    /// Music On Console, through `mocp -i`
+    Moc,
+}
+
+impl ConsolePlayer {
+    fn name(self) -> &'static str {
+        match self {
+            ConsolePlayer::Cmus => "cmus",
+            ConsolePlayer::Moc => "moc",
+        }
+    }
+}
+
+/// Subtitle format `lrcphile mpv-sub` writes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SubtitleFormat {
+    /// SubRip, shown in the player's own subtitle style
+    Srt,
+    /// Advanced SubStation Alpha, styled by the `--ass-*` options
+    Ass,
+}
+
+/// Document format `lrcphile booklet` writes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum BookletFormat {
+    Markdown,
+    /// A standalone page styled for printing, one track per page, which browsers can save as PDF
+    Html,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProcessingOrder {
+    /// Alphabetically by path
+    Path,
+    /// Most recently modified first
+    Mtime,
+    /// Shuffled, to spread requests across artists
+    Random,
+}
+
+/// How tracks that LRCLIB marks as instrumental are remembered on disk
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum InstrumentalPlaceholder {
+    /// An LRC file holding only an `[instrumental]` tag
+    Lrc,
+    /// A separate `.instrumental` file, which players won't show as a lyric line
+    File,
+    /// Nothing, so instrumental tracks are looked up again on every run
+    None,
+    /// Nothing on disk; the state file remembers the track is instrumental
+    State,
+}
+
+/// Kind of lyrics written for a track, selected with `--lyrics-type`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LyricsType {
+    /// Synced lyrics as LRC, or plain lyrics as TXT when there are none
+    Any,
+    /// Synced lyrics only; tracks with just plain lyrics count as missing
+    Synced,
+    /// Plain lyrics as TXT, taken from the synced lyrics when there are no plain ones
+    Plain,
+}
+
+/// How the end of a directory run is reported, selected with `--summary`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SummaryFormat {
+    /// Totals, and each album's coverage after recursive runs
+    Text,
+    /// A line per track grouped by album, with a status glyph and any message about it
+    Table,
+}
+
+/// Extension of the placeholder written with `--instrumental-placeholder file`
+const INSTRUMENTAL_EXTENSION: &str = "instrumental";
+
+/// How long to pause when rate limited without a `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// How many times a single lookup is retried after being rate limited
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Files looked up at the same time unless `--concurrency` says otherwise
+const DEFAULT_CONCURRENCY: u32 = 4;
+
+/// Hosts of the public LRCLIB instance, which everyone shares
+const PUBLIC_INSTANCE_HOSTS: [&str; 2] = ["lrclib.net", "www.lrclib.net"];
+
+/// Concurrency and requests per second lrcphile keeps to on the public instance unless told
+/// otherwise with `--exceed-public-limits`
+const PUBLIC_MAX_CONCURRENCY: u32 = 2;
+const PUBLIC_MAX_RATE: f64 = 2.0;
+
+/// File extensions scanned for when processing a directory
+const AUDIO_EXTENSIONS: [&str; 11] = [
+    "mp3", "flac", "wav", "ogg", "m4a", "aac", "opus", "wma", "ape", "dsf", "dff",
+];
+
+/// Per-directory file listing paths to skip during scans, in gitignore syntax
+const IGNORE_FILE_NAME: &str = ".lrcphileignore";
+
+#[derive(Debug, Clone)]
+struct TrackMetadata {
+    track_name: String,
+    artist_name: String,
+    album_name: String,
+    genre: Option<String>,
+    duration: f64,
+    /// Disc and track number tags, telling apart tracks an album gives the same title
+    disc_number: Option<u32>,
+    track_number: Option<u32>,
+    /// LRCLIB record previously matched to this track, if known
+    lrclib_id: Option<u64>,
+    /// Validators of that record as last fetched, to skip downloading it again if unchanged
+    validators: Option<CacheValidators>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TagField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+}
+
+/// A `--filter` condition evaluated against a track's tags once they are read
+#[derive(Debug, Clone)]
+struct TagFilter {
+    field: TagField,
+    value: String,
+    contains: bool,
+}
+
+impl TagFilter {
+    fn matches(&self, metadata: &TrackMetadata) -> bool {
+        let tag_value = match self.field {
+            TagField::Title => Some(&metadata.track_name),
+            TagField::Artist => Some(&metadata.artist_name),
+            TagField::Album => Some(&metadata.album_name),
+            TagField::Genre => metadata.genre.as_ref(),
+        };
+
+        let Some(tag_value) = tag_value else {
+            return false;
+        };
+
+        let tag_value = tag_value.to_lowercase();
+        if self.contains {
+            tag_value.contains(&self.value)
+        } else {
+            tag_value == self.value
+        }
+    }
+}
+
+impl Cli {
+    /// Subtitle style given by the `--ass-*` options
+    fn ass_style(&self) -> AssStyle {
+        AssStyle {
+            font: self.ass_font.clone(),
+            font_size: self.ass_font_size,
+            color: self.ass_color.clone(),
+            karaoke_color: self.ass_karaoke_color.clone(),
+            outline_color: self.ass_outline_color.clone(),
+            position: self.ass_position,
+            karaoke: self.ass_karaoke,
+        }
+    }
+
+    /// Whether any `--set-*` lookup override was given
+    fn has_metadata_overrides(&self) -> bool {
+        self.set_title.is_some()
+            || self.set_artist.is_some()
+            || self.set_album.is_some()
+            || self.set_duration.is_some()
+    }
+
+    /// The on-disk response cache, limited to `--cache-size`
+    fn response_cache(&self) -> Option<ResponseCache> {
+        ResponseCache::default_location()
+            .map(|dir| ResponseCache::open(dir, self.cache_size * 1024 * 1024))
+    }
+
+    /// Settings for the HTTP client shared by every request of the run
+    fn client_options(&self) -> ClientOptions {
+        let cache = if self.no_cache {
+            None
+        } else {
+            self.response_cache().map(Arc::new)
+        };
+
+        ClientOptions {
+            cache,
+            refresh: self.override_files || self.refetch_from.is_some(),
+            resolve: self.resolve.clone(),
+            ip_version: if self.ipv4 {
+                Some(IpVersion::V4)
+            } else if self.ipv6 {
+                Some(IpVersion::V6)
+            } else {
+                None
+            },
+            trace_http: self.trace_http.clone(),
+            requests: self.network_requests.clone(),
+            min_interval: self
+                .rate_limit()
+                .map(|rate_limit| Duration::from_secs_f64(1.0 / rate_limit)),
+        }
+    }
+
+    /// The providers of `--providers`, chained when there are several
+    fn lyrics_provider(&self) -> Result<Arc<dyn LyricsProvider>> {
+        let mut providers = self.lyrics_providers()?;
+        if providers.len() == 1 {
+            return Ok(providers.remove(0));
+        }
+        Ok(Arc::new(ProviderChain::new(providers)))
+    }
+
+    /// Each provider of `--providers` on its own, in order, for asking every one of them
+    fn lyrics_providers(&self) -> Result<Vec<Arc<dyn LyricsProvider>>> {
+        let options = self.client_options();
+        let mut providers: Vec<Arc<dyn LyricsProvider>> = Vec::new();
+        for name in &self.providers {
+            if name == LRCLIB_PROVIDER {
+                let lrclib = Lrclib::connect(&self.url, self.cached_only, &options)?;
+                providers.push(Arc::new(CountedProvider::new(
+                    LRCLIB_PROVIDER,
+                    Arc::new(lrclib),
+                    true,
+                    self.lookup_stats.clone(),
+                )));
+                continue;
+            }
+            let spec = self
+                .custom_providers
+                .iter()
+                .find(|spec| spec.name == *name)
+                .ok_or_else(|| {
+                    LrcphileError::Provider(format!(
+                        "No [[provider]] named {} in the config file",
+                        name
+                    ))
+                })?;
+            let custom = CustomProvider::new(spec, &options)?;
+            providers.push(Arc::new(CountedProvider::new(
+                custom.name().to_string(),
+                Arc::new(custom),
+                false,
+                self.lookup_stats.clone(),
+            )));
+        }
+        Ok(providers)
+    }
+
+    /// Whether `--url` is the public LRCLIB instance rather than a self-hosted one
+    fn is_public_instance(&self) -> bool {
+        reqwest::Url::parse(&self.url).is_ok_and(|url| {
+            url.host_str()
+                .is_some_and(|host| PUBLIC_INSTANCE_HOSTS.contains(&host))
+        })
+    }
+
+    /// Whether the public instance's limits apply to this run
+    fn limited_to_public(&self) -> bool {
+        self.is_public_instance() && !self.exceed_public_limits
+    }
+
+    /// How many files are looked up at the same time
+    fn concurrency(&self) -> usize {
+        let concurrency = self.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        if self.limited_to_public() {
+            concurrency.min(PUBLIC_MAX_CONCURRENCY) as usize
+        } else {
+            concurrency as usize
+        }
+    }
+
+    /// Most requests per second sent to the instance
+    fn rate_limit(&self) -> Option<f64> {
+        if self.limited_to_public() {
+            Some(
+                self.rate_limit
+                    .unwrap_or(PUBLIC_MAX_RATE)
+                    .min(PUBLIC_MAX_RATE),
+            )
+        } else {
+            self.rate_limit
+        }
+    }
+
+    /// A note for when `--concurrency` or `--rate-limit` asked for more than the public
+    /// instance's limits allow
+    fn public_limits_note(&self) -> Option<String> {
+        let exceeded = self
+            .concurrency
+            .is_some_and(|concurrency| concurrency > PUBLIC_MAX_CONCURRENCY)
+            || self.rate_limit.is_some_and(|rate| rate > PUBLIC_MAX_RATE);
+        (exceeded && self.limited_to_public()).then(|| {
+            format!(
+                "Keeping to {} concurrent lookups and {} requests per second on the public \
+                 instance; pass --exceed-public-limits to go faster",
+                PUBLIC_MAX_CONCURRENCY, PUBLIC_MAX_RATE
+            )
+        })
+    }
+}
+
+fn parse_rate_limit(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!(
+            "invalid rate '{}' (expected requests per second above 0)",
+            rate
+        )),
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| format!("invalid date: {}", e))
+}
+
+/// Parses a `HOST:IP` DNS override; IPv6 addresses may be given with or without brackets
+fn parse_resolve(resolve: &str) -> Result<(String, IpAddr), String> {
+    let (host, ip) = resolve
+        .split_once(':')
+        .ok_or_else(|| format!("invalid override '{}' (expected HOST:IP)", resolve))?;
+    let ip = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|e| format!("invalid IP address '{}': {}", ip, e))?;
+    if host.is_empty() {
+        return Err(format!("invalid override '{}' (expected HOST:IP)", resolve));
+    }
+    Ok((host.to_string(), ip))
+}
+
+/// Parses an `OLD=NEW` pair of library locations for `state import --relocate`
+fn parse_relocation(relocation: &str) -> Result<(PathBuf, PathBuf), String> {
+    match relocation.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok((
+            std::path::absolute(old).map_err(|e| e.to_string())?,
+            std::path::absolute(new).map_err(|e| e.to_string())?,
+        )),
+        _ => Err(format!(
+            "invalid relocation '{}' (expected OLD=NEW)",
+            relocation
+        )),
+    }
+}
+
+/// Parses file permissions given in octal, like `chmod`
+fn parse_file_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid file mode '{}' (expected octal, e.g. 644)", mode))
+}
+
+/// Parses a lyrics file extension, with or without its leading dot
+fn parse_extension(extension: &str) -> Result<String, String> {
+    let extension = extension.trim_start_matches('.');
+    if extension.is_empty() || extension.contains(['/', '\\', '.']) {
+        return Err(format!("invalid extension '{}'", extension));
+    }
+    Ok(extension.to_string())
+}
+
+/// Parses a duration made of whole seconds, minutes and hours, e.g. `45s`, `30m` or `1h30m`
+fn parse_runtime(runtime: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration '{}' (expected e.g. 45s, 30m or 1h30m)",
+            runtime
+        )
+    };
+
+    let mut seconds = 0;
+    let mut digits = String::new();
+    for c in runtime.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        seconds += value * unit;
+        digits.clear();
+    }
+
+    if !digits.is_empty() || seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+fn parse_tag_filter(filter: &str) -> Result<TagFilter, String> {
+    let (field, value, contains) = if let Some((field, value)) = filter.split_once("~=") {
+        (field, value, true)
+    } else if let Some((field, value)) = filter.split_once('=') {
+        (field, value, false)
+    } else {
+        return Err("expected TAG=VALUE or TAG~=VALUE".to_string());
+    };
+
+    let field = match field.trim().to_lowercase().as_str() {
+        "title" => TagField::Title,
+        "artist" => TagField::Artist,
+        "album" => TagField::Album,
+        "genre" => TagField::Genre,
+        other => {
+            return Err(format!(
+                "unknown tag '{}' (expected title, artist, album, or genre)",
+                other
+            ));
+        }
+    };
+
+    Ok(TagFilter {
+        field,
+        value: value.trim().to_lowercase(),
+        contains,
+    })
+}
+
+/// Prints per-file messages above the progress bar while one is active,
+/// so output from concurrent workers doesn't tear the bar
+#[derive(Clone, Default)]
+struct Console {
+    progress: Option<ProgressBar>,
+    /// Receives messages instead of the terminal, for full-screen interfaces
+    log: Option<UnboundedSender<String>>,
+    /// Messages about each file, kept for `--summary table` instead of printed
+    notes: Option<Arc<std::sync::Mutex<BTreeMap<PathBuf, String>>>>,
+}
+
+/// The progress bar of commands that go through many tracks, with room for an estimate in the
+/// prefix
+fn progress_bar(len: usize) -> ProgressBar {
+    let progress = ProgressBar::new(len as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("[{bar:40}] {pos}/{len} {prefix} {msg}")
+            .unwrap()
+            .progress_chars("# "),
+    );
+    progress
+}
+
+impl Console {
+    fn with_progress(progress: &ProgressBar) -> Self {
+        Self {
+            progress: Some(progress.clone()),
+            ..Self::default()
+        }
+    }
+
+    fn with_log(log: UnboundedSender<String>) -> Self {
+        Self {
+            log: Some(log),
+            ..Self::default()
+        }
+    }
+
+    /// Keeps warnings and failures about files to be shown by `take_notes` instead
+    fn keeping_notes(self) -> Self {
+        Self {
+            notes: Some(Arc::default()),
+            ..self
+        }
+    }
+
+    /// Records a message about a file when notes are kept, returning whether it was
+    fn keep_note(&self, file_path: &Path, message: &dyn std::fmt::Display) -> bool {
+        let Some(notes) = &self.notes else {
+            return false;
+        };
+        let mut notes = notes.lock().unwrap_or_else(|e| e.into_inner());
+        notes
+            .entry(file_path.to_path_buf())
+            .and_modify(|note| *note = format!("{}; {}", note, message))
+            .or_insert_with(|| message.to_string());
+        true
+    }
+
+    /// The messages kept about each file so far
+    fn take_notes(&self) -> BTreeMap<PathBuf, String> {
+        self.notes
+            .as_ref()
+            .map(|notes| std::mem::take(&mut *notes.lock().unwrap_or_else(|e| e.into_inner())))
+            .unwrap_or_default()
+    }
+
+    fn println(&self, line: String) {
+        if let Some(log) = &self.log {
+            let _ = log.send(line);
+        } else if let Some(progress) = &self.progress
+            && !progress.is_hidden()
+        {
+            progress.suspend(|| eprintln!("{}", line));
+        } else {
+            // A hidden bar, as when stderr isn't a terminal, drops whatever it's given to print
+            eprintln!("{}", line);
+        }
+    }
+
+    /// Shows a transient status on the progress bar, or prints it when there is none
+    fn set_status(&self, status: String) {
+        match &self.progress {
+            Some(progress) => progress.set_message(status),
+            None => self.println(status.yellow().to_string()),
+        }
+    }
+
+    /// Runs a prompt with the progress bar hidden, so only one worker talks to the terminal at a time
+    fn suspend<R>(&self, prompt: impl FnOnce() -> R) -> R {
+        match &self.progress {
+            Some(progress) => progress.suspend(prompt),
+            None => prompt(),
+        }
+    }
+
+    fn warning(&self, file_path: &Path, message: impl std::fmt::Display) {
+        if self.keep_note(file_path, &message) {
+            return;
+        }
+        self.println(format!(
+            "{} {}",
+            "Warning:".yellow().bold(),
+            format!("{}: {}", file_path.display(), message).yellow()
+        ));
+    }
+
+    fn error(&self, file_path: &Path, message: impl std::fmt::Display) {
+        if self.keep_note(file_path, &message) {
+            return;
+        }
+        self.println(format!(
+            "{} {}",
+            "Error:".red().bold(),
+            format!("{}: {}", file_path.display(), message).red()
+        ));
+    }
+
+    fn fixed(&self, file_path: &Path, message: impl std::fmt::Display) {
+        if self.keep_note(file_path, &message) {
+            return;
+        }
+        self.println(format!(
+            "{} {}",
+            "Fixed:".green().bold(),
+            format!("{}: {}", file_path.display(), message).green()
+        ));
+    }
+
+    fn failed(&self, file_path: &Path, message: impl std::fmt::Display) {
+        if self.keep_note(file_path, &message) {
+            return;
+        }
+        self.println(format!(
+            "{} {}",
+            "Failed:".red().bold(),
+            format!("{}: {}", file_path.display(), message).red()
+        ));
+    }
+}
+
+/// How long the stages of processing a file took; `None` for stages the file didn't reach
+#[derive(Debug, Clone, Copy, Default)]
+struct FileTimings {
+    /// Reading the tags, where slow disks and network shares show
+    tags: Option<Duration>,
+    /// Waiting on the instance, retries and rate limit pauses included
+    lookup: Option<Duration>,
+    /// Writing the lyrics file
+    write: Option<Duration>,
+}
+
+impl FileTimings {
+    fn stages(&self) -> [(&'static str, Option<Duration>); 3] {
+        [
+            ("tags", self.tags),
+            ("lookup", self.lookup),
+            ("write", self.write),
+        ]
+    }
+}
+
+impl std::fmt::Display for FileTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stages: Vec<String> = self
+            .stages()
+            .into_iter()
+            .filter_map(|(stage, time)| Some(format!("{} {}", stage, format_millis(time?))))
+            .collect();
+        write!(f, "{}", stages.join(", "))
+    }
+}
+
+fn format_millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Holds back every worker while the provider is rate limiting us, so a 429
+/// pauses the whole run instead of failing the files that happen to be in flight
+#[derive(Default)]
+struct Throttle {
+    resume_at: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// Waits until any active pause is over, returning whether it had to wait
+    async fn wait(&self) -> bool {
+        let resume_at = *self.resume_at.lock().await;
+        match resume_at {
+            Some(resume_at) if resume_at > Instant::now() => {
+                tokio::time::sleep_until(resume_at).await;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut resume_at = self.resume_at.lock().await;
+        if resume_at.is_none_or(|current| current < until) {
+            *resume_at = Some(until);
+        }
+    }
+}
+
+/// Limits from `--max-requests` and `--max-runtime`; once one is reached no new files are
+/// dispatched, while those in flight finish
+struct Budget {
+    max_requests: Option<u64>,
+    /// Requests the transports sent to the network so far
+    requests: Arc<AtomicU64>,
+    max_runtime: Option<Duration>,
+    /// In megabytes of resident memory grown since the start
+    max_memory: Option<u64>,
+    /// Resident memory in bytes when the run started, before any track was looked at
+    baseline_memory: u64,
+    started: Instant,
+}
+
+impl Budget {
+    fn new(args: &Cli) -> Self {
+        Self {
+            max_requests: args.max_requests,
+            requests: args.network_requests.clone(),
+            max_runtime: args.max_runtime,
+            max_memory: args.max_memory,
+            baseline_memory: resident_memory().unwrap_or(0),
+            started: Instant::now(),
+        }
+    }
+
+    /// Which limit was reached, if any
+    fn exhausted(&self) -> Option<String> {
+        if let Some(max_requests) = self.max_requests
+            && self.requests.load(Ordering::Relaxed) >= max_requests
+        {
+            return Some(format!("request budget of {} used up", max_requests));
+        }
+        if let Some(max_runtime) = self.max_runtime
+            && self.started.elapsed() >= max_runtime
+        {
+            return Some(format!(
+                "runtime budget of {} min used up",
+                max_runtime.as_secs().div_ceil(60)
+            ));
+        }
+        if let Some(max_memory) = self.max_memory
+            && resident_memory().is_some_and(|resident| {
+                resident.saturating_sub(self.baseline_memory) >= max_memory * 1024 * 1024
+            })
+        {
+            return Some(format!("memory limit of {} MB reached", max_memory));
+        }
+        None
+    }
+}
+
+/// Resident memory of this process in bytes, from `/proc`
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<u64> {
+    None
+}
+
+/// What lyrics a track has on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LyricsStatus {
+    Synced,
+    Plain,
+    Instrumental,
+    Missing,
+}
+
+impl LyricsStatus {
+    fn of(audio_path: &Path, args: &Cli) -> Self {
+        if find_instrumental_placeholder(audio_path, args).is_some() {
+            LyricsStatus::Instrumental
+        } else if lyrics_file_exists(audio_path, "lrc", args).unwrap_or(false) {
+            LyricsStatus::Synced
+        } else if lyrics_file_exists(audio_path, "txt", args).unwrap_or(false) {
+            LyricsStatus::Plain
+        } else {
+            LyricsStatus::Missing
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LyricsStatus::Synced => "synced",
+            LyricsStatus::Plain => "plain",
+            LyricsStatus::Instrumental => "instrumental",
+            LyricsStatus::Missing => "missing",
+        }
+    }
+}
+
+/// Lyrics coverage of the tracks in one album directory
+#[derive(Debug, Clone, Default)]
+struct AlbumSummary {
+    synced: usize,
+    plain: usize,
+    instrumental: usize,
+    missing: usize,
+}
+
+impl AlbumSummary {
+    fn total(&self) -> usize {
+        self.synced + self.plain + self.instrumental + self.missing
+    }
+}
+
+/// What happened to a single file during a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileOutcome {
+    Fetched,
+    Failed,
+    Skipped,
+    /// A doubtful search match was held back for `lrcphile review`
+    Quarantined,
+}
+
+impl FileOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            FileOutcome::Fetched => "fetched",
+            FileOutcome::Failed => "failed",
+            FileOutcome::Skipped => "skipped",
+            FileOutcome::Quarantined => "quarantined",
+        }
+    }
+}
+
+/// Totals for a run, aggregated from the outcome of each file once its worker is done
+#[derive(Debug, Clone)]
+struct ProcessingStats {
+    success: usize,
+    failed: usize,
+    skipped: usize,
+    quarantined: usize,
+    total: usize,
+    albums: BTreeMap<PathBuf, AlbumSummary>,
+    /// Outcome of every file, in the order they finished, for `--report` and `--summary
+    /// table`; only kept when one of them was asked for, since it grows with the library
+    outcomes: Option<Vec<(PathBuf, FileOutcome)>>,
+    /// How long each file's stages took, for `--verbose`
+    timings: Vec<FileTimings>,
+}
+
+impl ProcessingStats {
+    fn new(total: usize) -> Self {
+        Self {
+            success: 0,
+            failed: 0,
+            skipped: 0,
+            quarantined: 0,
+            total,
+            albums: BTreeMap::new(),
+            outcomes: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Counts a file's outcome, and its lyrics towards the coverage of the directory it's in
+    fn record(&mut self, file_path: &Path, outcome: FileOutcome, args: &Cli) {
+        match outcome {
+            FileOutcome::Fetched => self.success += 1,
+            FileOutcome::Failed => self.failed += 1,
+            FileOutcome::Skipped => self.skipped += 1,
+            FileOutcome::Quarantined => self.quarantined += 1,
+        }
+        if let Some(outcomes) = &mut self.outcomes {
+            outcomes.push((file_path.to_path_buf(), outcome));
+        }
+
+        let album_dir = file_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let album = self.albums.entry(album_dir).or_default();
+        match LyricsStatus::of(file_path, args) {
+            LyricsStatus::Synced => album.synced += 1,
+            LyricsStatus::Plain => album.plain += 1,
+            LyricsStatus::Instrumental => album.instrumental += 1,
+            LyricsStatus::Missing => album.missing += 1,
+        }
+    }
+
+    /// Keeps every file's outcome for `write_report` and `display_table`
+    fn keep_outcomes(&mut self) {
+        self.outcomes.get_or_insert_with(Vec::new);
+    }
+
+    fn progress_counts(&self) -> ProgressCounts {
+        ProgressCounts {
+            done: self.total - self.unprocessed(),
+            total: self.total,
+            fetched: self.success,
+            failed: self.failed,
+            skipped: self.skipped,
+            quarantined: self.quarantined,
+        }
+    }
+
+    /// Files left over when the run stopped early
+    fn unprocessed(&self) -> usize {
+        self.total - self.success - self.failed - self.skipped - self.quarantined
+    }
+
+    fn display_summary(&self) {
+        println!("\n{}", "Processing Summary:".bright_cyan().bold());
+        println!(
+            "  {} {} {}",
+            "Processed:".white(),
+            (self.total - self.unprocessed())
+                .to_string()
+                .bright_white()
+                .bold(),
+            "files".white()
+        );
+        println!(
+            "  {} {} {}",
+            "Successful:".green(),
+            self.success.to_string().bright_green().bold(),
+            "files".green()
+        );
+        println!(
+            "  {} {} {}",
+            "Failed:".red(),
+            self.failed.to_string().bright_red().bold(),
+            "files".red()
+        );
+        println!(
+            "  {} {} {}",
+            "Skipped (existing/instrumental/filtered):".yellow(),
+            self.skipped.to_string().bright_yellow().bold(),
+            "files".yellow()
+        );
+        if self.quarantined > 0 {
+            println!(
+                "  {} {} {}",
+                "Held for review (lrcphile review):".bright_magenta(),
+                self.quarantined.to_string().bright_magenta().bold(),
+                "files".bright_magenta()
+            );
+        }
+        if self.unprocessed() > 0 {
+            println!(
+                "  {} {} {}",
+                "Left for the next run:".white(),
+                self.unprocessed().to_string().bright_white().bold(),
+                "files".white()
+            );
+        }
+    }
+
+    /// Prints the median, 90th percentile and slowest time of each stage, telling a slow disk
+    /// (tags, write) from a slow network or instance (lookup)
+    fn display_timings(&self) {
+        println!("\n{}", "Timings:".bright_cyan().bold());
+        for (index, stage) in ["Tags:", "Lookup:", "Write:"].into_iter().enumerate() {
+            let mut times: Vec<Duration> = self
+                .timings
+                .iter()
+                .filter_map(|timings| timings.stages()[index].1)
+                .collect();
+            if times.is_empty() {
+                continue;
+            }
+            times.sort();
+            let percentile = |p: usize| times[(times.len() - 1) * p / 100];
+            println!(
+                "  {:<8} p50 {}  p90 {}  max {}  ({} files)",
+                stage.white(),
+                format_millis(percentile(50)).bright_white(),
+                format_millis(percentile(90)).bright_white(),
+                format_millis(percentile(100)).bright_white(),
+                times.len()
+            );
+        }
+    }
+
+    /// Writes a CSV line per file with its outcome, and the LRCLIB record it was matched to
+    /// with the confidence of that match
+    fn write_report(&self, report: &Path, state: &StateDb) -> Result<()> {
+        let mut csv = String::from("path,disc,track,outcome,lrclib_id,confidence\n");
+        let mut outcomes = self.outcomes.clone().unwrap_or_default();
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (file_path, outcome) in outcomes {
+            let record = state.get(&file_path);
+            let field = |value: Option<String>| value.unwrap_or_default();
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                csv_field(&file_path.to_string_lossy()),
+                field(
+                    record
+                        .and_then(|record| record.disc)
+                        .map(|disc| disc.to_string())
+                ),
+                field(
+                    record
+                        .and_then(|record| record.track)
+                        .map(|track| track.to_string())
+                ),
+                outcome.as_str(),
+                field(
+                    record
+                        .and_then(|record| record.lrclib_id)
+                        .map(|id| id.to_string())
+                ),
+                field(
+                    record
+                        .and_then(|record| record.confidence)
+                        .map(|confidence| confidence.to_string())
+                )
+            ));
+        }
+        fs::write(report, csv)?;
+        Ok(())
+    }
+
+    /// Prints a line per file under its album with a glyph for its outcome, the lyrics it has
+    /// now and the messages held back about it, aligned into columns
+    fn display_table(&self, notes: &BTreeMap<PathBuf, String>, args: &Cli) {
+        let mut outcomes = self.outcomes.clone().unwrap_or_default();
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        let file_name = |file_path: &Path| {
+            let name = file_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file_path.display().to_string());
+            if name.chars().count() > TABLE_NAME_WIDTH {
+                let cut: String = name.chars().take(TABLE_NAME_WIDTH - 1).collect();
+                format!("{}…", cut)
+            } else {
+                name
+            }
+        };
+        let name_width = outcomes
+            .iter()
+            .map(|(file_path, _)| file_name(file_path).chars().count())
+            .max()
+            .unwrap_or(0);
+
+        println!("\n{}", "Results:".bright_cyan().bold());
+        let mut album = None;
+        for (file_path, outcome) in &outcomes {
+            let album_dir = file_path.parent().unwrap_or(Path::new(""));
+            if album != Some(album_dir) {
+                album = Some(album_dir);
+                let name = album_dir
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_else(|| album_dir.to_string_lossy());
+                println!("  {}", name.bold());
+            }
+
+            let glyph = match outcome {
+                FileOutcome::Fetched => "✓".green(),
+                FileOutcome::Skipped => "·".bright_black(),
+                FileOutcome::Failed => "✗".red(),
+                FileOutcome::Quarantined => "?".bright_magenta(),
+            };
+            let lyrics = LyricsStatus::of(file_path, args);
+            let lyrics_column = format!("{:<12}", lyrics.as_str());
+            let lyrics_column = if lyrics == LyricsStatus::Missing {
+                lyrics_column.yellow()
+            } else {
+                lyrics_column.normal()
+            };
+            let line = format!(
+                "    {} {:<width$}  {}",
+                glyph,
+                file_name(file_path),
+                lyrics_column,
+                width = name_width
+            );
+            match notes.get(file_path) {
+                Some(note) => println!("{} {}", line, note.bright_black()),
+                None => println!("{}", line.trim_end()),
+            }
+        }
+        println!(
+            "  {} fetched  {} skipped  {} failed  {} held for review",
+            "✓".green(),
+            "·".bright_black(),
+            "✗".red(),
+            "?".bright_magenta()
+        );
+    }
+
+    /// Prints one line of lyrics coverage per album, highlighting albums that still miss lyrics
+    fn display_album_summary(&self) {
+        println!("\n{}", "Albums:".bright_cyan().bold());
+        for (album_dir, album) in &self.albums {
+            let name = album_dir
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_else(|| album_dir.to_string_lossy());
+
+            let mut counts = vec![format!("{}/{} synced", album.synced, album.total())];
+            for (count, label) in [
+                (album.plain, "plain"),
+                (album.instrumental, "instrumental"),
+                (album.missing, "missing"),
+            ] {
+                if count > 0 {
+                    counts.push(format!("{} {}", count, label));
+                }
+            }
+
+            let line = format!("  {}: {}", name, counts.join(", "));
+            if album.missing > 0 {
+                println!("{}", line.yellow());
+            } else {
+                println!("{}", line.green());
+            }
+        }
+    }
+}
+
+/// Longest file name shown in `--summary table` before it's cut short
+const TABLE_NAME_WIDTH: usize = 48;
+
+/// Quotes a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl TrackMetadata {
+    /// Where the track sits on its album, like "disc 2, track 5"
+    fn position(&self) -> Option<String> {
+        match (self.disc_number, self.track_number) {
+            (Some(disc), Some(track)) => Some(format!("disc {}, track {}", disc, track)),
+            (None, Some(track)) => Some(format!("track {}", track)),
+            (Some(disc), None) => Some(format!("disc {}", disc)),
+            (None, None) => None,
+        }
+    }
+
+    fn matches_filters(&self, args: &Cli) -> bool {
+        if args.min_duration.is_some_and(|min| self.duration < min)
+            || args.max_duration.is_some_and(|max| self.duration > max)
+        {
+            return false;
+        }
+
+        args.filters.iter().all(|filter| filter.matches(self))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Older Windows consoles print color codes literally until asked to interpret them
+    #[cfg(windows)]
+    let _ = colored::control::set_virtual_terminal(true);
+
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let mut config = match Config::default_location().map(|location| Config::load(&location)) {
+        Some(Ok(config)) => config,
+        // Let doctor start anyway so it can report what's wrong with the file, and init
+        // replace it
+        Some(Err(_)) if matches!(args.command, Some(Command::Doctor | Command::Init)) => {
+            Config::default()
+        }
+        Some(Err(e)) => {
+            eprintln!(
+                "{} {}",
+                "Error:".red().bold(),
+                format!("Failed to load config file: {}", e).red()
+            );
+            std::process::exit(1);
+        }
+        None => Config::default(),
+    };
+
+    if config.preserve_manual == Some(true)
+        && matches.value_source("preserve_manual") != Some(ValueSource::EnvVariable)
+    {
+        args.preserve_manual = true;
+    }
+
+    if let Some(name) = args.profile.clone().or(config.default_profile.clone()) {
+        let applied = match config.profiles.get(&name).cloned() {
+            Some(profile) => apply_profile(&mut args, &mut config, &matches, profile),
+            None => Err(format!("No [profile.{}] section in the config file", name)),
+        };
+        if let Err(e) = applied {
+            eprintln!(
+                "{} {}",
+                "Error:".red().bold(),
+                format!("Profile {}: {}", name, e).red()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let mut notes = Vec::new();
+    if args.max_memory.is_some() && resident_memory().is_none() {
+        notes.push("--max-memory is only enforced on Linux".to_string());
+    }
+    notes.extend(args.public_limits_note());
+    for note in notes {
+        // With --serve-stdio, stdout only carries replies
+        if args.serve_stdio {
+            eprintln!("{} {}", "Note:".yellow().bold(), note.yellow());
+        } else {
+            println!("{} {}", "Note:".yellow().bold(), note.yellow());
+        }
+    }
+
+    args.custom_providers = config.providers.clone();
+    if args.clean_lyrics {
+        let patterns: Vec<&str> = if config.clean_patterns.is_empty() {
+            DEFAULT_CLEAN_PATTERNS.to_vec()
+        } else {
+            config.clean_patterns.iter().map(String::as_str).collect()
+        };
+        match patterns.into_iter().map(Regex::new).collect() {
+            Ok(patterns) => {
+                args.cleanup_patterns = patterns;
+                args.cleanup_leading_only = config.clean_patterns.is_empty();
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Error:".red().bold(),
+                    format!("Invalid clean_patterns entry in the config file: {}", e).red()
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.censored_copy {
+        let words: Vec<&str> = if config.censor_words.is_empty() {
+            DEFAULT_CENSOR_WORDS.to_vec()
+        } else {
+            config.censor_words.iter().map(String::as_str).collect()
+        };
+        let alternatives: Vec<String> = words.into_iter().map(regex::escape).collect();
+        match Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|"))) {
+            Ok(pattern) => args.censor_pattern = Some(pattern),
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Error:".red().bold(),
+                    format!("Invalid censor_words in the config file: {}", e).red()
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let state = match load_state(&args) {
+        Ok(state) => Arc::new(Mutex::new(state)),
+        Err(e) => {
+            eprintln!(
+                "{} {}",
+                "Error:".red().bold(),
+                format!("Failed to load state file: {}", e).red()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if args.serve_stdio {
+        if args.output_dir.is_some() || args.no_write_outside_root {
+            let library = default_music_dir(&config);
+            if let Some(output_dir) = args.output_dir.clone() {
+                redirect_lyrics(&mut args, library.as_deref(), &output_dir);
+            }
+            if args.no_write_outside_root {
+                confine_writes(&mut args, library.as_deref());
+            }
+        }
+        if let Err(e) = serve::run(&args, &state).await {
+            eprintln!("{} {}", "Error:".red().bold(), e.to_string().red());
+            std::process::exit(1);
+        }
+        save_state(&state, &args).await;
+        return;
+    }
+
+    if let Some(command) = args.command.clone() {
+        if args.output_dir.is_some() || args.no_write_outside_root {
+            let library = commands::library(&command, &args, &config);
+            if let Some(output_dir) = args.output_dir.clone() {
+                redirect_lyrics(&mut args, library.as_deref(), &output_dir);
+            }
+            if args.no_write_outside_root {
+                confine_writes(&mut args, library.as_deref());
+            }
+        }
+        if let Err(e) = commands::run(&command, &args, &config, &state).await {
+            eprintln!("{} {}", "Error:".red().bold(), e.to_string().red());
+            std::process::exit(1);
+        }
+        save_state(&state, &args).await;
+        return;
+    }
+
+    let path = match args.path.clone().or_else(|| default_music_dir(&config)) {
+        Some(path) => path,
+        None => {
+            let hint = if is_termux() {
+                "run termux-setup-storage, pass a path, or set LRCPHILE_MUSIC_DIR".to_string()
+            } else {
+                format!(
+                    "pass a path, set LRCPHILE_MUSIC_DIR, or set music_dir in {}",
+                    Config::default_location()
+                        .map(|location| location.display().to_string())
+                        .unwrap_or_else(|| "the config file".to_string())
+                )
+            };
+            eprintln!(
+                "{} {}",
+                "Error:".red().bold(),
+                format!("Could not determine your music directory; {}", hint).red()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let provider = match args.lyrics_provider() {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!(
+                "{} {}",
+                "Error:".red().bold(),
+                format!("Failed to initialize the lyrics providers: {}", e).red()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let throttle = Arc::new(Throttle::default());
+
+    // Catch a read-only library before every single file fails to save
+    if let Some(output_dir) = args.output_dir.clone() {
+        redirect_lyrics(&mut args, Some(&path), &output_dir);
+    } else if let Err(e) = probe_writable(&path)
+        && matches!(
+            e.kind(),
+            std::io::ErrorKind::ReadOnlyFilesystem | std::io::ErrorKind::PermissionDenied
+        )
+    {
+        match offer_output_dir(&path, &e) {
+            Some(output_dir) => redirect_lyrics(&mut args, Some(&path), &output_dir),
+            None => std::process::exit(1),
+        }
+    }
+
+    if args.no_write_outside_root {
+        confine_writes(&mut args, Some(&path));
+    }
+
+    // Held until the run ends, so a second run over the same library refuses to start
+    let _lock = if path.is_dir() {
+        match LibraryLock::acquire(&path, args.break_lock) {
+            Ok(lock) => lock,
+            Err(e) => {
+                eprintln!("{} {}", "Error:".red().bold(), e.to_string().red());
+                if let Some(holder) = LibraryLock::holder(&path) {
+                    eprintln!("{} {}", "Held by:".yellow().bold(), holder.yellow());
+                }
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let events = match &args.progress_json {
+        Some(target) => match ProgressEvents::open(target) {
+            Ok(events) => Some(Arc::new(events)),
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Error:".red().bold(),
+                    format!("Failed to open --progress-json target: {}", e).red()
+                );
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let emit = |event: ProgressEvent| {
+        if let Some(events) = &events {
+            events.emit(event);
+        }
+    };
+
+    if args.has_metadata_overrides() && !path.is_file() {
+        eprintln!(
+            "{} {}",
+            "Error:".red().bold(),
+            "--set-title, --set-artist, --set-album and --set-duration only apply to a single file"
+                .red()
+        );
+        std::process::exit(1);
+    }
+
+    if path.is_file() {
+        let mut stats = ProcessingStats::new(1);
+        emit(ProgressEvent::Started { total: 1 });
+        emit(ProgressEvent::FileStarted { path: &path });
+        let outcome = process_file(
+            &path,
+            &args,
+            provider.as_ref(),
+            &throttle,
+            state.clone(),
+            &Console::default(),
+        )
+        .await;
+        stats.record(&path, outcome, &args);
+        emit(ProgressEvent::FileFinished {
+            path: &path,
+            outcome: outcome.as_str(),
+            counts: stats.progress_counts(),
+        });
+        emit(ProgressEvent::Finished {
+            counts: stats.progress_counts(),
+            stopped: None,
+        });
+    } else if path.is_dir() {
+        let budget = Budget::new(&args);
+        // In path order the walk yields tracks in the order they're processed, so the library is
+        // walked once to count them and again as workers free up, without listing it whole.
+        // Other orders need every path up front to sort or shuffle them
+        let listed = {
+            let state = state.lock().await;
+            match args.order {
+                ProcessingOrder::Path => AudioFiles::new(&path, &args).and_then(|walk| {
+                    let total = walk
+                        .filter(|file_path| is_wanted(file_path, &args, &state))
+                        .count();
+                    let walk: Box<dyn Iterator<Item = PathBuf>> =
+                        Box::new(AudioFiles::new(&path, &args)?.quiet());
+                    Ok((total, walk))
+                }),
+                _ => process_directory(&path, &args).map(|mut audio_files| {
+                    audio_files.retain(|file_path| is_wanted(file_path, &args, &state));
+                    let total = audio_files.len();
+                    let walk: Box<dyn Iterator<Item = PathBuf>> = Box::new(audio_files.into_iter());
+                    (total, walk)
+                }),
+            }
+        };
+        match listed {
+            Ok((total, audio_files)) => {
+                println!(
+                    "{} {}",
+                    "Found:".green().bold(),
+                    format!("{} audio files", total).bright_cyan()
+                );
+
+                emit(ProgressEvent::Started { total });
+                if total == 0 {
+                    println!("{}", "No audio files found.".yellow());
+                    emit(ProgressEvent::Finished {
+                        counts: ProcessingStats::new(0).progress_counts(),
+                        stopped: None,
+                    });
+                    return;
+                }
+
+                let concurrent_limit = args.concurrency();
+                let mut eta = Eta::new();
+
+                // Create progress bar
+                let progress = progress_bar(total);
+                progress.set_message("Processing audio files...");
+
+                // Only the tracks actually processed are kept, and only for a manifest
+                let mut processed_files = Vec::new();
+                let mut stats = ProcessingStats::new(total);
+                let table = args.summary == SummaryFormat::Table;
+                if args.report.is_some() || table {
+                    stats.keep_outcomes();
+                }
+                let console = if table {
+                    Console::with_progress(&progress).keeping_notes()
+                } else {
+                    Console::with_progress(&progress)
+                };
+
+                stream::iter(audio_files)
+                    // Checked as each file comes up, as the path-order walk isn't filtered ahead
+                    .filter_map(|file_path| {
+                        let state = state.clone();
+                        let args = &args;
+                        async move {
+                            is_wanted(&file_path, args, &*state.lock().await).then_some(file_path)
+                        }
+                    })
+                    // Files are only pulled in as workers free up, so this stops new lookups
+                    // as soon as the budget runs out
+                    .take_while(|_| future::ready(budget.exhausted().is_none()))
+                    .map(|file_path| {
+                        let args_clone = args.clone();
+                        let state_clone = state.clone();
+                        let console_clone = console.clone();
+                        let provider_clone = provider.clone();
+                        let throttle_clone = throttle.clone();
+                        let events_clone = events.clone();
+                        async move {
+                            if let Some(events) = &events_clone {
+                                events.emit(ProgressEvent::FileStarted { path: &file_path });
+                            }
+                            let mut timings = FileTimings::default();
+                            let outcome = process_file_timed(
+                                &file_path,
+                                &args_clone,
+                                provider_clone.as_ref(),
+                                &throttle_clone,
+                                state_clone.clone(),
+                                &console_clone,
+                                &mut timings,
+                            )
+                            .await;
+                            if args_clone.verbose {
+                                console_clone.println(format!(
+                                    "{} {}",
+                                    "Timing:".bright_black().bold(),
+                                    format!("{}: {}", file_path.display(), timings).bright_black()
+                                ));
+                            }
+                            (file_path, outcome, timings)
+                        }
+                    })
+                    .buffer_unordered(concurrent_limit)
+                    // Outcomes are tallied here, one at a time, so workers never contend for the stats
+                    .for_each(|(file_path, outcome, timings)| {
+                        stats.record(&file_path, outcome, &args);
+                        emit(ProgressEvent::FileFinished {
+                            path: &file_path,
+                            outcome: outcome.as_str(),
+                            counts: stats.progress_counts(),
+                        });
+                        eta.record(timings.lookup.is_some());
+                        if let Some(remaining) = eta.remaining(total - eta.finished) {
+                            progress.set_prefix(format_eta(remaining));
+                        }
+                        if args.verbose {
+                            stats.timings.push(timings);
+                        }
+                        if args.manifest.is_some() {
+                            processed_files.push(file_path);
+                        }
+                        progress.inc(1);
+                        future::ready(())
+                    })
+                    .await;
+
+                progress.finish_with_message("Processing complete!");
+
+                let stopped = budget.exhausted().filter(|_| stats.unprocessed() > 0);
+                emit(ProgressEvent::Finished {
+                    counts: stats.progress_counts(),
+                    stopped: stopped.clone(),
+                });
+                if let Some(reason) = stopped {
+                    println!(
+                        "{} {}",
+                        "Stopped:".yellow().bold(),
+                        format!("{}; run again to continue where this run left off", reason)
+                            .yellow()
+                    );
+                }
+
+                if table {
+                    stats.display_table(&console.take_notes(), &args);
+                }
+                stats.display_summary();
+                if args.verbose {
+                    stats.display_timings();
+                }
+                if args.recursive && !table {
+                    stats.display_album_summary();
+                }
+                if let Some(report) = &args.report
+                    && let Err(e) = stats.write_report(report, &*state.lock().await)
+                {
+                    eprintln!(
+                        "{} {}",
+                        "Error:".red().bold(),
+                        format!("Failed to write report {}: {}", report.display(), e).red()
+                    );
+                }
+                if let Some(manifest) = &args.manifest {
+                    write_manifest(manifest, &path, &processed_files, &args);
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    "Error:".red().bold(),
+                    format!("Error collecting tracks from {}: {}", path.display(), e).red()
+                );
+                std::process::exit(1);
+            }
+        }
+    } else {
+        eprintln!(
+            "{} {}",
+            "Error:".red().bold(),
+            format!(
+                "Path does not exist or is not a file or directory: {}",
+                path.display()
+            )
+            .red()
+        );
+        std::process::exit(1);
+    }
+
+    save_state(&state, &args).await;
+}
+
+/// Running estimate of the time left, from how fast the tracks that needed a lookup went.
+/// Tracks skipped without one take next to no time, so they only count towards the share of
+/// the remaining tracks expected to need a lookup
+struct Eta {
+    started: Instant,
+    finished: usize,
+    looked_up: usize,
+}
+
+impl Eta {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            finished: 0,
+            looked_up: 0,
+        }
+    }
+
+    fn record(&mut self, looked_up: bool) {
+        self.finished += 1;
+        self.looked_up += usize::from(looked_up);
+    }
+
+    /// Time left for `left` more tracks; `None` until a lookup has finished
+    fn remaining(&self, left: usize) -> Option<Duration> {
+        if self.looked_up == 0 {
+            return None;
+        }
+        let per_lookup = self.started.elapsed() / self.looked_up as u32;
+        let lookups_left = left * self.looked_up / self.finished;
+        Some(per_lookup * lookups_left as u32)
+    }
+}
+
+/// Formats a remaining-time estimate for the progress bar, e.g. `~42 min left`
+fn format_eta(remaining: Duration) -> String {
+    let minutes = remaining.as_secs().div_ceil(60);
+    match minutes {
+        0 => "<1 min left".to_string(),
+        1..60 => format!("~{} min left", minutes),
+        _ => format!("~{} h {} min left", minutes / 60, minutes % 60),
+    }
+}
+
+/// Fills in options from a config profile, leaving anything given on the command line alone
+fn apply_profile(
+    args: &mut Cli,
+    config: &mut Config,
+    matches: &ArgMatches,
+    profile: Profile,
+) -> Result<(), String> {
+    let unset = |id: &str| {
+        !matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+
+    if profile.music_dir.is_some() {
+        config.music_dir = profile.music_dir;
+    }
+    if let Some(url) = profile.url
+        && unset("url")
+    {
+        args.url = url;
+    }
+    if let Some(state_file) = profile.state_file
+        && unset("state_file")
+    {
+        args.state_file = Some(state_file);
+    }
+    if let Some(output_dir) = profile.output_dir
+        && unset("output_dir")
+    {
+        args.output_dir = Some(output_dir);
+    }
+    if let Some(quote_style) = profile.quote_style
+        && unset("quote_style")
+    {
+        args.quote_style = QuoteStyle::from_str(&quote_style, true)
+            .map_err(|_| format!("invalid quote_style '{}'", quote_style))?;
+    }
+    if let Some(order) = profile.order
+        && unset("order")
+    {
+        args.order = ProcessingOrder::from_str(&order, true)
+            .map_err(|_| format!("invalid order '{}'", order))?;
+    }
+    if unset("filters") {
+        for filter in &profile.filters {
+            args.filters.push(parse_tag_filter(filter)?);
+        }
+    }
+    if unset("providers") && !profile.providers.is_empty() {
+        args.providers = profile.providers;
+    }
+    if unset("resolve") {
+        for resolve in &profile.resolve {
+            args.resolve.push(parse_resolve(resolve)?);
+        }
+    }
+
+    if let Some(file_mode) = profile.file_mode
+        && unset("file_mode")
+    {
+        args.file_mode = Some(parse_file_mode(&file_mode)?);
+    }
+    if let Some(output_filter) = profile.output_filter
+        && unset("output_filter")
+    {
+        args.output_filter = Some(output_filter);
+    }
+    if let Some(ass_font) = profile.ass_font
+        && unset("ass_font")
+    {
+        args.ass_font = ass_font;
+    }
+    if let Some(ass_font_size) = profile.ass_font_size
+        && unset("ass_font_size")
+    {
+        args.ass_font_size = ass_font_size;
+    }
+    for (name, color, arg) in [
+        ("ass_color", profile.ass_color, &mut args.ass_color),
+        (
+            "ass_karaoke_color",
+            profile.ass_karaoke_color,
+            &mut args.ass_karaoke_color,
+        ),
+        (
+            "ass_outline_color",
+            profile.ass_outline_color,
+            &mut args.ass_outline_color,
+        ),
+    ] {
+        if let Some(color) = color
+            && unset(name)
+        {
+            *arg = ass::parse_color(&color)?;
+        }
+    }
+    if let Some(summary) = profile.summary
+        && unset("summary")
+    {
+        args.summary = SummaryFormat::from_str(&summary, true)
+            .map_err(|_| format!("invalid summary '{}'", summary))?;
+    }
+    if let Some(lyrics_type) = profile.lyrics_type
+        && unset("lyrics_type")
+    {
+        args.lyrics_type = LyricsType::from_str(&lyrics_type, true)
+            .map_err(|_| format!("invalid lyrics_type '{}'", lyrics_type))?;
+    }
+    if let Some(ass_position) = profile.ass_position
+        && unset("ass_position")
+    {
+        args.ass_position = SubtitlePosition::from_str(&ass_position, true)
+            .map_err(|_| format!("invalid ass_position '{}'", ass_position))?;
+    }
+    if let Some(lrc_extension) = profile.lrc_extension
+        && unset("lrc_extension")
+    {
+        args.lrc_extension = parse_extension(&lrc_extension)?;
+    }
+    if let Some(txt_extension) = profile.txt_extension
+        && unset("txt_extension")
+    {
+        args.txt_extension = parse_extension(&txt_extension)?;
+    }
+    if let Some(max_runtime) = profile.max_runtime
+        && unset("max_runtime")
+    {
+        args.max_runtime = Some(parse_runtime(&max_runtime)?);
+    }
+
+    if let Some(max_line_length) = profile.max_line_length
+        && unset("max_line_length")
+    {
+        args.max_line_length = Some(max_line_length.max(10));
+    }
+    if let Some(gap_marker) = profile.gap_marker
+        && unset("gap_marker")
+    {
+        args.gap_marker = Some(gap_marker);
+    }
+    if let Some(max_depth) = profile.max_depth
+        && unset("max_depth")
+    {
+        args.max_depth = Some(max_depth);
+    }
+    if let Some(concurrency) = profile.concurrency
+        && unset("concurrency")
+    {
+        if concurrency == 0 {
+            return Err("concurrency must be at least 1".to_string());
+        }
+        args.concurrency = Some(concurrency);
+    }
+    if let Some(rate_limit) = profile.rate_limit
+        && unset("rate_limit")
+    {
+        args.rate_limit = Some(parse_rate_limit(&rate_limit.to_string())?);
+    }
+    if let Some(max_memory) = profile.max_memory
+        && unset("max_memory")
+    {
+        args.max_memory = Some(max_memory);
+    }
+    if let Some(max_requests) = profile.max_requests
+        && unset("max_requests")
+    {
+        args.max_requests = Some(max_requests);
+    }
+    if let Some(min_confidence) = profile.min_confidence
+        && unset("min_confidence")
+    {
+        if min_confidence > 100 {
+            return Err(format!("invalid min_confidence {}", min_confidence));
+        }
+        args.min_confidence = Some(min_confidence);
+    }
+    for (id, value, target) in [
+        ("min_duration", profile.min_duration, &mut args.min_duration),
+        ("max_duration", profile.max_duration, &mut args.max_duration),
+    ] {
+        if value.is_some() && unset(id) {
+            *target = value;
+        }
+    }
+    for (id, value, target) in [
+        ("recursive", profile.recursive, &mut args.recursive),
+        (
+            "override_files",
+            profile.override_files,
+            &mut args.override_files,
+        ),
+        (
+            "follow_symlinks",
+            profile.follow_symlinks,
+            &mut args.follow_symlinks,
+        ),
+        ("cached_only", profile.cached_only, &mut args.cached_only),
+        ("ipv4", profile.ipv4, &mut args.ipv4),
+        ("ipv6", profile.ipv6, &mut args.ipv6),
+        (
+            "preserve_manual",
+            profile.preserve_manual,
+            &mut args.preserve_manual,
+        ),
+        (
+            "normalize_encoding",
+            profile.normalize_encoding,
+            &mut args.normalize_encoding,
+        ),
+        ("bom", profile.bom, &mut args.bom),
+        ("crlf", profile.crlf, &mut args.crlf),
+        (
+            "repair_timing",
+            profile.repair_timing,
+            &mut args.repair_timing,
+        ),
+        (
+            "normalize_text",
+            profile.normalize_text,
+            &mut args.normalize_text,
+        ),
+        ("clean_lyrics", profile.clean_lyrics, &mut args.clean_lyrics),
+        (
+            "censored_copy",
+            profile.censored_copy,
+            &mut args.censored_copy,
+        ),
+        ("split_synced", profile.split_synced, &mut args.split_synced),
+        (
+            "flatten_offset",
+            profile.flatten_offset,
+            &mut args.flatten_offset,
+        ),
+        (
+            "expand_timestamps",
+            profile.expand_timestamps,
+            &mut args.expand_timestamps,
+        ),
+        ("match_mtime", profile.match_mtime, &mut args.match_mtime),
+        ("copy_owner", profile.copy_owner, &mut args.copy_owner),
+        ("fix_tags", profile.fix_tags, &mut args.fix_tags),
+        ("verbose", profile.verbose, &mut args.verbose),
+        ("ass_karaoke", profile.ass_karaoke, &mut args.ass_karaoke),
+        (
+            "sidecar_metadata",
+            profile.sidecar_metadata,
+            &mut args.sidecar_metadata,
+        ),
+    ] {
+        if let Some(value) = value
+            && unset(id)
+        {
+            *target = value;
+        }
+    }
+
+    Ok(())
+}
+
+/// Library to process when no path is given: `LRCPHILE_MUSIC_DIR`, then the config file,
+/// then the platform's music folder
+fn default_music_dir(config: &Config) -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("LRCPHILE_MUSIC_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = &config.music_dir {
+        return Some(dir.clone());
+    }
+    if is_termux() {
+        return termux_music_dir();
+    }
+
+    UserDirs::new()?.audio_dir().map(Path::to_path_buf)
+}
+
+/// Lines `--clean-lyrics` removes from the top of the lyrics unless the config file has its
+/// own `clean_patterns`: songwriting and production credits in English, Chinese, Japanese
+/// and Korean
+const DEFAULT_CLEAN_PATTERNS: [&str; 4] = [
+    r"(?i)^\s*(lyrics|lyricist|words|music|composer|composed|arranger|arranged|producer|produced|written)(\s+by)?\s*[:：]",
+    r"(?i)^\s*lyrics\s+by\s",
+    r"^\s*(作词|作詞|作曲|编曲|編曲|制作人|製作人|监制|監製|混音|和声|和聲)\s*[:：]",
+    r"^\s*(작사|작곡|편곡)\s*[:：]",
+];
+
+/// Words `--censored-copy` masks unless the config file has its own `censor_words`
+const DEFAULT_CENSOR_WORDS: [&str; 16] = [
+    "fuck",
+    "fucks",
+    "fucked",
+    "fucking",
+    "fucker",
+    "motherfucker",
+    "shit",
+    "shitty",
+    "bullshit",
+    "bitch",
+    "bitches",
+    "asshole",
+    "cunt",
+    "dick",
+    "pussy",
+    "bastard",
+];
+
+/// Extensions players recognize synced lyrics by, counted as existing lyrics whichever
+/// `--lrc-extension` is in use
+const LRC_VARIANTS: [&str; 2] = ["lrc", "elrc"];
+
+/// The extension actually used on disk for the `lrc` and `txt` kinds of lyrics file, from
+/// `--lrc-extension` and `--txt-extension`
+fn lyrics_extension<'a>(extension: &'a str, args: &'a Cli) -> &'a str {
+    match extension {
+        "lrc" => &args.lrc_extension,
+        "txt" => &args.txt_extension,
+        _ => extension,
+    }
+}
+
+/// Whether a file extension is one lyrics files are written or recognized with
+fn is_lyrics_extension(extension: &str, args: &Cli) -> bool {
+    LRC_VARIANTS.contains(&extension)
+        || ["txt", &args.lrc_extension, &args.txt_extension].contains(&extension)
+}
+
+/// Extensions a lyrics file of this kind may have been written with before the configured
+/// one, counted as existing lyrics and replaced when lyrics are saved
+fn lyrics_variants(extension: &str) -> &'static [&'static str] {
+    match extension {
+        "lrc" => &LRC_VARIANTS,
+        "txt" => &["txt"],
+        _ => &[],
+    }
+}
+
+/// Whether the track has a lyrics file of this kind, under the configured extension or any
+/// other variant players recognize
+fn lyrics_file_exists(audio_path: &Path, extension: &str, args: &Cli) -> Result<bool> {
+    let path = get_lyrics_file_path(audio_path, extension, args)?;
+    Ok(path.exists()
+        || lyrics_variants(extension)
+            .iter()
+            .any(|variant| path.with_extension(variant).exists()))
+}
+
+/// Removes the track's lyrics files of the same kind left under another extension, once
+/// `lyrics_path` has been written with the configured one, so players don't find both
+fn remove_stale_variants(lyrics_path: &Path, extension: &str) -> Result<()> {
+    for variant in lyrics_variants(extension) {
+        let stale = lyrics_path.with_extension(variant);
+        if stale != lyrics_path && stale.is_file() {
+            fs::remove_file(&stale)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends every lyrics file read or written from now on to `output_dir`, keeping its path
+/// relative to `library`; without a library, or for tracks outside it, files go straight
+/// into `output_dir`
+fn redirect_lyrics(args: &mut Cli, library: Option<&Path>, output_dir: &Path) {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let library = library.map(|library| match library.parent() {
+        Some(parent) if library.is_file() => absolute(parent),
+        _ => absolute(library),
+    });
+    args.lyrics_redirect = Some((library, absolute(output_dir)));
+}
+
+/// Keeps lyrics files inside the library, or the directory they're redirected to, for
+/// `--no-write-outside-root`
+fn confine_writes(args: &mut Cli, library: Option<&Path>) {
+    let root = match (&args.lyrics_redirect, library) {
+        (Some((_, output_dir)), _) => output_dir.clone(),
+        (None, Some(library)) if library.is_file() => {
+            library.parent().unwrap_or(library).to_path_buf()
+        }
+        (None, Some(library)) => library.to_path_buf(),
+        (None, None) => return,
+    };
+    let root =
+        fs::canonicalize(&root).unwrap_or_else(|_| std::path::absolute(&root).unwrap_or(root));
+    args.write_root = Some(root);
+}
+
+/// Options for writing lyrics into a directory outside the library, like a player's, which
+/// `--no-write-outside-root` then keeps the writes inside of
+fn writing_to(args: &Cli, dir: &Path) -> Cli {
+    let mut args = args.clone();
+    if args.no_write_outside_root {
+        args.lyrics_redirect = None;
+        confine_writes(&mut args, Some(dir));
+    }
+    args
+}
+
+/// Refuses a lyrics file that would end up outside the write root once symlinks, in its
+/// directories or the file itself, are followed
+fn ensure_inside_root(target: &Path, args: &Cli) -> Result<()> {
+    let Some(root) = &args.write_root else {
+        return Ok(());
+    };
+    let outside = || LrcphileError::InvalidPath {
+        path: target.to_path_buf(),
+        reason: "resolves to a location outside the library (--no-write-outside-root)",
+    };
+
+    // A symlink is written through, so it's where it points that counts, even if nothing
+    // is there yet
+    let resolved = if target.is_symlink() {
+        fs::canonicalize(target).map_err(|_| outside())?
+    } else {
+        // The file and some of its directories may not exist yet, so the deepest existing
+        // directory is resolved and the rest appended
+        let absolute = std::path::absolute(target)?;
+        let mut missing = Vec::new();
+        let mut existing = absolute.as_path();
+        let resolved = loop {
+            match fs::canonicalize(existing) {
+                Ok(resolved) => break resolved,
+                Err(_) => {
+                    let (Some(parent), Some(name)) = (existing.parent(), existing.file_name())
+                    else {
+                        return Err(outside());
+                    };
+                    missing.push(name);
+                    existing = parent;
+                }
+            }
+        };
+        missing
+            .iter()
+            .rev()
+            .fold(resolved, |path, name| path.join(name))
+    };
+
+    if resolved.starts_with(root) {
+        Ok(())
+    } else {
+        Err(outside())
+    }
+}
+
+/// Asks whether lyrics could be written where they would go, without writing anything there
+fn probe_writable(path: &Path) -> std::io::Result<()> {
+    let dir = if path.is_file() {
+        path.parent().unwrap_or(path)
+    } else {
+        path
+    };
+
+    // access(2) knows about read-only mounts and which user is asking, which the mode bits don't
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir =
+            std::ffi::CString::new(dir.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+        // SAFETY: `dir` is a valid NUL-terminated string that outlives the call
+        if unsafe { libc::access(dir.as_ptr(), libc::W_OK) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(unix))]
+    if fs::metadata(dir)?.permissions().readonly() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "read-only",
+        ));
+    }
+    Ok(())
+}
+
+/// Explains that the library can't be written to and, when someone is there to answer,
+/// offers to write the lyrics to a directory in the user data directory instead
+fn offer_output_dir(library: &Path, error: &std::io::Error) -> Option<PathBuf> {
+    eprintln!(
+        "{} {}",
+        "Error:".red().bold(),
+        format!("Cannot write lyrics to {}: {}", library.display(), error).red()
+    );
+
+    let suggestion = ProjectDirs::from("", "", "lrcphile").map(|dirs| {
+        let name = library.file_name().unwrap_or(library.as_os_str());
+        dirs.data_dir().join("lyrics").join(name)
+    });
+    let Some(suggestion) = suggestion.filter(|_| std::io::stdin().is_terminal()) else {
+        eprintln!(
+            "{}",
+            "Pass --output-dir DIR to write the lyrics files somewhere else.".yellow()
+        );
+        return None;
+    };
+
+    let answer = prompt::ask(&format!(
+        "Write the lyrics to {} instead? [y/N]: ",
+        suggestion.display()
+    ));
+    matches!(answer.as_deref(), Some("y" | "yes")).then_some(suggestion)
+}
+
+/// Whether we're running inside Termux on Android, where the usual user directories don't exist
+fn is_termux() -> bool {
+    std::env::var_os("TERMUX_VERSION").is_some()
+        || std::env::var("PREFIX").is_ok_and(|prefix| prefix.contains("com.termux"))
+}
+
+/// The shared music folder linked into the home directory by `termux-setup-storage`
+fn termux_music_dir() -> Option<PathBuf> {
+    let music_dir = UserDirs::new()?.home_dir().join("storage").join("music");
+    music_dir.is_dir().then_some(music_dir)
+}
+
+/// Records the lyrics files of the run's tracks for `lrcphile check`, relative to the
+/// directory they were written to
+fn write_manifest(manifest_path: &Path, library: &Path, audio_files: &[PathBuf], args: &Cli) {
+    let root = args
+        .lyrics_redirect
+        .as_ref()
+        .map_or(library, |(_, output_dir)| output_dir.as_path());
+    let lyrics_paths = audio_files.iter().flat_map(|audio_path| {
+        ["lrc", "txt"]
+            .into_iter()
+            .filter_map(|extension| get_lyrics_file_path(audio_path, extension, args).ok())
+            .chain(find_instrumental_placeholder(audio_path, args))
+            .filter(|lyrics_path| lyrics_path.is_file())
+            .collect::<Vec<_>>()
+    });
+    let manifest = Manifest::build(root, lyrics_paths);
+    match manifest.save(manifest_path) {
+        Ok(()) => println!(
+            "{} {}",
+            "Manifest:".green().bold(),
+            format!(
+                "{} lyrics files recorded in {}",
+                manifest.files.len(),
+                manifest_path.display()
+            )
+            .bright_green()
+        ),
+        Err(e) => eprintln!(
+            "{} {}",
+            "Error:".red().bold(),
+            format!(
+                "Failed to write manifest {}: {}",
+                manifest_path.display(),
+                e
+            )
+            .red()
+        ),
+    }
+}
+
+fn load_state(args: &Cli) -> Result<StateDb> {
+    match args.state_file.clone().or_else(StateDb::default_location) {
+        Some(location) => StateDb::open(&location),
+        None => Ok(StateDb::default()),
+    }
+}
+
+async fn save_state(state: &Mutex<StateDb>, args: &Cli) {
+    let mut state = state.lock().await;
+    let lookups = args
+        .lookup_stats
+        .lock()
+        .map(|mut stats| std::mem::take(&mut *stats))
+        .unwrap_or_default();
+    state.record_provider_stats(lookups);
+    if let Err(e) = state.save() {
+        eprintln!(
+            "{} {}",
+            "Warning:".yellow().bold(),
+            format!("Failed to save state file: {}", e).yellow()
+        );
+    }
+}
+
+fn is_newer_than(file_path: &Path, date: Option<NaiveDate>) -> bool {
+    let Some(date) = date else {
+        return true;
+    };
+
+    match fs::metadata(file_path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => DateTime::<Local>::from(modified).date_naive() >= date,
+        Err(_) => true,
+    }
+}
+
+/// Whether `--newer-than` and `--changed-only` let a track through
+fn is_wanted(file_path: &Path, args: &Cli, state: &StateDb) -> bool {
+    is_newer_than(file_path, args.newer_than)
+        && !(args.changed_only && state.is_unchanged(file_path))
+}
+
+fn process_directory(dir_path: &Path, args: &Cli) -> Result<Vec<PathBuf>> {
+    // Already in path order, as each directory is listed sorted
+    let mut all_tracks: Vec<PathBuf> = AudioFiles::new(dir_path, args)?.collect();
+    match args.order {
+        ProcessingOrder::Path => {}
+        ProcessingOrder::Mtime => {
+            all_tracks.sort_by_cached_key(|path| std::cmp::Reverse(state::modified_secs(path)))
+        }
+        ProcessingOrder::Random => all_tracks.shuffle(&mut rand::thread_rng()),
+    }
+
+    Ok(all_tracks)
+}
+
+/// Audio files under a directory, found one by one as they're asked for, so a large library
+/// never has to be held in memory as a whole. Each directory's entries are sorted, which
+/// yields the files in path order
+struct AudioFiles<'a> {
+    args: &'a Cli,
+    /// Directories being listed, innermost last
+    listings: Vec<DirListing>,
+    /// Real directories entered so far with `--follow-symlinks`, so symlink loops end
+    visited_dirs: HashSet<PathBuf>,
+    /// Whether skipped directories are reported; off for a walk that repeats an earlier one
+    warn: bool,
+}
+
+/// What is left to look at in one directory
+struct DirListing {
+    entries: std::vec::IntoIter<(PathBuf, fs::FileType)>,
+    depth: usize,
+    /// Ignore files of the directory and its parents, outermost first
+    ignores: Vec<Arc<Gitignore>>,
+}
+
+impl<'a> AudioFiles<'a> {
+    fn new(dir_path: &Path, args: &'a Cli) -> Result<Self> {
+        let mut visited_dirs = HashSet::new();
+        if args.follow_symlinks {
+            visited_dirs.insert(dir_path.canonicalize()?);
+        }
+        let listing = DirListing::read(dir_path, 0, &[])?;
+        Ok(Self {
+            args,
+            listings: vec![listing],
+            visited_dirs,
+            warn: true,
+        })
+    }
+
+    /// Walks without reporting skipped directories again
+    fn quiet(self) -> Self {
+        Self {
+            warn: false,
+            ..self
+        }
+    }
+
+    fn warn(&self, message: String) {
+        if self.warn {
+            eprintln!("{} {}", "Warning:".yellow().bold(), message.yellow());
+        }
+    }
+
+    /// Enters a subdirectory unless it's too deep, or a directory already listed
+    fn enter(&mut self, path: &Path, depth: usize, ignores: &[Arc<Gitignore>]) {
+        if self
+            .args
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth)
+        {
+            return;
+        }
+
+        if self.args.follow_symlinks {
+            // Symlink farms can point back up the tree, so only enter each real directory once
+            match path.canonicalize() {
+                Ok(canonical) => {
+                    if !self.visited_dirs.insert(canonical) {
+                        self.warn(format!(
+                            "Skipping already visited directory {}",
+                            path.display()
+                        ));
+                        return;
+                    }
+                }
+                Err(e) => {
+                    self.warn(format!("Error resolving {}: {}", path.display(), e));
+                    return;
+                }
+            }
+        }
+
+        match DirListing::read(path, depth + 1, ignores) {
+            Ok(listing) => self.listings.push(listing),
+            Err(e) => {
+                self.warn(format!(
+                    "Error reading subdirectory {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        }
+    }
+}
+
+impl Iterator for AudioFiles<'_> {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        loop {
+            let listing = self.listings.last_mut()?;
+            let Some((path, file_type)) = listing.entries.next() else {
+                self.listings.pop();
+                continue;
+            };
+
+            // Symlinks, to audio files as much as to directories, are only followed when asked to
+            if !self.args.follow_symlinks && file_type.is_symlink() {
+                continue;
+            }
+            if is_ignored(&path, path.is_dir(), &listing.ignores) {
+                continue;
+            }
+
+            if path.is_file() {
+                let is_audio = path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                    });
+                if is_audio {
+                    return Some(path);
+                }
+            } else if path.is_dir() && self.args.recursive {
+                let (depth, ignores) = (listing.depth, listing.ignores.clone());
+                self.enter(&path, depth, &ignores);
+            }
+        }
+    }
+}
+
+impl DirListing {
+    fn read(dir_path: &Path, depth: usize, parent_ignores: &[Arc<Gitignore>]) -> Result<Self> {
+        let mut ignores = parent_ignores.to_vec();
+        ignores.extend(load_ignore_file(dir_path).map(Arc::new));
+
+        // Only one directory's names are held at a time per level
+        let mut entries = fs::read_dir(dir_path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((entry.path(), entry.file_type()?))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Self {
+            entries: entries.into_iter(),
+            depth,
+            ignores,
+        })
+    }
+}
+
+fn load_ignore_file(dir_path: &Path) -> Option<Gitignore> {
+    let ignore_path = dir_path.join(IGNORE_FILE_NAME);
+    if !ignore_path.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir_path);
+    if let Some(e) = builder.add(&ignore_path) {
+        eprintln!(
+            "{} {}",
+            "Warning:".yellow().bold(),
+            format!("Error reading {}: {}", ignore_path.display(), e).yellow()
+        );
+    }
+
+    match builder.build() {
+        Ok(gitignore) => Some(gitignore),
+        Err(e) => {
+            eprintln!(
+                "{} {}",
+                "Warning:".yellow().bold(),
+                format!("Invalid patterns in {}: {}", ignore_path.display(), e).yellow()
+            );
+            None
+        }
+    }
+}
+
+/// Checks a path against the ignore files of its directory and all its parents,
+/// letting the deepest matching rule win as in gitignore
+fn is_ignored(path: &Path, is_dir: bool, ignores: &[Arc<Gitignore>]) -> bool {
+    for gitignore in ignores.iter().rev() {
+        match gitignore.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+async fn process_file(
+    file_path: &PathBuf,
+    args: &Cli,
+    provider: &dyn LyricsProvider,
+    throttle: &Throttle,
+    state: Arc<Mutex<StateDb>>,
+    console: &Console,
+) -> FileOutcome {
+    let mut timings = FileTimings::default();
+    process_file_timed(
+        file_path,
+        args,
+        provider,
+        throttle,
+        state,
+        console,
+        &mut timings,
+    )
+    .await
+}
+
+/// Processes a file like `process_file`, noting how long each stage took
+async fn process_file_timed(
+    file_path: &PathBuf,
+    args: &Cli,
+    provider: &dyn LyricsProvider,
+    throttle: &Throttle,
+    state: Arc<Mutex<StateDb>>,
+    console: &Console,
+    timings: &mut FileTimings,
+) -> FileOutcome {
+    let started = Instant::now();
+    let metadata_result = read_metadata_with_overrides(file_path, args).await;
+    timings.tags = Some(started.elapsed());
+    let track = metadata_result.as_ref().ok().cloned();
+    let outcome;
+    match metadata_result {
+        Ok(metadata) if !metadata.matches_filters(args) => {
+            outcome = FileOutcome::Skipped;
+        }
+        Ok(mut metadata) => {
+            // Check if lyrics files already exist
+            let instrumental_placeholder = find_instrumental_placeholder(file_path, args);
+            let lrc_exists = match lyrics_file_exists(file_path, "lrc", args) {
+                Ok(exists) => exists,
+                Err(e) => {
+                    console.error(file_path, format!("Error determining LRC file path: {}", e));
+                    return FileOutcome::Failed;
+                }
+            };
+            let txt_exists = match lyrics_file_exists(file_path, "txt", args) {
+                Ok(exists) => exists,
+                Err(e) => {
+                    console.error(file_path, format!("Error determining TXT file path: {}", e));
+                    return FileOutcome::Failed;
+                }
+            };
+
+            if args.normalize_encoding {
+                for extension in ["lrc", "txt"] {
+                    if let Ok(path) = get_lyrics_file_path(file_path, extension, args)
+                        && path.exists()
+                        && let Err(e) = normalize_lyrics_file(&path, args)
+                    {
+                        console.warning(
+                            file_path,
+                            format!(
+                                "Failed to normalize {} file: {}",
+                                extension.to_uppercase(),
+                                e
+                            ),
+                        );
+                    }
+                }
+            }
+
+            let existing_provider = if args.only_from.is_some() || args.refetch_from.is_some() {
+                existing_lyrics_provider(file_path, args)
+            } else {
+                None
+            };
+            let refetch = args
+                .refetch_from
+                .as_ref()
+                .is_some_and(|provider| existing_provider.as_ref() == Some(provider));
+
+            let should_fetch = if args
+                .only_from
+                .as_ref()
+                .is_some_and(|provider| existing_provider.as_ref() != Some(provider))
+            {
+                false
+            } else if state.lock().await.is_quarantined(file_path) {
+                // Already waiting for review
+                false
+            } else if let Some(placeholder) = &instrumental_placeholder {
+                // An expired placeholder goes away so the track is treated as never fetched
+                is_expired(placeholder, args.instrumental_expiry)
+                    && match fs::remove_file(placeholder) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            console.warning(
+                                file_path,
+                                format!("Failed to remove expired placeholder: {}", e),
+                            );
+                            false
+                        }
+                    }
+            } else if !lrc_exists
+                && !txt_exists
+                && let Some(no_lyrics) = state
+                    .lock()
+                    .await
+                    .get(file_path)
+                    .and_then(|record| record.no_lyrics)
+                    // Misses recorded by an earlier run only hold while --miss-expiry is set
+                    .filter(|no_lyrics| no_lyrics.instrumental || args.miss_expiry.is_some())
+            {
+                // Remembered in the state file instead of with a placeholder, each kind with
+                // its own expiry
+                no_lyrics.is_expired(if no_lyrics.instrumental {
+                    args.instrumental_expiry
+                } else {
+                    args.miss_expiry
+                })
+            } else if lrc_exists || txt_exists {
+                let replace = args.override_files || refetch;
+                // Both checked again when saving; these only spare the lookup
+                if replace && !args.force && is_edited_since_fetch(file_path, &state, args).await {
+                    console.warning(
+                        file_path,
+                        "Keeping lyrics file edited since it was fetched (use --force to override it)",
+                    );
+                    false
+                } else if replace
+                    && args.preserve_manual
+                    && !args.force
+                    && has_manual_lyrics(file_path, args)
+                {
+                    console.warning(
+                        file_path,
+                        "Keeping hand-made lyrics file (use --force to override it)",
+                    );
+                    false
+                } else {
+                    replace
+                }
+            } else {
+                true
+            };
+
+            if !should_fetch {
+                outcome = FileOutcome::Skipped;
+            } else {
+                // Overrides correct a bad match, so the record matched before isn't reused
+                if !args.has_metadata_overrides() {
+                    metadata.lrclib_id =
+                        known_lrclib_id(file_path, Some(&metadata), &state, args).await;
+                }
+                // Refreshing lyrics that are still on disk only needs to ask whether they changed
+                if (lrc_exists || txt_exists) && instrumental_placeholder.is_none() && !args.force {
+                    metadata.validators = state
+                        .lock()
+                        .await
+                        .get(file_path)
+                        .and_then(|record| record.validators.clone());
+                }
+                let started = Instant::now();
+                let fetched = fetch_with_retry(
+                    provider,
+                    &metadata,
+                    throttle,
+                    console,
+                    args.search_fallback,
+                    args.interactive,
+                )
+                .await;
+                timings.lookup = Some(started.elapsed());
+                match fetched {
+                    Ok(lyrics_result) => {
+                        let lyrics_file = prepare_lyrics_file(
+                            &lyrics_result,
+                            args.instrumental_placeholder,
+                            args.lyrics_type,
+                        );
+                        let mut doubts = if lyrics_result.matched == MatchKind::Search {
+                            picker::doubts(&metadata, &lyrics_result)
+                        } else {
+                            Vec::new()
+                        };
+                        // A match the user picked themselves is trusted whatever its score
+                        if let (Some(min_confidence), Some(confidence)) =
+                            (args.min_confidence, lyrics_result.confidence)
+                            && confidence < min_confidence
+                            && lyrics_result.matched != MatchKind::Picked
+                        {
+                            doubts.push(format!(
+                                "confidence {} below --min-confidence {}",
+                                confidence, min_confidence
+                            ));
+                        }
+                        if !doubts.is_empty()
+                            && let Some(lyrics_file) = &lyrics_file
+                        {
+                            // Hold a doubtful match back for review rather than write it
+                            let mut state = state.lock().await;
+                            // Only LRCLIB records have an ID to remember a rejection by
+                            let rejected = state
+                                .get(file_path)
+                                .and_then(|record| record.rejected_lrclib_id)
+                                .is_some_and(|id| lyrics_result.lrclib_id() == Some(id));
+                            if !rejected {
+                                state.quarantine(
+                                    file_path,
+                                    QuarantinedMatch {
+                                        lrclib_id: lyrics_result.lrclib_id(),
+                                        content: lyrics_file.content.clone(),
+                                        extension: lyrics_file.extension.to_string(),
+                                        reason: doubts.join(", "),
+                                    },
+                                );
+                            }
+                            state.record_confidence(file_path, lyrics_result.confidence);
+                            return if rejected {
+                                FileOutcome::Failed
+                            } else {
+                                state.record_processed(file_path);
+                                FileOutcome::Quarantined
+                            };
+                        }
+                        if args.preview
+                            && let Some(lyrics_file) = &lyrics_file
+                            && !tokio::task::block_in_place(|| {
+                                console.suspend(|| {
+                                    confirm_preview(file_path, lyrics_file, args.preview_lines)
+                                })
+                            })
+                        {
+                            // A rejected match isn't remembered, so the next run looks it up afresh
+                            return FileOutcome::Skipped;
+                        }
+
+                        {
+                            let mut state = state.lock().await;
+                            state.record_lrclib_id(file_path, lyrics_result.lrclib_id(), &metadata);
+                            state.record_confidence(file_path, lyrics_result.confidence);
+                        }
+                        if args.fix_tags
+                            && lyrics_result.matched != MatchKind::Exact
+                            && lyrics_file.is_some()
+                        {
+                            match fix_tags(
+                                file_path,
+                                &lyrics_result.track_name,
+                                &lyrics_result.artist_name,
+                                &lyrics_result.album_name,
+                            ) {
+                                Ok(true) => {
+                                    // The record now goes with the corrected tags
+                                    state.lock().await.record_lrclib_id(
+                                        file_path,
+                                        lyrics_result.lrclib_id(),
+                                        // Fields the record left empty weren't retagged
+                                        &TrackMetadata {
+                                            track_name: or_tag(
+                                                &lyrics_result.track_name,
+                                                &metadata.track_name,
+                                            ),
+                                            artist_name: or_tag(
+                                                &lyrics_result.artist_name,
+                                                &metadata.artist_name,
+                                            ),
+                                            album_name: or_tag(
+                                                &lyrics_result.album_name,
+                                                &metadata.album_name,
+                                            ),
+                                            ..metadata.clone()
+                                        },
+                                    );
+                                    console.fixed(
+                                        file_path,
+                                        format!(
+                                            "tagged as {} - {} ({})",
+                                            lyrics_result.artist_name,
+                                            lyrics_result.track_name,
+                                            lyrics_result.album_name
+                                        ),
+                                    )
+                                }
+                                Ok(false) => {}
+                                Err(e) => {
+                                    console.warning(file_path, format!("Failed to fix tags: {}", e))
+                                }
+                            }
+                        }
+                        match lyrics_file {
+                            Some(lyrics_file) => {
+                                let started = Instant::now();
+                                let saved = save_lyrics_file(
+                                    file_path,
+                                    &lyrics_file.content,
+                                    lyrics_file.extension,
+                                    Some(&metadata),
+                                    &state,
+                                    args,
+                                )
+                                .await;
+                                timings.write = Some(started.elapsed());
+                                match saved {
+                                    Ok(lyrics_path) => {
+                                        let mut state = state.lock().await;
+                                        state.record_no_lyrics(file_path, None);
+                                        state.record_lyrics_hash(
+                                            file_path,
+                                            written_lyrics_hash(&lyrics_path, &lyrics_file.content),
+                                        );
+                                        state.record_validators(
+                                            file_path,
+                                            lyrics_result.validators.clone(),
+                                        );
+                                        outcome = FileOutcome::Fetched;
+                                    }
+                                    Err(e) => {
+                                        console.failed(
+                                            file_path,
+                                            format!(
+                                                "Failed to save {}: {}",
+                                                lyrics_file.description, e
+                                            ),
+                                        );
+                                        outcome = FileOutcome::Failed;
+                                    }
+                                }
+                            }
+                            // Instrumental, but no placeholder is wanted
+                            None if lyrics_result.instrumental => {
+                                if args.instrumental_placeholder == InstrumentalPlaceholder::State {
+                                    state.lock().await.record_no_lyrics(file_path, Some(true));
+                                }
+                                outcome = FileOutcome::Skipped;
+                            }
+                            None => {
+                                outcome = FileOutcome::Failed;
+                            }
+                        }
+                    }
+                    Err(LrcphileError::NotFound) => {
+                        if args.miss_expiry.is_some() {
+                            state.lock().await.record_no_lyrics(file_path, Some(false));
+                        }
+                        outcome = FileOutcome::Failed;
+                    }
+                    Err(LrcphileError::NotModified) => {
+                        outcome = FileOutcome::Skipped;
+                    }
+                    Err(e) => {
+                        console.failed(file_path, format!("Failed to fetch lyrics: {}", e));
+                        // Leave the file unrecorded so the next --changed-only run retries it
+                        return FileOutcome::Failed;
+                    }
+                }
+            }
+
+            if args.edit && matches!(outcome, FileOutcome::Fetched | FileOutcome::Skipped) {
+                edit_lyrics_file(file_path, args, console);
+            }
+        }
+        Err(_) => {
+            outcome = FileOutcome::Failed;
+        }
+    }
+
+    let mut state = state.lock().await;
+    if let Some(metadata) = &track {
+        let remembered_instrumental = state
+            .get(file_path)
+            .and_then(|record| record.no_lyrics)
+            .is_some_and(|no_lyrics| no_lyrics.instrumental);
+        let content = lyrics_content(file_path, args).or_else(|| {
+            remembered_instrumental.then(|| LyricsContent {
+                instrumental: true,
+                ..LyricsContent::default()
+            })
+        });
+        state.record_content(file_path, metadata, content);
+    }
+    // A miss is tried again by `--changed-only`, since the lyrics may have been added since
+    if !matches!(outcome, FileOutcome::Failed) {
+        state.record_processed(file_path);
+    }
+    outcome
+}
+
+/// Summarizes the lyrics on disk for a track, for `lrcphile stats`; `None` when it has none
+fn lyrics_content(audio_path: &Path, args: &Cli) -> Option<LyricsContent> {
+    if find_instrumental_placeholder(audio_path, args).is_some() {
+        return Some(LyricsContent {
+            instrumental: true,
+            ..LyricsContent::default()
+        });
+    }
+
+    let (lyrics, synced) = ["lrc", "txt"].into_iter().find_map(|extension| {
+        let lyrics_path = get_lyrics_file_path(audio_path, extension, args).ok()?;
+        let lyrics = read_lyrics_file(&lyrics_path).ok()?;
+        Some(match extension {
+            "lrc" => {
+                let lrc = Lrc::parse(&lyrics);
+                (lrc.to_plain_text(), lrc.is_synced())
+            }
+            _ => (lyrics, false),
+        })
+    })?;
+    Some(LyricsContent {
+        synced,
+        instrumental: false,
+        lines: lyrics
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count(),
+        language: whatlang::detect(&lyrics)
+            .filter(whatlang::Info::is_reliable)
+            .map(|info| info.lang().code().to_string()),
+    })
+}
+
+/// Opens the track's lyrics file in the user's editor for `--edit`, writing back what they
+/// saved once it reads as LRC or they choose to keep it anyway
+fn edit_lyrics_file(file_path: &Path, args: &Cli, console: &Console) {
+    let Some((lyrics_path, extension)) = ["lrc", "txt"].into_iter().find_map(|extension| {
+        let path = get_lyrics_file_path(file_path, extension, args).ok()?;
+        path.exists().then_some((path, extension))
+    }) else {
+        return;
+    };
+    if is_instrumental_lrc_file(&lyrics_path) {
+        return;
+    }
+
+    let edited = tokio::task::block_in_place(|| {
+        console.suspend(|| -> Result<bool> {
+            let content = read_lyrics_file(&lyrics_path)?;
+            match editor::edit_lyrics(&content, extension)? {
+                Some(edited) if edited != content => {
+                    write_lyrics_file(&lyrics_path, encode_lyrics(&edited, args), args)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })
+    });
+    match edited {
+        Ok(true) => console.println(format!(
+            "{} {}",
+            "Edited:".green().bold(),
+            lyrics_path.display().to_string().bright_green()
+        )),
+        Ok(false) => {}
+        Err(e) => console.warning(file_path, format!("Failed to edit lyrics: {}", e)),
+    }
+}
+
+/// Lyrics ready to be written next to an audio file
+struct LyricsFile {
+    content: String,
+    extension: &'static str,
+    description: &'static str,
+}
+
+/// Picks what to write for a lookup result: an instrumental placeholder, synced lyrics,
+/// or plain lyrics, in that order of preference unless `lyrics_type` narrows it
+fn prepare_lyrics_file(
+    lyrics_result: &LyricsResponse,
+    placeholder: InstrumentalPlaceholder,
+    lyrics_type: LyricsType,
+) -> Option<LyricsFile> {
+    let header = lyrics_result.generate_header();
+    if lyrics_result.instrumental {
+        // Write a placeholder with an instrumental tag to avoid refetching
+        match placeholder {
+            InstrumentalPlaceholder::Lrc => Some(LyricsFile {
+                content: format!("{}\n[instrumental]", header),
+                extension: "lrc",
+                description: "instrumental LRC file",
+            }),
+            InstrumentalPlaceholder::File => Some(LyricsFile {
+                content: format!("{}\n[instrumental]", header),
+                extension: INSTRUMENTAL_EXTENSION,
+                description: "instrumental placeholder",
+            }),
+            InstrumentalPlaceholder::None | InstrumentalPlaceholder::State => None,
+        }
+    } else if lyrics_type == LyricsType::Plain {
+        let plain_lyrics = lyrics_result.plain_lyrics.clone().or_else(|| {
+            lyrics_result
+                .synced_lyrics
+                .as_deref()
+                .map(lrc::to_plain_text)
+        })?;
+        Some(LyricsFile {
+            content: format!("{}\n{}", header, plain_lyrics),
+            extension: "txt",
+            description: "TXT file",
+        })
+    } else if let Some(synced_lyrics) = &lyrics_result.synced_lyrics {
+        // Save synced lyrics to a .lrc file
+        Some(LyricsFile {
+            content: format!("{}\n{}", header, synced_lyrics),
+            extension: "lrc",
+            description: "LRC file",
+        })
+    } else if lyrics_type == LyricsType::Synced {
+        None
+    } else {
+        // Only save plain lyrics to a .txt file
+        lyrics_result
+            .plain_lyrics
+            .as_ref()
+            .map(|plain_lyrics| LyricsFile {
+                content: format!("{}\n{}", header, plain_lyrics),
+                extension: "txt",
+                description: "TXT file",
+            })
+    }
+}
+
+/// Shows the start of the lyrics about to be written and asks whether to write them
+fn confirm_preview(file_path: &Path, lyrics_file: &LyricsFile, lines: usize) -> bool {
+    println!(
+        "\n{} {}",
+        format!("{}:", lyrics_file.description).bright_cyan().bold(),
+        file_path.display().to_string().bright_white()
+    );
+    for line in lyrics_file.content.lines().take(lines) {
+        println!("  {}", line);
+    }
+
+    matches!(
+        prompt::ask("Save these lyrics? [y/N]: ").as_deref(),
+        Some("y" | "yes")
+    )
+}
+
+/// Finds the LRCLIB ID a track was matched to before, from the state file or the
+/// header of its existing LRC file
+async fn known_lrclib_id(
+    file_path: &Path,
+    metadata: Option<&TrackMetadata>,
+    state: &Mutex<StateDb>,
+    args: &Cli,
+) -> Option<u64> {
+    {
+        let state = state.lock().await;
+        if state
+            .get(file_path)
+            .is_some_and(|record| record.lrclib_id.is_some())
+        {
+            // A record matched with other tags is dropped rather than looked for in the file
+            return state.lrclib_id(file_path, metadata);
+        }
+    }
+
+    let lrc_path = get_lyrics_file_path(file_path, "lrc", args).ok()?;
+    let content = read_lyrics_file(&lrc_path).ok()?;
+    // The header names the record's title and artist, which still match the tags unless
+    // the track was retagged
+    let same = |tag: &str, value: &str| {
+        read_header_tag(&content, tag).is_some_and(|tag| tag.eq_ignore_ascii_case(value.trim()))
+    };
+    if let Some(metadata) = metadata
+        && !(same("ti", &metadata.track_name) && same("ar", &metadata.artist_name))
+    {
+        return None;
+    }
+    read_header_tag(&content, "lrclib_id")?.parse().ok()
+}
+
+/// Fingerprint of lyrics text that survives re-encoding and line ending changes
+fn lyrics_hash(content: &str) -> String {
+    Sha256::digest(content.replace("\r\n", "\n"))
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hash of a lyrics file as it was written, after rearranging and `--output-filter`, so
+/// only later hand edits count as changes; `content` stands in if it can't be read back
+fn written_lyrics_hash(lyrics_path: &Path, content: &str) -> String {
+    match read_lyrics_file(lyrics_path) {
+        Ok(written) => lyrics_hash(&written),
+        Err(_) => lyrics_hash(content),
+    }
+}
+
+/// Whether the lyrics file lrcphile wrote for the track was changed by hand afterwards,
+/// judged by the hash recorded with every write whether or not the file has the
+/// `[by: lrcphile]` tag, which an edit may well have removed
+async fn is_edited_since_fetch(audio_path: &Path, state: &Mutex<StateDb>, args: &Cli) -> bool {
+    let Some(written_hash) = state
+        .lock()
+        .await
+        .get(audio_path)
+        .and_then(|record| record.lyrics_hash.clone())
+    else {
+        return false;
+    };
+
+    let files: Vec<String> = ["lrc", "txt"]
+        .into_iter()
+        .filter_map(|extension| get_lyrics_file_path(audio_path, extension, args).ok())
+        .filter_map(|path| read_lyrics_file(&path).ok())
+        .collect();
+    !files.is_empty()
+        && !files
+            .iter()
+            .any(|content| lyrics_hash(content) == written_hash)
+}
+
+/// Whether the track has a lyrics file that lrcphile didn't write, such as a hand-synced LRC
+fn has_manual_lyrics(audio_path: &Path, args: &Cli) -> bool {
+    ["lrc", "txt"]
+        .into_iter()
+        .filter_map(|extension| get_lyrics_file_path(audio_path, extension, args).ok())
+        .filter(|path| path.exists())
+        .any(|path| {
+            read_lyrics_file(&path).is_ok_and(|content| !content.contains("[by: lrcphile]"))
+        })
+}
+
+/// Which provider a track's existing lyrics file was fetched from, according to its
+/// provenance comment; files from before provenance was recorded came from LRCLIB
+fn existing_lyrics_provider(audio_path: &Path, args: &Cli) -> Option<String> {
+    ["lrc", "txt", INSTRUMENTAL_EXTENSION]
+        .into_iter()
+        .filter_map(|extension| get_lyrics_file_path(audio_path, extension, args).ok())
+        .filter(|path| path.exists())
+        .find_map(|path| {
+            let content = read_lyrics_file(&path).ok()?;
+            let provenance = read_header_tag(&content, "#").and_then(|comment| {
+                comment
+                    .split_whitespace()
+                    .find_map(|field| field.strip_prefix("provider="))
+            });
+            match provenance {
+                Some(provider) => Some(provider.to_string()),
+                None => read_header_tag(&content, "lrclib_id").map(|_| LRCLIB_PROVIDER.to_string()),
+            }
+        })
+}
+
+/// The LRC file a command was pointed at: the path itself if it is a lyrics file, or else the
+/// LRC file of the audio file
+fn find_lrc_file(path: &Path, args: &Cli) -> Result<PathBuf> {
+    let lrc_path = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if is_lyrics_extension(extension, args) => path.to_path_buf(),
+        _ => get_lyrics_file_path(path, "lrc", args)?,
+    };
+    if !lrc_path.exists() {
+        return Err(LrcphileError::Metadata(format!(
+            "No LRC file at {}",
+            lrc_path.display()
+        )));
+    }
+    Ok(lrc_path)
+}
+
+/// Reads the value of an LRC header tag such as `[ar: Artist]`
+fn read_header_tag<'a>(content: &'a str, tag: &str) -> Option<&'a str> {
+    content.lines().find_map(|line| {
+        let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+        let (name, value) = inner.split_once(':')?;
+        (name.trim() == tag).then(|| value.trim())
+    })
+}
+
+/// Fetches lyrics, with `search` falling back to a search when there is no exact match, and
+/// rates how confident the match is. With `interactive`, the user picks between several
+/// plausible search results instead of taking the closest one
+async fn fetch_with_retry(
+    provider: &dyn LyricsProvider,
+    metadata: &TrackMetadata,
+    throttle: &Throttle,
+    console: &Console,
+    search: bool,
+    interactive: bool,
+) -> Result<LyricsResponse> {
+    let mut lyrics = match with_retry(throttle, console, || provider.fetch(metadata)).await {
+        Err(LrcphileError::NotFound) if search || interactive => {
+            search_with_retry(provider, metadata, throttle, console, interactive).await?
+        }
+        result => result?,
+    };
+    lyrics.confidence = Some(picker::confidence(metadata, &lyrics));
+    Ok(lyrics)
+}
+
+/// Search fallback of `fetch_with_retry`, taking the best plausible candidate
+async fn search_with_retry(
+    provider: &dyn LyricsProvider,
+    metadata: &TrackMetadata,
+    throttle: &Throttle,
+    console: &Console,
+    interactive: bool,
+) -> Result<LyricsResponse> {
+    let results = with_retry(throttle, console, || provider.search(metadata)).await?;
+    let mut candidates = picker::plausible_candidates(metadata, results);
+    let choice = match candidates.len() {
+        0 => None,
+        1 => Some((0, MatchKind::Search)),
+        _ if interactive => {
+            tokio::task::block_in_place(|| console.suspend(|| picker::pick(metadata, &candidates)))
+                .map(|index| (index, MatchKind::Picked))
+        }
+        _ => Some((0, MatchKind::Search)),
+    };
+
+    choice
+        .map(|(index, matched)| LyricsResponse {
+            matched,
+            namesakes: picker::namesakes(metadata, &candidates, index),
+            ..candidates.swap_remove(index)
+        })
+        .ok_or(LrcphileError::NotFound)
+}
+
+/// Runs a provider request, pausing the whole pipeline and retrying when the provider rate limits us
+async fn with_retry<'a, T>(
+    throttle: &Throttle,
+    console: &Console,
+    request: impl Fn() -> BoxFuture<'a, Result<T>>,
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        if throttle.wait().await {
+            console.set_status("Processing audio files...".to_string());
+        }
+
+        match request().await {
+            Err(LrcphileError::RateLimited { retry_after }) if retries < MAX_RATE_LIMIT_RETRIES => {
+                retries += 1;
+                // Without a hint from the server, wait longer after each refusal
+                let wait = retry_after.unwrap_or(DEFAULT_RETRY_AFTER * 2u32.pow(retries - 1));
+                console.set_status(format!("Rate limited, waiting {}s...", wait.as_secs()));
+                throttle.pause_for(wait).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn read_metadata(file_path: &PathBuf, args: &Cli) -> Result<TrackMetadata> {
+    let tagged_file = Probe::open(file_path)?.read()?;
+    let tag = tagged_file.primary_tag();
+    let mut track_name = tag.and_then(|tag| tag.title()).map(|s| s.to_string());
+    let mut artist_name = tag.and_then(|tag| tag.artist()).map(|s| s.to_string());
+    let mut album_name = tag.and_then(|tag| tag.album()).map(|s| s.to_string());
+    let genre = tag.and_then(|tag| tag.genre()).map(|s| s.to_string());
+    let disc_number = tag.and_then(|tag| tag.disk());
+    let track_number = tag.and_then(|tag| tag.track());
+    let duration = tagged_file.properties().duration().as_secs() as f64;
+
+    if args.sidecar_metadata
+        && (track_name.is_none() || artist_name.is_none() || album_name.is_none())
+        && let Some(sidecar) = read_sidecar_metadata(file_path)
+    {
+        track_name = track_name.or(sidecar.title);
+        artist_name = artist_name.or(sidecar.artist);
+        album_name = album_name.or(sidecar.album);
+    }
+
+    // Return metadata for potential lyrics fetching
+    if let (Some(track_name), Some(artist_name), Some(album_name)) =
+        (track_name, artist_name, album_name)
+    {
+        return Ok(TrackMetadata {
+            track_name,
+            artist_name,
+            album_name,
+            genre,
+            duration,
+            disc_number,
+            track_number,
+            lrclib_id: None,
+            validators: None,
+        });
+    }
+
+    Err(LrcphileError::Metadata(
+        "Missing required metadata (title, artist, or album)".to_string(),
+    ))
+}
+
+/// Names a media server keeps next to an audio file instead of in its tags
+#[derive(Default)]
+struct SidecarMetadata {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+}
+
+/// Reads the first sidecar found beside the audio file: a Kodi-style `NAME.nfo`, or a
+/// `NAME.json` or yt-dlp's `NAME.info.json`
+fn read_sidecar_metadata(file_path: &Path) -> Option<SidecarMetadata> {
+    let nfo_path = file_path.with_extension("nfo");
+    if let Ok(nfo) = fs::read_to_string(&nfo_path) {
+        let element = |name: &str| {
+            let pattern = Regex::new(&format!(r"(?s)<{0}>\s*(.*?)\s*</{0}>", name)).ok()?;
+            let value = pattern.captures(&nfo)?.get(1)?.as_str();
+            let value = value
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&");
+            (!value.is_empty()).then_some(value)
+        };
+        return Some(SidecarMetadata {
+            title: element("title"),
+            artist: element("artist"),
+            album: element("album"),
+        });
+    }
+
+    for json_path in [
+        file_path.with_extension("json"),
+        file_path.with_extension("info.json"),
+    ] {
+        let Ok(json) = fs::read_to_string(&json_path) else {
+            continue;
+        };
+        // `NAME.json` may be something else entirely, so yt-dlp's `NAME.info.json` is still tried
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&json) else {
+            continue;
+        };
+        // yt-dlp puts the song title in `track` and the video title in `title`
+        let field = |names: &[&str]| {
+            names.iter().find_map(|name| match json.get(name)? {
+                serde_json::Value::String(value) if !value.trim().is_empty() => {
+                    Some(value.trim().to_string())
+                }
+                serde_json::Value::Array(values) => {
+                    let values: Vec<&str> = values
+                        .iter()
+                        .filter_map(serde_json::Value::as_str)
+                        .collect();
+                    (!values.is_empty()).then(|| values.join(", "))
+                }
+                _ => None,
+            })
+        };
+        let sidecar = SidecarMetadata {
+            title: field(&["track", "title"]),
+            artist: field(&["artist", "artists", "album_artist", "creator"]),
+            album: field(&["album"]),
+        };
+        if sidecar.title.is_some() || sidecar.artist.is_some() || sidecar.album.is_some() {
+            return Some(sidecar);
+        }
+    }
+    None
+}
+
+/// Reads the track's tags, with any `--set-*` overrides taking their place; a file whose tags
+/// are too incomplete to read can still be looked up when the overrides fill every gap
+async fn read_metadata_with_overrides(file_path: &PathBuf, args: &Cli) -> Result<TrackMetadata> {
+    let mut metadata = match read_metadata(file_path, args).await {
+        Ok(metadata) => metadata,
+        Err(e) if !args.has_metadata_overrides() => return Err(e),
+        Err(e) => {
+            let (Some(_), Some(_), Some(_)) = (&args.set_title, &args.set_artist, &args.set_album)
+            else {
+                return Err(e);
+            };
+            let tagged_file = Probe::open(file_path)?.read()?;
+            TrackMetadata {
+                track_name: String::new(),
+                artist_name: String::new(),
+                album_name: String::new(),
+                genre: None,
+                duration: tagged_file.properties().duration().as_secs() as f64,
+                disc_number: None,
+                track_number: None,
+                lrclib_id: None,
+                validators: None,
+            }
+        }
+    };
+
+    if let Some(title) = &args.set_title {
+        metadata.track_name = title.clone();
+    }
+    if let Some(artist) = &args.set_artist {
+        metadata.artist_name = artist.clone();
+    }
+    if let Some(album) = &args.set_album {
+        metadata.album_name = album.clone();
+    }
+    if let Some(duration) = args.set_duration {
+        metadata.duration = duration;
+    }
+    Ok(metadata)
+}
+
+/// A record's value for a tag, or the track's own where the record left it empty
+fn or_tag(record: &str, track: &str) -> String {
+    if record.trim().is_empty() {
+        track.to_string()
+    } else {
+        record.to_string()
+    }
+}
+
+/// Writes the names of the matched record into the audio file's primary tag, returning
+/// whether anything changed
+fn fix_tags(file_path: &Path, title: &str, artist: &str, album: &str) -> Result<bool> {
+    let mut tagged_file = Probe::open(file_path)?.read()?;
+    let Some(tag) = tagged_file.primary_tag_mut() else {
+        return Ok(false);
+    };
+
+    let differs = |current: Option<Cow<'_, str>>, canonical: &str| {
+        current.as_deref() != Some(canonical) && !canonical.trim().is_empty()
+    };
+    let mut changed = false;
+    if differs(tag.title(), title) {
+        tag.set_title(title.to_string());
+        changed = true;
+    }
+    if differs(tag.artist(), artist) {
+        tag.set_artist(artist.to_string());
+        changed = true;
+    }
+    if differs(tag.album(), album) {
+        tag.set_album(album.to_string());
+        changed = true;
+    }
+
+    if changed {
+        tag.save_to_path(file_path, WriteOptions::default())?;
+    }
+    Ok(changed)
+}
+
+/// Reads lyrics stored in the audio file's own tags, if any
+fn read_embedded_lyrics(file_path: &Path) -> Result<Option<String>> {
+    let tagged_file = Probe::open(file_path)?.read()?;
+    Ok(tagged_file
+        .tags()
+        .iter()
+        .find_map(|tag| tag.get_string(&ItemKey::Lyrics))
+        .map(str::to_string))
+}
+
+/// Removes the lyrics stored in the audio file's tags, returning whether there were any;
+/// tags without lyrics are left untouched
+fn remove_embedded_lyrics(file_path: &Path) -> Result<bool> {
+    let mut tagged_file = Probe::open(file_path)?.read()?;
+    // MP3s' ID3v2 tags also hold synced lyrics in SYLT frames, which the generic tag doesn't
+    // carry, so they're edited frame by frame
+    let id3v2_frames = tagged_file.file_type() == FileType::Mpeg;
+    let mut removed = id3v2_frames && remove_id3v2_lyrics(file_path)?;
+
+    let with_lyrics: Vec<TagType> = tagged_file
+        .tags()
+        .iter()
+        .filter(|tag| !(id3v2_frames && tag.tag_type() == TagType::Id3v2))
+        .filter(|tag| tag.get_string(&ItemKey::Lyrics).is_some())
+        .map(|tag| tag.tag_type())
+        .collect();
+
+    for tag_type in with_lyrics {
+        if let Some(tag) = tagged_file.tag_mut(tag_type) {
+            tag.remove_key(&ItemKey::Lyrics);
+            tag.save_to_path(file_path, WriteOptions::default())?;
+            removed = true;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the USLT and SYLT frames of an MP3's ID3v2 tag, leaving its other frames as they
+/// are; false when it has neither
+fn remove_id3v2_lyrics(file_path: &Path) -> Result<bool> {
+    let mut mpeg_file = MpegFile::read_from(&mut fs::File::open(file_path)?, ParseOptions::new())?;
+    let Some(tag) = mpeg_file.id3v2_mut() else {
+        return Ok(false);
+    };
+
+    let removed: usize = ["USLT", "SYLT"]
+        .into_iter()
+        .map(|id| tag.remove(&FrameId::Valid(Cow::Borrowed(id))).count())
+        .sum();
+    if removed > 0 {
+        tag.save_to_path(file_path, WriteOptions::default())?;
+    }
+    Ok(removed > 0)
+}
+
+fn get_lyrics_file_path(audio_file_path: &Path, extension: &str, args: &Cli) -> Result<PathBuf> {
+    let audio_dir = audio_file_path
+        .parent()
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: audio_file_path.to_path_buf(),
+            reason: "Could not determine parent directory",
+        })?;
+
+    let file_stem = audio_file_path
+        .file_stem()
+        .ok_or_else(|| LrcphileError::InvalidPath {
+            path: audio_file_path.to_path_buf(),
+            reason: "Could not determine file name",
+        })?;
+
+    let mut file_stem = file_stem.to_string_lossy();
+    if is_termux() || cfg!(windows) {
+        // Shared storage on Android is FAT-like and rejects characters other filesystems allow,
+        // as does Windows for names that came over from a Linux share
+        file_stem = sanitize_file_name(&file_stem).into();
+    }
+    if cfg!(windows) {
+        file_stem = windows_safe_name(&file_stem).into_owned().into();
+    }
+
+    let mut lyrics_path = match &args.lyrics_redirect {
+        Some((library, output_dir)) => {
+            let audio_dir = std::path::absolute(audio_dir).unwrap_or_else(|_| audio_dir.into());
+            let relative = library
+                .as_ref()
+                .and_then(|library| audio_dir.strip_prefix(library).ok())
+                .unwrap_or(Path::new(""));
+            if cfg!(windows) {
+                // The mirrored folders are created anew, so their names must suit Windows too
+                relative.components().fold(
+                    output_dir.clone(),
+                    |mirrored, component| match component {
+                        std::path::Component::Normal(name) => {
+                            mirrored.join(&*windows_safe_name(&name.to_string_lossy()))
+                        }
+                        component => mirrored.join(component),
+                    },
+                )
+            } else {
+                output_dir.join(relative)
+            }
+        }
+        None => audio_dir.to_path_buf(),
+    };
+    lyrics_path.push(format!(
+        "{}.{}",
+        file_stem,
+        lyrics_extension(extension, args)
+    ));
+
+    Ok(extended_length_path(lyrics_path))
+}
+
+/// Replaces characters that FAT and exFAT filesystems don't allow in file names
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Device names Windows reserves in every directory, whatever the extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a name Windows can create: reserved device names like `CON` or `aux.live` get an
+/// underscore, and trailing dots and spaces, which Windows silently drops, become underscores
+/// so the name doesn't turn into another one or into nothing
+fn windows_safe_name(name: &str) -> Cow<'_, str> {
+    let trimmed = name.trim_end_matches(['.', ' ']);
+    let mut safe = if trimmed.len() < name.len() {
+        Cow::Owned(format!(
+            "{}{}",
+            trimmed,
+            "_".repeat(name[trimmed.len()..].chars().count())
+        ))
+    } else {
+        Cow::Borrowed(name)
+    };
+
+    let base = safe.split('.').next().unwrap_or("").trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| base.eq_ignore_ascii_case(reserved))
+    {
+        safe = Cow::Owned(format!("{}_{}", base, &safe[base.len()..]));
+    }
+    safe
+}
+
+/// Windows file APIs reject paths longer than this unless they use the `\\?\` prefix
+#[cfg(windows)]
+const LONG_PATH_THRESHOLD: usize = 248;
+
+/// Gives deep paths on Windows the `\\?\` (or `\\?\UNC\` for network shares) prefix so they
+/// can be written past the legacy `MAX_PATH` limit
+#[cfg(windows)]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    // Verbatim paths skip normalization, so resolve `.`, `..` and `/` first
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    let Some(text) = absolute.to_str() else {
+        return path;
+    };
+    if text.len() < LONG_PATH_THRESHOLD || text.starts_with(r"\\?\") {
+        return path;
+    }
+
+    match text.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{}", share)),
+        None => PathBuf::from(format!(r"\\?\{}", text)),
+    }
+}
+
+#[cfg(not(windows))]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// Finds the file marking a track as instrumental, in either placeholder style
+fn find_instrumental_placeholder(audio_path: &Path, args: &Cli) -> Option<PathBuf> {
+    if let Ok(lrc_path) = get_lyrics_file_path(audio_path, "lrc", args)
+        && is_instrumental_lrc_file(&lrc_path)
+    {
+        return Some(lrc_path);
+    }
+
+    let marker_path = get_lyrics_file_path(audio_path, INSTRUMENTAL_EXTENSION, args).ok()?;
+    marker_path.exists().then_some(marker_path)
+}
+
+/// Whether a placeholder was written more than `expiry_days` days ago
+fn is_expired(placeholder: &Path, expiry_days: Option<u64>) -> bool {
+    let (Some(expiry_days), Some(written)) = (expiry_days, state::modified_secs(placeholder))
+    else {
+        return false;
+    };
+    let now = Local::now().timestamp().max(0) as u64;
+    now.saturating_sub(written) > expiry_days * 24 * 60 * 60
+}
+
+/// Whether a lyrics file is an instrumental placeholder, whose modification time dates it
+fn is_placeholder_file(lyrics_path: &Path) -> bool {
+    lyrics_path
+        .extension()
+        .is_some_and(|extension| extension == INSTRUMENTAL_EXTENSION)
+        || is_instrumental_lrc_file(lyrics_path)
+}
+
+fn is_instrumental_lrc_file(lrc_path: &Path) -> bool {
+    if let Ok(content) = read_lyrics_file(lrc_path) {
+        content.contains("[by: lrcphile]") && content.contains("[instrumental]")
+    } else {
+        false
+    }
 }
 
-impl LyricsResponse {
-    fn generate_header(&self) -> String {
-        let minutes = (self.duration as u32) / 60;
-        let seconds = (self.duration as u32) % 60;
-        let length = format!("{}:{:02}", minutes, seconds);
-
-        format!(
-            "[ti: {}]\n[ar: {}]\n[al: {}]\n[length: {}]\n[by: lrcphile]",
-            self.track_name, self.artist_name, self.album_name, length
-        )
+/// Decodes lyrics bytes to a string, honoring a BOM and otherwise guessing
+/// legacy encodings (GBK, Shift-JIS, Windows-1252...) when the bytes are not UTF-8
+fn decode_lyrics(bytes: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return text.into_owned();
     }
+
+    let encoding = if std::str::from_utf8(bytes).is_ok() {
+        UTF_8
+    } else {
+        let mut detector = EncodingDetector::new();
+        detector.feed(bytes, true);
+        detector.guess(None, true)
+    };
+
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    text.into_owned()
 }
 
-#[derive(Debug)]
-struct TrackMetadata {
-    track_name: String,
-    artist_name: String,
-    album_name: String,
-    duration: f64,
+fn read_lyrics_file(path: &Path) -> Result<String> {
+    Ok(decode_lyrics(&fs::read(path)?))
 }
 
-#[derive(Debug, Clone)]
-struct ProcessingStats {
-    success: usize,
-    failed: usize,
-    skipped: usize,
-    total: usize,
+/// Encodes lyrics as UTF-8 with the BOM and line endings requested on the command line
+fn encode_lyrics(lyrics: &str, args: &Cli) -> Vec<u8> {
+    let mut text = lyrics.replace("\r\n", "\n");
+    if args.crlf {
+        text = text.replace('\n', "\r\n");
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    if args.bom {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
 }
 
-impl ProcessingStats {
-    fn new(total: usize) -> Self {
-        Self {
-            success: 0,
-            failed: 0,
-            skipped: 0,
-            total,
+/// Writes a lyrics file, refusing locations `--no-write-outside-root` rules out; the
+/// contents go to a temporary sibling first and replace the file in one rename, so an
+/// interrupted run never leaves it half written
+fn write_lyrics_file(path: &Path, contents: impl AsRef<[u8]>, args: &Cli) -> Result<()> {
+    ensure_inside_root(path, args)?;
+    // A symlinked lyrics file is written through rather than replaced by a regular file
+    let path = match fs::canonicalize(path) {
+        Ok(target) if path.is_symlink() => target,
+        _ => path.to_path_buf(),
+    };
+    // A fresh name each time, so a leftover or planted file is never written through
+    let (temp_path, mut temp_file) = loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+        let temp_path = path.with_file_name(temp_name);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => break (temp_path, file),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
         }
-    }
+    };
 
-    fn increment_success(&mut self) {
-        self.success += 1;
+    let written = std::io::Write::write_all(&mut temp_file, contents.as_ref())
+        .and_then(|()| keep_file_attributes(&path, &temp_path, args))
+        .and_then(|()| fs::rename(&temp_path, &path));
+    drop(temp_file);
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    Ok(written?)
+}
 
-    fn increment_failed(&mut self) {
-        self.failed += 1;
+/// Gives the file about to replace a lyrics file the permissions, and on Unix the owner where
+/// allowed, of the one it replaces, except what `--file-mode` and `--copy-owner` set afterwards
+fn keep_file_attributes(path: &Path, replacement: &Path, args: &Cli) -> std::io::Result<()> {
+    let Ok(old) = fs::metadata(path) else {
+        return Ok(());
+    };
+    if args.file_mode.is_none() {
+        fs::set_permissions(replacement, old.permissions())?;
     }
+    #[cfg(unix)]
+    if !args.copy_owner {
+        use std::os::unix::fs::MetadataExt;
 
-    fn increment_skipped(&mut self) {
-        self.skipped += 1;
+        // Only root can give files away, so an unchanged owner is not an error
+        let _ = std::os::unix::fs::chown(replacement, Some(old.uid()), Some(old.gid()));
     }
+    Ok(())
+}
 
-    fn display_summary(&self) {
-        println!("\n{}", "Processing Summary:".bright_cyan().bold());
-        println!(
-            "  {} {} {}",
-            "Processed:".white(),
-            self.total.to_string().bright_white().bold(),
-            "files".white()
-        );
-        println!(
-            "  {} {} {}",
-            "Successful:".green(),
-            self.success.to_string().bright_green().bold(),
-            "files".green()
-        );
-        println!(
-            "  {} {} {}",
-            "Failed:".red(),
-            self.failed.to_string().bright_red().bold(),
-            "files".red()
-        );
-        println!(
-            "  {} {} {}",
-            "Skipped (existing/instrumental):".yellow(),
-            self.skipped.to_string().bright_yellow().bold(),
-            "files".yellow()
-        );
+/// Rewrites an existing lyrics file as UTF-8, returning whether it was changed
+fn normalize_lyrics_file(path: &Path, args: &Cli) -> Result<bool> {
+    let original = fs::read(path)?;
+    let normalized = encode_lyrics(&decode_lyrics(&original), args);
+    if normalized == original {
+        return Ok(false);
     }
+
+    write_lyrics_file(path, normalized, args)?;
+    Ok(true)
 }
 
-impl TrackMetadata {
-    async fn fetch_lyrics(
-        self,
-        url: &str,
-    ) -> Result<Option<LyricsResponse>, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-
-        let api_url = format!(
-            "{}/api/get?track_name={}&artist_name={}&album_name={}&duration={}",
-            url.trim_end_matches('/'),
-            urlencoding::encode(&self.track_name),
-            urlencoding::encode(&self.artist_name),
-            urlencoding::encode(&self.album_name),
-            self.duration,
-        );
+/// Lays lyrics out the way the options ask for: `--normalize-text` tidies the text,
+/// `--clean-lyrics` drops credit lines,
+/// `--max-line-length` wraps long lines, and for synced lyrics, with `--flatten-offset`, the offset tag is applied to the timestamps,
+/// `--repair-timing` puts lines in order (and marks long gaps with `--gap-marker`), and with
+/// `--expand-timestamps`, lines carrying several timestamps become one line per timestamp
+fn arrange_lyrics<'a>(lyrics: &'a str, extension: &str, args: &Cli) -> Cow<'a, str> {
+    let text = (args.normalize_text || args.clean_lyrics || args.max_line_length.is_some())
+        && (extension == "lrc" || extension == "txt");
+    let synced =
+        extension == "lrc" && (args.flatten_offset || args.repair_timing || args.expand_timestamps);
+    if !text && !synced {
+        return Cow::Borrowed(lyrics);
+    }
 
-        let response = client
-            .get(&api_url)
-            .header(
-                "User-Agent",
-                "lrcphile v0.1.0 (https://github.com/khalil-cheddadi/lrcphile)",
-            )
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            let lyrics_response: LyricsResponse = response.json().await?;
-            Ok(Some(lyrics_response))
-        } else if response.status() == 404 {
-            Ok(None)
+    let mut lrc = Lrc::parse(lyrics);
+    if args.normalize_text {
+        lrc.map_text(|text| lrc::normalize_text(text, args.quote_style));
+        lrc.collapse_blank_lines();
+    }
+    if args.clean_lyrics {
+        if args.cleanup_leading_only {
+            lrc.remove_leading_matching(&args.cleanup_patterns);
         } else {
-            Err(format!("API request failed with status: {}", response.status()).into())
+            lrc.remove_matching(&args.cleanup_patterns);
+        }
+    }
+    if let Some(max_line_length) = args.max_line_length {
+        lrc.wrap(max_line_length as usize, args.split_synced);
+    }
+    if extension != "lrc" {
+        return Cow::Owned(lrc.to_string());
+    }
+    if args.flatten_offset {
+        lrc.flatten_offset();
+    }
+    if args.repair_timing {
+        lrc.repair_timing();
+        if let Some(gap) = args.gap_marker {
+            lrc.fill_gaps(gap * 1000);
         }
     }
+    if args.expand_timestamps {
+        lrc = lrc.expanded();
+    }
+    Cow::Owned(lrc.to_string())
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Cli::parse();
+/// Runs lyrics through the `--output-filter` command: the lyrics go to its stdin, the track
+/// is described in `LRCPHILE_*` environment variables, and its stdout is what gets written.
+/// A non-zero exit vetoes the file, with the command's stderr as the reason
+async fn filter_lyrics(
+    command: &str,
+    lyrics: &str,
+    audio_path: &Path,
+    lyrics_path: &Path,
+    metadata: Option<&TrackMetadata>,
+) -> Result<String> {
+    if command.trim().is_empty() {
+        return Ok(lyrics.to_string());
+    }
 
-    let path = match &args.path {
-        Some(p) => p.clone(),
-        None => UserDirs::new()
-            .expect("Failed to get user directories")
-            .audio_dir()
-            .unwrap()
-            .to_path_buf(),
+    // Run by the shell, so quoted arguments and paths with spaces work as they would at a prompt
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
     };
+    let tag = |field: fn(&TrackMetadata) -> &str| metadata.map(field).unwrap_or_default();
+    let mut child = shell
+        .arg(command)
+        .env("LRCPHILE_AUDIO_PATH", audio_path)
+        .env("LRCPHILE_LYRICS_PATH", lyrics_path)
+        .env("LRCPHILE_TITLE", tag(|metadata| &metadata.track_name))
+        .env("LRCPHILE_ARTIST", tag(|metadata| &metadata.artist_name))
+        .env("LRCPHILE_ALBUM", tag(|metadata| &metadata.album_name))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| LrcphileError::Rejected(format!("could not run {}: {}", command, e)))?;
 
-    if path.is_file() {
-        process_file(&path, &args, None).await;
-    } else if path.is_dir() {
-        match process_directory(&path, args.recursive) {
-            Ok(audio_files) => {
-                println!(
-                    "{} {}",
-                    "Found:".green().bold(),
-                    format!("{} audio files", audio_files.len()).bright_cyan()
-                );
+    // Written while the output is read, so a filter that answers before reading everything
+    // can't leave both sides waiting on full pipes
+    let stdin = child.stdin.take();
+    let write = async move {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(lyrics.as_bytes()).await;
+        }
+    };
+    let ((), output) = tokio::join!(write, child.wait_with_output());
+    let output = output?;
 
-                if audio_files.len() == 0 {
-                    println!("{}", "No audio files found.".yellow());
-                    return;
-                }
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(LrcphileError::Rejected(if reason.is_empty() {
+            format!("{} exited with {}", command, output.status)
+        } else {
+            reason
+        }));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
-                // Create progress bar
-                let progress = ProgressBar::new(audio_files.len() as u64);
-                progress.set_style(
-                    ProgressStyle::default_bar()
-                        .template("[{bar:40}] {pos}/{len} {msg}")
-                        .unwrap()
-                        .progress_chars("# "),
-                );
-                progress.set_message("Processing audio files...");
+/// Writes lyrics for a track, described to `--output-filter` by its tags when they could be
+/// read
+async fn save_lyrics_file(
+    audio_path: &Path,
+    lyrics: &str,
+    extension: &str,
+    metadata: Option<&TrackMetadata>,
+    state: &Mutex<StateDb>,
+    args: &Cli,
+) -> Result<PathBuf> {
+    // Write the lyrics to the file
+    let file_path = get_lyrics_file_path(audio_path, extension, args)?;
+    ensure_inside_root(&file_path, args)?;
+    // Every command saves through here, so none of them replaces a hand-made file or one
+    // edited since it was fetched
+    if args.preserve_manual && !args.force && has_manual_lyrics(audio_path, args) {
+        return Err(LrcphileError::ManualLyrics(file_path));
+    }
+    if !args.force && is_edited_since_fetch(audio_path, state, args).await {
+        return Err(LrcphileError::EditedSinceFetch(file_path));
+    }
+    if args.lyrics_redirect.is_some()
+        && let Some(parent) = file_path.parent()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut lyrics = arrange_lyrics(lyrics, extension, args);
+    if let Some(command) = &args.output_filter {
+        lyrics =
+            Cow::Owned(filter_lyrics(command, &lyrics, audio_path, &file_path, metadata).await?);
+    }
+    write_lyrics_file(&file_path, encode_lyrics(&lyrics, args), args)?;
+    apply_file_policy(&file_path, audio_path, args)?;
+    remove_stale_variants(&file_path, extension)?;
 
-                let stats = Arc::new(Mutex::new(ProcessingStats::new(audio_files.len())));
+    if args.censored_copy
+        && (extension == "lrc" || extension == "txt")
+        && let Some(pattern) = &args.censor_pattern
+    {
+        let censored_path = censored_file_path(&file_path);
+        write_lyrics_file(
+            &censored_path,
+            encode_lyrics(&censor_lyrics(&lyrics, pattern), args),
+            args,
+        )?;
+        apply_file_policy(&censored_path, audio_path, args)?;
+    }
+    Ok(file_path)
+}
 
-                // Process files concurrently with a limit of 4
-                let concurrent_limit = 4;
-                stream::iter(audio_files)
-                    .map(|file_path| {
-                        let args_clone = args.clone();
-                        let progress_clone = progress.clone();
-                        let stats_clone = stats.clone();
-                        async move {
-                            process_file(&file_path, &args_clone, Some(stats_clone)).await;
-                            progress_clone.inc(1);
-                        }
-                    })
-                    .buffer_unordered(concurrent_limit)
-                    .collect::<Vec<_>>()
-                    .await;
+/// Where `--censored-copy` writes the censored variant of a lyrics file: `NAME.censored.lrc`
+/// beside `NAME.lrc`
+fn censored_file_path(lyrics_path: &Path) -> PathBuf {
+    let extension = lyrics_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    lyrics_path.with_extension(format!("censored.{}", extension))
+}
 
-                progress.finish_with_message("Processing complete!");
+/// Masks every word the pattern matches, keeping its first letter (`f***`); tags and
+/// timestamps are left alone
+fn censor_lyrics(lyrics: &str, pattern: &Regex) -> String {
+    let mut lrc = Lrc::parse(lyrics);
+    lrc.map_text(|text| {
+        pattern
+            .replace_all(text, |captures: &regex::Captures| {
+                let mut chars = captures[0].chars();
+                let first = chars.next().map(String::from).unwrap_or_default();
+                first + &"*".repeat(chars.count())
+            })
+            .into_owned()
+    });
+    lrc.to_string()
+}
 
-                let final_stats = stats.lock().await;
-                final_stats.display_summary();
-            }
-            Err(e) => {
-                eprintln!(
-                    "{} {}",
-                    "Error:".red().bold(),
-                    format!("Error collecting tracks from {}: {}", path.display(), e).red()
-                );
-                std::process::exit(1);
-            }
+/// Applies `--file-mode`, `--copy-owner` and `--match-mtime` to a lyrics file just written
+/// for an audio file
+fn apply_file_policy(lyrics_path: &Path, audio_path: &Path, args: &Cli) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        if let Some(mode) = args.file_mode {
+            fs::set_permissions(lyrics_path, fs::Permissions::from_mode(mode))?;
         }
-    } else {
-        eprintln!(
-            "{} {}",
-            "Error:".red().bold(),
-            format!(
-                "Path does not exist or is not a file or directory: {}",
-                path.display()
-            )
-            .red()
-        );
-        std::process::exit(1);
+        if args.copy_owner {
+            let audio = fs::metadata(audio_path)?;
+            std::os::unix::fs::chown(lyrics_path, Some(audio.uid()), Some(audio.gid()))?;
+        }
+    }
+
+    // Last, since changing ownership or permissions doesn't touch the modification time.
+    // Placeholders keep theirs, which is when they were written and so when they expire
+    if args.match_mtime && !is_placeholder_file(lyrics_path) {
+        let modified = fs::metadata(audio_path)?.modified()?;
+        fs::File::options()
+            .append(true)
+            .open(lyrics_path)?
+            .set_modified(modified)?;
     }
+    Ok(())
 }
 
-fn process_directory(
-    dir_path: &PathBuf,
-    recursive: bool,
-) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    let mut all_tracks = Vec::new();
-    let audio_extensions = [
-        "mp3", "flac", "wav", "ogg", "m4a", "aac", "opus", "wma", "ape", "dsf", "dff",
-    ];
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() {
-            if let Some(extension) = path.extension() {
-                if let Some(ext_str) = extension.to_str() {
-                    if audio_extensions.contains(&ext_str.to_lowercase().as_str()) {
-                        all_tracks.push(path);
-                    }
-                }
-            }
-        } else if path.is_dir() && recursive {
-            match process_directory(&path, recursive) {
-                Ok(sub_tracks) => all_tracks.extend(sub_tracks),
-                Err(e) => {
-                    eprintln!(
-                        "{} {}",
-                        "Warning:".yellow().bold(),
-                        format!("Error reading subdirectory {}: {}", path.display(), e).yellow()
-                    );
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use provider::fixtures::{RECORD, metadata};
+    use provider::{HttpResponse, MockTransport};
+
+    fn rate_limited() -> HttpResponse {
+        let mut response = MockTransport::json(429, "{}");
+        response
+            .headers
+            .push(("Retry-After".to_string(), "0".to_string()));
+        response
     }
 
-    all_tracks.sort();
+    async fn fetch(transport: Arc<MockTransport>, search: bool) -> Result<LyricsResponse> {
+        let lrclib = Lrclib::new("http://lrclib.test", false, transport);
+        fetch_with_retry(
+            &lrclib,
+            &metadata(),
+            &Throttle::default(),
+            &Console::default(),
+            search,
+            false,
+        )
+        .await
+    }
 
-    Ok(all_tracks)
-}
+    #[tokio::test]
+    async fn retries_after_being_rate_limited() {
+        let transport = MockTransport::new([rate_limited(), MockTransport::json(200, RECORD)]);
+        let lyrics = fetch(transport.clone(), false).await.unwrap();
+        assert_eq!(lyrics.id, 11);
+        assert_eq!(lyrics.matched, MatchKind::Exact);
+        assert_eq!(transport.urls.lock().unwrap().len(), 2);
+    }
 
-async fn process_file(file_path: &PathBuf, args: &Cli, stats: Option<Arc<Mutex<ProcessingStats>>>) {
-    let metadata_result = read_metadata(file_path).await;
-    let stats = stats.unwrap_or(Arc::new(Mutex::new(ProcessingStats::new(0))));
-    match metadata_result {
-        Ok(metadata) => {
-            // Check if lyrics files already exist
-            let is_instrumental;
-            let lrc_exists = match get_lyrics_file_path(file_path, "lrc") {
-                Ok(path) => {
-                    is_instrumental = is_instrumental_lrc_file(&path);
-                    path.exists()
-                }
-                Err(e) => {
-                    eprintln!(
-                        "{} {}",
-                        "Error:".red().bold(),
-                        format!("Error determining LRC file path: {}", e).red()
-                    );
-                    return;
-                }
-            };
-            let txt_exists = match get_lyrics_file_path(file_path, "txt") {
-                Ok(path) => path.exists(),
-                Err(e) => {
-                    eprintln!(
-                        "{} {}",
-                        "Error:".red().bold(),
-                        format!("Error determining TXT file path: {}", e).red()
-                    );
-                    return;
-                }
-            };
+    #[tokio::test]
+    async fn gives_up_when_rate_limited_too_often() {
+        let transport = MockTransport::new((0..=MAX_RATE_LIMIT_RETRIES).map(|_| rate_limited()));
+        assert!(matches!(
+            fetch(transport.clone(), false).await,
+            Err(LrcphileError::RateLimited { .. })
+        ));
+        assert_eq!(
+            transport.urls.lock().unwrap().len(),
+            MAX_RATE_LIMIT_RETRIES as usize + 1
+        );
+    }
 
-            let should_fetch = if is_instrumental {
-                false
-            } else if lrc_exists || txt_exists {
-                args.override_files
-            } else {
-                true
-            };
+    #[tokio::test]
+    async fn falls_back_to_search_when_the_signature_misses() {
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            MockTransport::json(200, &format!("[{}]", RECORD)),
+        ]);
+        let lyrics = fetch(transport.clone(), true).await.unwrap();
+        assert_eq!(lyrics.id, 11);
+        assert_eq!(lyrics.matched, MatchKind::Search);
+        assert!(transport.urls.lock().unwrap()[1].contains("/api/search?"));
+    }
 
-            if !should_fetch {
-                stats.lock().await.increment_skipped();
-            } else {
-                match metadata.fetch_lyrics(&args.url).await {
-                    Ok(Some(lyrics_result)) => {
-                        let header = lyrics_result.generate_header();
-                        if lyrics_result.instrumental {
-                            // Create LRC file with instrumental tag to avoid refetching
-                            let instrumental_lrc = format!("{}\n[instrumental]", header);
-                            match save_lyrics_file(file_path, &instrumental_lrc, "lrc") {
-                                Ok(_) => {
-                                    stats.lock().await.increment_success();
-                                }
-                                Err(e) => {
-                                    eprintln!(
-                                        "{} {}",
-                                        "Failed:".red().bold(),
-                                        format!("Failed to save instrumental LRC file: {}", e)
-                                            .red()
-                                    );
-                                    stats.lock().await.increment_failed();
-                                }
-                            }
-                        } else if let Some(synced_lyrics) = &lyrics_result.synced_lyrics {
-                            // Save synced lyrics to a .lrc file
-                            let lrc_with_header = format!("{}\n{}", header, synced_lyrics);
-                            match save_lyrics_file(file_path, &lrc_with_header, "lrc") {
-                                Ok(_) => {
-                                    stats.lock().await.increment_success();
-                                }
-                                Err(e) => {
-                                    eprintln!(
-                                        "{} {}",
-                                        "Failed:".red().bold(),
-                                        format!("Failed to save LRC file: {}", e).red()
-                                    );
-                                    stats.lock().await.increment_failed();
-                                }
-                            }
-                        } else if let Some(plain_lyrics) = &lyrics_result.plain_lyrics {
-                            // Only save plain lyrics to a .txt file
-                            let txt_with_header = format!("{}\n{}", header, plain_lyrics);
-                            match save_lyrics_file(file_path, &txt_with_header, "txt") {
-                                Ok(_) => {
-                                    stats.lock().await.increment_success();
-                                }
-                                Err(e) => {
-                                    eprintln!(
-                                        "{} {}",
-                                        "Failed:".red().bold(),
-                                        format!("Failed to save TXT file: {}", e).red()
-                                    );
-                                    stats.lock().await.increment_failed();
-                                }
-                            }
-                        }
-                    }
-                    Ok(None) => {
-                        stats.lock().await.increment_failed();
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "{} {}",
-                            "Failed:".red().bold(),
-                            format!("Failed to fetch lyrics: {}", e).red()
-                        );
-                        stats.lock().await.increment_failed();
-                    }
-                }
-            }
-        }
-        Err(_) => {
-            stats.lock().await.increment_failed();
-        }
+    #[tokio::test]
+    async fn misses_without_searching_unless_asked() {
+        let transport = MockTransport::new([MockTransport::json(404, r#"{"code":404}"#)]);
+        assert!(matches!(
+            fetch(transport.clone(), false).await,
+            Err(LrcphileError::NotFound)
+        ));
+        assert_eq!(transport.urls.lock().unwrap().len(), 1);
     }
-}
 
-async fn read_metadata(file_path: &PathBuf) -> Result<TrackMetadata, Box<dyn std::error::Error>> {
-    let tagged_file = Probe::open(file_path)?.read()?;
+    #[tokio::test]
+    async fn misses_when_search_finds_nothing_plausible() {
+        let other = RECORD
+            .replace(
+                r#""trackName":"Song""#,
+                r#""trackName":"Another Song Entirely""#,
+            )
+            .replace(r#""artistName":"Band""#, r#""artistName":"Someone Else""#);
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            MockTransport::json(200, &format!("[{}]", other)),
+        ]);
+        assert!(matches!(
+            fetch(transport, true).await,
+            Err(LrcphileError::NotFound)
+        ));
+    }
 
-    // Return metadata for potential lyrics fetching
-    if let Some(tag) = tagged_file.primary_tag() {
-        let track_name = tag.title().map(|s| s.to_string());
-        let artist_name = tag.artist().map(|s| s.to_string());
-        let album_name = tag.album().map(|s| s.to_string());
-        let duration = tagged_file.properties().duration().as_secs() as f64;
-
-        if let (Some(track_name), Some(artist_name), Some(album_name)) =
-            (track_name, artist_name, album_name)
-        {
-            return Ok(TrackMetadata {
-                track_name,
-                artist_name,
-                album_name,
-                duration,
-            });
-        }
+    #[tokio::test]
+    async fn misses_when_search_is_empty() {
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            MockTransport::json(200, "[]"),
+        ]);
+        assert!(matches!(
+            fetch(transport, true).await,
+            Err(LrcphileError::NotFound)
+        ));
     }
 
-    Err("Missing required metadata (title, artist, or album)".into())
-}
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runs_the_output_filter_through_the_shell() {
+        let (audio, lyrics) = (Path::new("Song.flac"), Path::new("Song.lrc"));
+        let quoted = filter_lyrics("sed -e 's/a b/c/'", "a b\n", audio, lyrics, None).await;
+        assert_eq!(quoted.unwrap(), "c\n");
 
-fn get_lyrics_file_path(
-    audio_file_path: &PathBuf,
-    extension: &str,
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let audio_dir = audio_file_path
-        .parent()
-        .ok_or("Could not determine parent directory")?;
+        let title = "printf %s \"$LRCPHILE_TITLE\"";
+        let described = filter_lyrics(title, "", audio, lyrics, Some(&metadata())).await;
+        assert_eq!(described.unwrap(), "Song");
 
-    let file_stem = audio_file_path
-        .file_stem()
-        .ok_or("Could not determine file name")?;
+        let vetoed = filter_lyrics("echo no >&2; exit 1", "", audio, lyrics, None).await;
+        assert!(matches!(vetoed, Err(LrcphileError::Rejected(reason)) if reason == "no"));
+    }
 
-    let mut lyrics_path = audio_dir.to_path_buf();
-    lyrics_path.push(format!("{}.{}", file_stem.to_string_lossy(), extension));
+    #[test]
+    fn reads_the_info_json_past_an_unrelated_json_sidecar() {
+        let dir =
+            std::env::temp_dir().join(format!("lrcphile-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Song.json"), "{not json").unwrap();
+        fs::write(
+            dir.join("Song.info.json"),
+            r#"{"title":"Song (Official Video)","track":"Song","artist":"Band"}"#,
+        )
+        .unwrap();
 
-    Ok(lyrics_path)
-}
+        let sidecar = read_sidecar_metadata(&dir.join("Song.mp3"));
+        fs::remove_dir_all(&dir).unwrap();
+        let sidecar = sidecar.unwrap();
+        assert_eq!(sidecar.title.as_deref(), Some("Song"));
+        assert_eq!(sidecar.artist.as_deref(), Some("Band"));
+        assert_eq!(sidecar.album, None);
+    }
 
-fn is_instrumental_lrc_file(lrc_path: &PathBuf) -> bool {
-    if let Ok(content) = fs::read_to_string(lrc_path) {
-        content.contains("[by: lrcphile]") && content.contains("[instrumental]")
-    } else {
-        false
+    #[cfg(unix)]
+    #[test]
+    fn replacing_lyrics_keeps_the_old_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("lrcphile-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Song.lrc");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let args = Cli::parse_from(["lrcphile"]);
+        write_lyrics_file(&path, "new", &args).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let content = fs::read_to_string(&path).unwrap();
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(content, "new");
+        assert_eq!(leftovers, 1);
     }
-}
 
-fn save_lyrics_file(
-    file_path: &PathBuf,
-    lyrics: &str,
-    extension: &str,
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    // Write the lyrics to the file
-    let file_path = get_lyrics_file_path(file_path, extension)?;
-    fs::write(&file_path, lyrics)?;
-    Ok(file_path)
+    #[test]
+    fn replaces_characters_fat_filesystems_reject() {
+        assert_eq!(sanitize_file_name("AC/DC: Live?"), "AC_DC_ Live_");
+        assert_eq!(sanitize_file_name(r#"a"b*c<d>e\f|g"#), "a_b_c_d_e_f_g");
+        assert_eq!(sanitize_file_name("tab\there"), "tab_here");
+        assert_eq!(sanitize_file_name("Café (Live)"), "Café (Live)");
+    }
+
+    #[test]
+    fn renames_reserved_windows_device_names() {
+        assert_eq!(windows_safe_name("CON"), "CON_");
+        assert_eq!(windows_safe_name("nul.lrc"), "nul_.lrc");
+        assert_eq!(windows_safe_name("aux.live.lrc"), "aux_.live.lrc");
+        assert_eq!(windows_safe_name("COM1.txt"), "COM1_.txt");
+        assert!(matches!(windows_safe_name("Console.lrc"), Cow::Borrowed(_)));
+        assert!(matches!(windows_safe_name("COM10.lrc"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn replaces_trailing_dots_and_spaces_on_windows() {
+        assert_eq!(windows_safe_name("Song."), "Song_");
+        assert_eq!(windows_safe_name("Song . "), "Song___");
+        assert_eq!(windows_safe_name("..."), "___");
+        assert_eq!(windows_safe_name("NUL."), "NUL_");
+        assert!(matches!(windows_safe_name("Song.lrc"), Cow::Borrowed(_)));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn builds_windows_safe_lyrics_paths() {
+        let args = Cli::parse_from(["lrcphile"]);
+        let lyrics = |audio: &str| get_lyrics_file_path(Path::new(audio), "lrc", &args).unwrap();
+        assert_eq!(
+            lyrics(r"C:\Music\CON.flac"),
+            Path::new(r"C:\Music\CON_.lrc")
+        );
+        assert_eq!(
+            lyrics(r"\\server\share\Song..flac"),
+            Path::new(r"\\server\share\Song_.lrc")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn mirrors_redirected_folders_with_windows_safe_names() {
+        let mut args = Cli::parse_from(["lrcphile"]);
+        args.lyrics_redirect = Some((
+            Some(PathBuf::from(r"C:\Music")),
+            PathBuf::from(r"D:\Lyrics"),
+        ));
+        let lyrics = get_lyrics_file_path(Path::new(r"C:\Music\AUX\Song .flac"), "lrc", &args);
+        assert_eq!(lyrics.unwrap(), Path::new(r"D:\Lyrics\AUX_\Song_.lrc"));
+
+        let outside = get_lyrics_file_path(Path::new(r"E:\Other\nul.flac"), "lrc", &args);
+        assert_eq!(outside.unwrap(), Path::new(r"D:\Lyrics\nul_.lrc"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn prefixes_long_windows_paths() {
+        let name = format!("{}.lrc", "a".repeat(250));
+        let local = extended_length_path(Path::new(r"C:\Music").join(&name));
+        assert_eq!(local, Path::new(r"\\?\C:\Music").join(&name));
+        let share = extended_length_path(Path::new(r"\\server\share").join(&name));
+        assert_eq!(share, Path::new(r"\\?\UNC\server\share").join(&name));
+        let short = PathBuf::from(r"\\server\share\Song_.lrc");
+        assert_eq!(extended_length_path(short.clone()), short);
+    }
 }
@@ -0,0 +1,72 @@
+use crate::error::{LrcphileError, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The lyrics files of a library with a hash of each, written by `--manifest` so
+/// `lrcphile check` can tell later whether a backup or migration kept them intact
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    /// When the manifest was written, in RFC 3339
+    pub created: String,
+    /// Directory the paths are relative to
+    pub root: PathBuf,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestEntry {
+    /// Relative to the root, or absolute for files outside it
+    pub path: PathBuf,
+    /// SHA-256 of the file's bytes, in hex
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Hashes the given lyrics files, skipping any that can't be read
+    pub fn build(root: &Path, lyrics_paths: impl Iterator<Item = PathBuf>) -> Self {
+        let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut files: Vec<ManifestEntry> = lyrics_paths
+            .filter_map(|lyrics_path| {
+                let sha256 = file_hash(&lyrics_path).ok()?;
+                let absolute = std::path::absolute(&lyrics_path).unwrap_or(lyrics_path);
+                let path = absolute
+                    .strip_prefix(&root)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(absolute);
+                Some(ManifestEntry { path, sha256 })
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self {
+            created: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            root,
+            files,
+        }
+    }
+
+    pub fn load(location: &Path) -> Result<Self> {
+        serde_json::from_reader(BufReader::new(fs::File::open(location)?))
+            .map_err(|e| LrcphileError::Parse(format!("{}: {}", location.display(), e)))
+    }
+
+    pub fn save(&self, location: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(location)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Hash of a file's exact bytes, so even a change of encoding or line endings shows up
+pub fn file_hash(path: &Path) -> Result<String> {
+    Ok(Sha256::digest(fs::read(path)?)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
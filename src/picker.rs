@@ -0,0 +1,216 @@
+use crate::TrackMetadata;
+use crate::prompt;
+use crate::provider::{LyricsResponse, MatchKind};
+use colored::Colorize;
+
+/// How far a search result's duration may be from the track's before it's not considered a match
+const DURATION_TOLERANCE: f64 = 10.0;
+
+/// How many lyrics lines to show for each candidate in the picker
+const SNIPPET_LINES: usize = 2;
+
+/// Duration difference beyond which a search result is doubtful, though still plausible
+const CONFIDENT_DURATION_DELTA: f64 = 3.0;
+
+/// Share of the confidence kept by a record that wasn't matched by its exact signature
+const SEARCH_DISCOUNT: f64 = 0.9;
+
+/// Similarity credited for a field the record leaves empty, as some providers don't return
+/// every field: neither agreement nor disagreement
+const UNKNOWN_SIMILARITY: f64 = 0.5;
+
+/// Keeps the search results that could plausibly be the track, best match first:
+/// same album before other releases, then closest in duration. A result needs the track's
+/// title or artist, so a song of similar length that only shares a word or two isn't taken
+pub fn plausible_candidates(
+    metadata: &TrackMetadata,
+    results: Vec<LyricsResponse>,
+) -> Vec<LyricsResponse> {
+    let same = |a: &str, b: &str| a.trim().eq_ignore_ascii_case(b.trim());
+    let mut candidates: Vec<LyricsResponse> = results
+        .into_iter()
+        .filter(|result| (result.duration - metadata.duration).abs() <= DURATION_TOLERANCE)
+        .filter(|result| {
+            same(&result.track_name, &metadata.track_name)
+                || same(&result.artist_name, &metadata.artist_name)
+        })
+        .filter(|result| {
+            result.instrumental || result.synced_lyrics.is_some() || result.plain_lyrics.is_some()
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| {
+        let key = |result: &LyricsResponse| {
+            (
+                !result.album_name.eq_ignore_ascii_case(&metadata.album_name),
+                (result.duration - metadata.duration).abs(),
+            )
+        };
+        key(a)
+            .partial_cmp(&key(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    candidates
+}
+
+/// Reasons to doubt that a search result really is the track; empty when it matches well
+pub fn doubts(metadata: &TrackMetadata, candidate: &LyricsResponse) -> Vec<String> {
+    let mut doubts = Vec::new();
+    // A field the record leaves empty is unknown, not a mismatch
+    let differs =
+        |a: &str, b: &str| !a.trim().is_empty() && !a.trim().eq_ignore_ascii_case(b.trim());
+    if differs(&candidate.track_name, &metadata.track_name) {
+        doubts.push(format!("title is \"{}\"", candidate.track_name));
+    }
+    if differs(&candidate.artist_name, &metadata.artist_name) {
+        doubts.push(format!("artist is \"{}\"", candidate.artist_name));
+    }
+    if differs(&candidate.album_name, &metadata.album_name) {
+        doubts.push(format!("album is \"{}\"", candidate.album_name));
+    }
+    let delta = candidate.duration - metadata.duration;
+    if delta.abs() > CONFIDENT_DURATION_DELTA {
+        doubts.push(format!("duration is off by {:+.0}s", delta));
+    }
+    if candidate.namesakes > 0 {
+        let position = metadata
+            .position()
+            .map(|position| format!(" (the track is {})", position))
+            .unwrap_or_default();
+        doubts.push(format!(
+            "{} other tracks on the album are also titled \"{}\" and about as long{}",
+            candidate.namesakes, candidate.track_name, position
+        ));
+    }
+    doubts
+}
+
+/// How many other candidates share the chosen one's title and album with a duration too
+/// close to the track's to tell them apart, as with intros, interludes and reprises repeated
+/// across the discs of an album
+pub fn namesakes(metadata: &TrackMetadata, candidates: &[LyricsResponse], chosen: usize) -> usize {
+    let same = |a: &str, b: &str| a.trim().eq_ignore_ascii_case(b.trim());
+    let chosen_record = &candidates[chosen];
+    candidates
+        .iter()
+        .enumerate()
+        .filter(|(index, candidate)| {
+            *index != chosen
+                && same(&candidate.track_name, &chosen_record.track_name)
+                && same(&candidate.album_name, &chosen_record.album_name)
+                && same(&candidate.artist_name, &chosen_record.artist_name)
+                && (candidate.duration - metadata.duration).abs() <= CONFIDENT_DURATION_DELTA
+        })
+        .count()
+}
+
+/// How sure it is that a record is the track, from 0 to 100: how closely title, artist and album
+/// agree and how near the duration is, discounted when the record only turned up in a search
+pub fn confidence(metadata: &TrackMetadata, record: &LyricsResponse) -> u8 {
+    let duration =
+        (1.0 - (record.duration - metadata.duration).abs() / DURATION_TOLERANCE).max(0.0);
+    let field = |record: &str, track: &str| {
+        if record.trim().is_empty() {
+            UNKNOWN_SIMILARITY
+        } else {
+            similarity(record, track)
+        }
+    };
+    let score = 0.4 * field(&record.track_name, &metadata.track_name)
+        + 0.3 * field(&record.artist_name, &metadata.artist_name)
+        + 0.1 * field(&record.album_name, &metadata.album_name)
+        + 0.2 * duration;
+    let score = if record.matched == MatchKind::Exact {
+        score
+    } else {
+        score * SEARCH_DISCOUNT
+    };
+    (score * 100.0).round() as u8
+}
+
+/// Similarity of two strings from 0 to 1, ignoring case and surrounding whitespace;
+/// one minus their edit distance relative to the longer one
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.trim().to_lowercase().chars().collect();
+    let b: Vec<char> = b.trim().to_lowercase().chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// Lists the candidates with their duration delta and a lyrics snippet, and asks which one to use;
+/// `None` when the user skips the track
+pub fn pick(metadata: &TrackMetadata, candidates: &[LyricsResponse]) -> Option<usize> {
+    println!(
+        "\n{} {} - {} ({}, {}:{:02})",
+        "Several matches for".bright_cyan().bold(),
+        metadata.track_name.bright_white().bold(),
+        metadata.artist_name.bright_white(),
+        metadata.album_name,
+        metadata.duration as u32 / 60,
+        metadata.duration as u32 % 60
+    );
+
+    for (index, candidate) in candidates.iter().enumerate() {
+        let delta = candidate.duration - metadata.duration;
+        let kind = if candidate.instrumental {
+            "instrumental"
+        } else if candidate.synced_lyrics.is_some() {
+            "synced"
+        } else {
+            "plain"
+        };
+
+        println!(
+            "  {} {} - {} ({}) {} {}",
+            format!("[{}]", index + 1).bright_white().bold(),
+            candidate.track_name,
+            candidate.artist_name,
+            candidate.album_name,
+            format!("{:+.0}s", delta).yellow(),
+            kind.dimmed()
+        );
+        for line in snippet(candidate) {
+            println!("      {}", line.dimmed());
+        }
+    }
+
+    loop {
+        let answer = prompt::ask(&format!(
+            "Use which match? [1-{}/s to skip]: ",
+            candidates.len()
+        ))?;
+        if answer == "s" || answer.is_empty() {
+            return None;
+        }
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=candidates.len()).contains(&choice) => return Some(choice - 1),
+            _ => println!("{}", "Enter a number from the list, or s to skip.".yellow()),
+        }
+    }
+}
+
+/// First few non-empty lyrics lines, preferring plain lyrics since they carry no timestamps
+fn snippet(candidate: &LyricsResponse) -> impl Iterator<Item = &str> {
+    candidate
+        .plain_lyrics
+        .as_deref()
+        .or(candidate.synced_lyrics.as_deref())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(SNIPPET_LINES)
+}
@@ -0,0 +1,147 @@
+use crate::error::{LrcphileError, Result};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Where `--progress-json` sends its events
+#[derive(Debug, Clone)]
+pub enum ProgressTarget {
+    /// A file descriptor the wrapping program opened for us, like `fd:3`
+    Fd(u32),
+    /// A UNIX socket the wrapping program listens on, like `unix:/run/gui.sock`
+    Socket(PathBuf),
+    /// A file or named pipe
+    File(PathBuf),
+}
+
+pub fn parse_target(target: &str) -> Result<ProgressTarget, String> {
+    if let Some(fd) = target.strip_prefix("fd:") {
+        return fd
+            .parse()
+            .map(ProgressTarget::Fd)
+            .map_err(|_| format!("invalid file descriptor '{}' (expected e.g. fd:3)", fd));
+    }
+    if let Some(socket) = target.strip_prefix("unix:") {
+        return Ok(ProgressTarget::Socket(PathBuf::from(socket)));
+    }
+    if target.is_empty() {
+        return Err("expected fd:N, unix:PATH or a file path".to_string());
+    }
+    Ok(ProgressTarget::File(PathBuf::from(target)))
+}
+
+/// Counts of a run so far, sent with each finished file
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ProgressCounts {
+    pub done: usize,
+    pub total: usize,
+    pub fetched: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub quarantined: usize,
+}
+
+/// One line of `--progress-json` output, tagged with its `event` name
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// The files to process were collected
+    Started {
+        total: usize,
+    },
+    FileStarted {
+        path: &'a Path,
+    },
+    FileFinished {
+        path: &'a Path,
+        /// As in `--report`: fetched, failed, skipped or quarantined
+        outcome: &'static str,
+        counts: ProgressCounts,
+    },
+    /// The run is over; `stopped` says which budget ended it early
+    Finished {
+        counts: ProgressCounts,
+        stopped: Option<String>,
+    },
+}
+
+/// Writes progress events as JSON lines for programs wrapping lrcphile, so they can show
+/// their own progress without reading the terminal output
+pub struct ProgressEvents {
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl ProgressEvents {
+    pub fn open(target: &ProgressTarget) -> Result<Self> {
+        let sink: Box<dyn Write + Send> = match target {
+            ProgressTarget::Fd(fd) => Box::new(open_fd(*fd)?),
+            ProgressTarget::Socket(path) => Box::new(connect(path)?),
+            ProgressTarget::File(path) => {
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+        };
+        Ok(Self {
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Sends one event; a reader that went away never fails the run
+    pub fn emit(&self, event: ProgressEvent) {
+        let Ok(mut line) = serde_json::to_vec(&event) else {
+            return;
+        };
+        line.push(b'\n');
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = sink.write_all(&line).and_then(|()| sink.flush());
+        }
+    }
+}
+
+/// Duplicates an inherited descriptor, so writing to it doesn't depend on `/dev/fd` being
+/// mounted, and sockets the wrapper hands over work as well as pipes and files
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<std::fs::File> {
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    let invalid = || LrcphileError::InvalidPath {
+        path: PathBuf::from(format!("fd:{}", fd)),
+        reason: "no writable file descriptor by that number was passed to lrcphile",
+    };
+    let fd = RawFd::try_from(fd).map_err(|_| invalid())?;
+    // SAFETY: F_GETFL only reads the descriptor's flags, and fails for one that isn't open,
+    // which has to be ruled out before borrowing it
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || flags & libc::O_ACCMODE == libc::O_RDONLY {
+        return Err(invalid());
+    }
+
+    // SAFETY: the descriptor is open, and stays so while it's duplicated
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned = borrowed.try_clone_to_owned().map_err(|_| invalid())?;
+    Ok(std::fs::File::from(owned))
+}
+
+#[cfg(not(unix))]
+fn open_fd(fd: u32) -> Result<std::fs::File> {
+    Err(LrcphileError::InvalidPath {
+        path: PathBuf::from(format!("fd:{}", fd)),
+        reason: "file descriptors can only be used on Unix; give a file or pipe path instead",
+    })
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> Result<std::os::unix::net::UnixStream> {
+    Ok(std::os::unix::net::UnixStream::connect(path)?)
+}
+
+#[cfg(not(unix))]
+fn connect(path: &Path) -> Result<std::fs::File> {
+    Err(LrcphileError::InvalidPath {
+        path: path.to_path_buf(),
+        reason: "UNIX sockets can only be used on Unix; give a file or pipe path instead",
+    })
+}
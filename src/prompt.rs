@@ -0,0 +1,18 @@
+use std::io::{self, Write};
+
+/// Prints a prompt and reads one trimmed, lowercased answer; `None` on EOF or error
+pub fn ask(prompt: &str) -> Option<String> {
+    ask_text(prompt).map(|answer| answer.to_lowercase())
+}
+
+/// Like `ask`, keeping the answer's case, for paths and URLs
+pub fn ask_text(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
@@ -0,0 +1,1071 @@
+use crate::TrackMetadata;
+use crate::cache::{CachingTransport, ResponseCache};
+use crate::error::{LrcphileError, Result};
+use crate::state::ProviderStats;
+use crate::trace::TracingTransport;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+const USER_AGENT: &str = "lrcphile v0.1.0 (https://github.com/khalil-cheddadi/lrcphile)";
+
+/// A response as seen by providers, independent of the HTTP client that produced it
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Looks up a header value by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses a `Retry-After` header given either in seconds or as an HTTP date
+    pub fn retry_after(&self) -> Option<Duration> {
+        let value = self.header("Retry-After")?.trim();
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        let seconds = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+        Some(Duration::from_secs(seconds as u64))
+    }
+}
+
+/// Performs the HTTP requests of a provider; swap it out to replay canned responses
+pub trait Transport: Send + Sync {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>>;
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>>;
+}
+
+/// IP version to restrict connections to
+#[derive(Debug, Clone, Copy)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// How the HTTP client connects, for networks where the defaults pick the wrong route,
+/// and where lookups are cached
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Addresses to connect to for these host names instead of asking DNS
+    pub resolve: Vec<(String, IpAddr)>,
+    pub ip_version: Option<IpVersion>,
+    pub cache: Option<Arc<ResponseCache>>,
+    /// Ask the instance again even when a cached response exists
+    pub refresh: bool,
+    /// Least time between two requests reaching the network, from `--rate-limit`
+    pub min_interval: Option<Duration>,
+    /// File every request reaching the network is logged to, from `--trace-http`
+    pub trace_http: Option<PathBuf>,
+    /// Requests that reached the network so far, shared by every transport of the run for
+    /// `--max-requests`
+    pub requests: Arc<AtomicU64>,
+}
+
+/// The real network transport, sharing one connection pool across all requests
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+    min_interval: Option<Duration>,
+    /// When the next request may be sent, with `min_interval` set
+    next_request: tokio::sync::Mutex<Option<tokio::time::Instant>>,
+}
+
+impl ReqwestTransport {
+    pub fn new(options: &ClientOptions) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().user_agent(USER_AGENT);
+        for (host, ip) in &options.resolve {
+            // reqwest ignores the port here and keeps the one from the URL
+            builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+        }
+        // Binding to the unspecified address of one family only lets connections use that family
+        builder = match options.ip_version {
+            Some(IpVersion::V4) => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            Some(IpVersion::V6) => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            None => builder,
+        };
+        Ok(Self {
+            client: builder.build()?,
+            min_interval: options.min_interval,
+            next_request: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Waits for this request's turn, so requests from concurrent workers are spaced out
+    async fn pace(&self) {
+        let Some(min_interval) = self.min_interval else {
+            return;
+        };
+        let send_at = {
+            let mut next_request = self.next_request.lock().await;
+            let now = tokio::time::Instant::now();
+            let send_at = next_request.map_or(now, |next| next.max(now));
+            *next_request = Some(send_at + min_interval);
+            send_at
+        };
+        tokio::time::sleep_until(send_at).await;
+    }
+
+    async fn read_response(response: reqwest::Response) -> Result<HttpResponse> {
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?.to_vec();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            self.pace().await;
+            let mut request = self.client.get(url);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            Self::read_response(request.send().await?).await
+        })
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            self.pace().await;
+            let mut request = self.client.post(url).body(body);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            Self::read_response(request.send().await?).await
+        })
+    }
+}
+
+/// Talks HTTP to an instance listening on a Unix domain socket, skipping TCP and TLS
+/// entirely when lrcphile runs on the same host; each request opens a fresh connection
+#[cfg(unix)]
+pub struct UnixSocketTransport {
+    socket: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocketTransport {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: hyper::Method,
+        url: &str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Result<HttpResponse> {
+        let provider_error =
+            |e: &dyn std::fmt::Display| LrcphileError::Provider(format!("Request failed: {}", e));
+
+        let uri: hyper::Uri = url.parse().map_err(|e| provider_error(&e))?;
+        let stream = tokio::net::UnixStream::connect(&self.socket)
+            .await
+            .map_err(|e| {
+                LrcphileError::Provider(format!(
+                    "Failed to connect to {}: {}",
+                    self.socket.display(),
+                    e
+                ))
+            })?;
+        let (mut sender, connection) = hyper::client::conn::handshake(stream)
+            .await
+            .map_err(|e| provider_error(&e))?;
+        tokio::spawn(connection);
+
+        let mut request = hyper::Request::builder()
+            .method(method)
+            .uri(uri.path_and_query().map_or("/", |path| path.as_str()))
+            .header("Host", uri.host().unwrap_or("localhost"))
+            .header("User-Agent", USER_AGENT);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(hyper::Body::from(body))
+            .map_err(|e| provider_error(&e))?;
+
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|e| provider_error(&e))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| provider_error(&e))?
+            .to_vec();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixSocketTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(self.send(hyper::Method::GET, url, headers, Vec::new()))
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(self.send(hyper::Method::POST, url, headers, body))
+    }
+}
+
+/// Picks the transport for an instance URL, returning it along with the base URL requests
+/// should be made against; `unix:///path/to/lrclib.sock` connects over a Unix domain socket
+pub fn connect(url: &str, options: &ClientOptions) -> Result<(String, Arc<dyn Transport>)> {
+    if let Some(socket) = url.strip_prefix("unix://") {
+        #[cfg(unix)]
+        return Ok((
+            "http://localhost".to_string(),
+            Arc::new(UnixSocketTransport::new(socket)),
+        ));
+        #[cfg(not(unix))]
+        return Err(LrcphileError::Provider(format!(
+            "Unix domain sockets are not supported on this platform: {}",
+            socket
+        )));
+    }
+
+    Ok((url.to_string(), Arc::new(ReqwestTransport::new(options)?)))
+}
+
+/// Counts the requests that reach the network, under the cache, so answers read from disk
+/// aren't counted and each retry or follow-up request is
+struct CountingTransport {
+    inner: Arc<dyn Transport>,
+    requests: Arc<AtomicU64>,
+}
+
+impl Transport for CountingTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.get(url, headers)
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.post(url, headers, body)
+    }
+}
+
+/// Adds the request counting, logging and response caching `options` ask for around a
+/// transport
+pub fn wrap_transport(
+    mut transport: Arc<dyn Transport>,
+    options: &ClientOptions,
+) -> Result<Arc<dyn Transport>> {
+    transport = Arc::new(CountingTransport {
+        inner: transport,
+        requests: options.requests.clone(),
+    });
+    if let Some(trace_http) = &options.trace_http {
+        transport = Arc::new(TracingTransport::new(transport, trace_http)?);
+    }
+    if let Some(cache) = &options.cache {
+        transport = Arc::new(CachingTransport::new(
+            transport,
+            cache.clone(),
+            options.refresh,
+        ));
+    }
+    Ok(transport)
+}
+
+/// A source of lyrics for a track
+pub trait LyricsProvider: Send + Sync {
+    fn fetch<'a>(&'a self, metadata: &'a TrackMetadata) -> BoxFuture<'a, Result<LyricsResponse>>;
+
+    /// Looser lookup used when `fetch` finds no exact match, returning every candidate record
+    fn search<'a>(
+        &'a self,
+        metadata: &'a TrackMetadata,
+    ) -> BoxFuture<'a, Result<Vec<LyricsResponse>>>;
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct LyricsResponse {
+    pub id: u64,
+    #[serde(rename = "trackName")]
+    pub track_name: String,
+    #[serde(rename = "artistName")]
+    pub artist_name: String,
+    #[serde(rename = "albumName")]
+    pub album_name: String,
+    pub duration: f64,
+    pub instrumental: bool,
+    #[serde(rename = "plainLyrics")]
+    pub plain_lyrics: Option<String>,
+    #[serde(rename = "syncedLyrics")]
+    pub synced_lyrics: Option<String>,
+    /// Name of the provider the record came from, recorded in the file header
+    #[serde(skip)]
+    pub provider: Cow<'static, str>,
+    /// Validators the provider sent along, for a conditional lookup next time
+    #[serde(skip)]
+    pub validators: CacheValidators,
+    /// How the record was matched to the track
+    #[serde(skip)]
+    pub matched: MatchKind,
+    /// How sure lrcphile is that the record is the track, from 0 to 100
+    #[serde(skip)]
+    pub confidence: Option<u8>,
+    /// Other search results from the same album with the same title and a similar duration,
+    /// which the record could be mistaken for
+    #[serde(skip)]
+    pub namesakes: usize,
+}
+
+/// How a lyrics record was matched to a track
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchKind {
+    /// Looked up directly by ID or by the track's exact signature
+    #[default]
+    Exact,
+    /// The best candidate of the search fallback
+    Search,
+    /// A search candidate chosen by the user
+    Picked,
+}
+
+impl MatchKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MatchKind::Exact => "exact",
+            MatchKind::Search => "search",
+            MatchKind::Picked => "picked",
+        }
+    }
+}
+
+/// HTTP cache validators of a lyrics record, letting a refresh ask whether it changed
+/// instead of downloading it again
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CacheValidators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn from_response(response: &HttpResponse) -> Self {
+        Self {
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Request headers that make the provider answer 304 when the record is unchanged
+    fn conditional_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(etag) = &self.etag {
+            headers.push(("If-None-Match".to_string(), etag.clone()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push(("If-Modified-Since".to_string(), last_modified.clone()));
+        }
+        headers
+    }
+}
+
+impl LyricsResponse {
+    /// The record's LRCLIB ID, which records from other providers don't have
+    pub fn lrclib_id(&self) -> Option<u64> {
+        (self.provider == LRCLIB_PROVIDER).then_some(self.id)
+    }
+
+    pub fn generate_header(&self) -> String {
+        let minutes = (self.duration as u32) / 60;
+        let seconds = (self.duration as u32) % 60;
+        let length = format!("{}:{:02}", minutes, seconds);
+
+        let fetched = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let confidence = self
+            .confidence
+            .map(|confidence| format!(" match={} confidence={}", self.matched.as_str(), confidence))
+            .unwrap_or_default();
+
+        // An LRCLIB ID has its own tag, which older versions read; other providers' IDs go
+        // into the provenance comment
+        let (lrclib_id, id) = match self.lrclib_id() {
+            Some(id) => (format!("[lrclib_id: {}]\n", id), String::new()),
+            None => (String::new(), format!(" id={}", self.id)),
+        };
+
+        // Fields the provider didn't return are left out rather than written empty
+        let tags: String = [
+            ("ti", &self.track_name),
+            ("ar", &self.artist_name),
+            ("al", &self.album_name),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(tag, value)| format!("[{}: {}]\n", tag, value))
+        .collect();
+
+        format!(
+            "{}[length: {}]\n{}[by: lrcphile]\n[#: provider={}{} fetched={}{}]",
+            tags, length, lrclib_id, self.provider, id, fetched, confidence
+        )
+    }
+}
+
+/// Lyrics submitted to LRCLIB's `/api/publish`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishRequest {
+    pub track_name: String,
+    pub artist_name: String,
+    pub album_name: String,
+    pub duration: f64,
+    pub plain_lyrics: String,
+    pub synced_lyrics: String,
+}
+
+/// Provider name recorded in the header of files fetched from LRCLIB
+pub const LRCLIB_PROVIDER: &str = "lrclib";
+
+/// An LRCLIB instance, either lrclib.net or a self-hosted one
+pub struct Lrclib {
+    base_url: String,
+    /// Query `/api/get-cached`, which never reaches out to external lyrics sources
+    cached_only: bool,
+    transport: Arc<dyn Transport>,
+}
+
+impl Lrclib {
+    /// Connects to the instance at `url` with the transport it calls for
+    pub fn connect(url: &str, cached_only: bool, options: &ClientOptions) -> Result<Self> {
+        let (base_url, transport) = connect(url, options)?;
+        Ok(Self::new(
+            &base_url,
+            cached_only,
+            wrap_transport(transport, options)?,
+        ))
+    }
+
+    pub fn new(base_url: &str, cached_only: bool, transport: Arc<dyn Transport>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cached_only,
+            transport,
+        }
+    }
+
+    async fn fetch_by_id(
+        &self,
+        id: u64,
+        validators: Option<&CacheValidators>,
+    ) -> Result<LyricsResponse> {
+        let api_url = format!("{}/api/get/{}", self.base_url, id);
+        let headers = validators
+            .map(CacheValidators::conditional_headers)
+            .unwrap_or_default();
+        Self::parse_response(self.transport.get(&api_url, headers).await?)
+    }
+
+    async fn fetch_by_signature(&self, metadata: &TrackMetadata) -> Result<LyricsResponse> {
+        let endpoint = if self.cached_only {
+            "get-cached"
+        } else {
+            "get"
+        };
+        let api_url = format!(
+            "{}/api/{}?track_name={}&artist_name={}&album_name={}&duration={}",
+            self.base_url,
+            endpoint,
+            urlencoding::encode(&metadata.track_name),
+            urlencoding::encode(&metadata.artist_name),
+            urlencoding::encode(&metadata.album_name),
+            metadata.duration,
+        );
+
+        Self::parse_response(self.transport.get(&api_url, Vec::new()).await?)
+    }
+
+    async fn search_records(&self, metadata: &TrackMetadata) -> Result<Vec<LyricsResponse>> {
+        // The album is left out so other releases of the same recording show up too
+        let api_url = format!(
+            "{}/api/search?track_name={}&artist_name={}",
+            self.base_url,
+            urlencoding::encode(&metadata.track_name),
+            urlencoding::encode(&metadata.artist_name),
+        );
+
+        Self::parse_search_response(self.transport.get(&api_url, Vec::new()).await?)
+    }
+
+    /// Records whose title, artist, album or lyrics contain the text, for identifying a track
+    /// from a line of its lyrics
+    pub async fn search_text(&self, text: &str) -> Result<Vec<LyricsResponse>> {
+        let api_url = format!(
+            "{}/api/search?q={}",
+            self.base_url,
+            urlencoding::encode(text)
+        );
+        Self::parse_search_response(self.transport.get(&api_url, Vec::new()).await?)
+    }
+
+    fn parse_search_response(response: HttpResponse) -> Result<Vec<LyricsResponse>> {
+        match response.status {
+            200..=299 => serde_json::from_slice::<Vec<LyricsResponse>>(&response.body)
+                .map(|mut results| {
+                    for result in &mut results {
+                        result.provider = LRCLIB_PROVIDER.into();
+                    }
+                    results
+                })
+                .map_err(|e| LrcphileError::Parse(format!("LRCLIB search response: {}", e))),
+            429 => Err(LrcphileError::RateLimited {
+                retry_after: response.retry_after(),
+            }),
+            status => Err(LrcphileError::Provider(format!(
+                "Search request failed with status: {}",
+                status
+            ))),
+        }
+    }
+
+    /// Reports a record's lyrics as incorrect so the instance's curators can review it
+    pub async fn flag(&self, track_id: u64, reason: &str) -> Result<()> {
+        let body = serde_json::json!({
+            "track_id": track_id,
+            "content": reason,
+        });
+
+        let response = self.post_authorized("flag", &body).await?;
+        match response.status {
+            200..=299 => Ok(()),
+            404 | 405 => Err(LrcphileError::Provider(
+                "This instance does not support flagging lyrics".to_string(),
+            )),
+            status => Err(LrcphileError::Provider(format!(
+                "Flag request failed with status {}: {}",
+                status,
+                String::from_utf8_lossy(&response.body)
+            ))),
+        }
+    }
+
+    /// Uploads lyrics for a track that LRCLIB doesn't have (or has wrong)
+    pub async fn publish(&self, request: &PublishRequest) -> Result<()> {
+        let response = self.post_authorized("publish", request).await?;
+        match response.status {
+            200..=299 => Ok(()),
+            status => Err(LrcphileError::Provider(format!(
+                "Publish request failed with status {}: {}",
+                status,
+                String::from_utf8_lossy(&response.body)
+            ))),
+        }
+    }
+
+    /// Sends a JSON body to a write endpoint along with a freshly solved publish token
+    async fn post_authorized(&self, endpoint: &str, body: &impl Serialize) -> Result<HttpResponse> {
+        let token = self.publish_token().await?;
+        self.transport
+            .post(
+                &format!("{}/api/{}", self.base_url, endpoint),
+                vec![
+                    ("Content-Type".to_string(), "application/json".to_string()),
+                    ("X-Publish-Token".to_string(), token),
+                ],
+                serde_json::to_vec(body)?,
+            )
+            .await
+    }
+
+    /// Requests a proof-of-work challenge and solves it, yielding a token that
+    /// authorizes one write request
+    async fn publish_token(&self) -> Result<String> {
+        let response = self
+            .transport
+            .post(
+                &format!("{}/api/request-challenge", self.base_url),
+                Vec::new(),
+                Vec::new(),
+            )
+            .await?;
+        if !(200..=299).contains(&response.status) {
+            return Err(LrcphileError::Provider(format!(
+                "Challenge request failed with status: {}",
+                response.status
+            )));
+        }
+
+        let challenge: Challenge = serde_json::from_slice(&response.body)
+            .map_err(|e| LrcphileError::Parse(format!("LRCLIB challenge: {}", e)))?;
+        let prefix = challenge.prefix.clone();
+        let nonce = tokio::task::spawn_blocking(move || solve_challenge(&challenge))
+            .await
+            .map_err(|e| LrcphileError::Provider(format!("Challenge solver failed: {}", e)))??;
+
+        Ok(format!("{}:{}", prefix, nonce))
+    }
+
+    /// Maps an LRCLIB response onto lyrics or the matching error kind
+    fn parse_response(response: HttpResponse) -> Result<LyricsResponse> {
+        match response.status {
+            200..=299 => serde_json::from_slice::<LyricsResponse>(&response.body)
+                .map(|lyrics| LyricsResponse {
+                    provider: LRCLIB_PROVIDER.into(),
+                    validators: CacheValidators::from_response(&response),
+                    ..lyrics
+                })
+                .map_err(|e| LrcphileError::Parse(format!("LRCLIB response: {}", e))),
+            304 => Err(LrcphileError::NotModified),
+            404 => Err(LrcphileError::NotFound),
+            429 => Err(LrcphileError::RateLimited {
+                retry_after: response.retry_after(),
+            }),
+            status => Err(LrcphileError::Provider(format!(
+                "API request failed with status: {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl LyricsProvider for Lrclib {
+    fn fetch<'a>(&'a self, metadata: &'a TrackMetadata) -> BoxFuture<'a, Result<LyricsResponse>> {
+        Box::pin(async move {
+            // A known record is a direct lookup; fall back to matching by signature if it's gone
+            if let Some(id) = metadata.lrclib_id {
+                match self.fetch_by_id(id, metadata.validators.as_ref()).await {
+                    Err(LrcphileError::NotFound) => {}
+                    result => return result,
+                }
+            }
+
+            self.fetch_by_signature(metadata).await
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        metadata: &'a TrackMetadata,
+    ) -> BoxFuture<'a, Result<Vec<LyricsResponse>>> {
+        Box::pin(self.search_records(metadata))
+    }
+}
+
+/// Lookups of a run by provider, shared by its `CountedProvider`s and added to the state
+/// file's totals when it is saved
+pub type LookupStats = Arc<std::sync::Mutex<HashMap<String, ProviderStats>>>;
+
+/// Counts how a provider answers each lookup, for `lrcphile stats --providers`
+pub struct CountedProvider {
+    name: String,
+    inner: Arc<dyn LyricsProvider>,
+    /// Whether `search` sends requests, rather than answering "not found" for providers
+    /// that can only look tracks up directly
+    searches: bool,
+    stats: LookupStats,
+}
+
+impl CountedProvider {
+    pub fn new(
+        name: impl Into<String>,
+        inner: Arc<dyn LyricsProvider>,
+        searches: bool,
+        stats: LookupStats,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+            searches,
+            stats,
+        }
+    }
+
+    fn count<T>(&self, result: &Result<T>) {
+        let Ok(mut stats) = self.stats.lock() else {
+            return;
+        };
+        let stats = stats.entry(self.name.clone()).or_default();
+        stats.requests += 1;
+        match result {
+            Ok(_) | Err(LrcphileError::NotModified) => stats.hits += 1,
+            Err(LrcphileError::NotFound) => stats.misses += 1,
+            Err(_) => stats.errors += 1,
+        }
+    }
+}
+
+impl LyricsProvider for CountedProvider {
+    fn fetch<'a>(&'a self, metadata: &'a TrackMetadata) -> BoxFuture<'a, Result<LyricsResponse>> {
+        Box::pin(async move {
+            let result = self.inner.fetch(metadata).await;
+            self.count(&result);
+            result
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        metadata: &'a TrackMetadata,
+    ) -> BoxFuture<'a, Result<Vec<LyricsResponse>>> {
+        Box::pin(async move {
+            let result = self.inner.search(metadata).await;
+            if self.searches {
+                self.count(&result);
+            }
+            result
+        })
+    }
+}
+
+/// Several providers asked in turn, the next one only when those before it have no lyrics
+pub struct ProviderChain {
+    providers: Vec<Arc<dyn LyricsProvider>>,
+}
+
+impl ProviderChain {
+    pub fn new(providers: Vec<Arc<dyn LyricsProvider>>) -> Self {
+        Self { providers }
+    }
+}
+
+impl LyricsProvider for ProviderChain {
+    fn fetch<'a>(&'a self, metadata: &'a TrackMetadata) -> BoxFuture<'a, Result<LyricsResponse>> {
+        Box::pin(async move {
+            // A provider that fails outright is skipped too, but its error is what's reported
+            // if none of the others have the track either
+            let mut failure = None;
+            for provider in &self.providers {
+                match provider.fetch(metadata).await {
+                    Err(LrcphileError::NotFound) => {}
+                    Err(e @ LrcphileError::Provider(_) | e @ LrcphileError::Parse(_)) => {
+                        failure.get_or_insert(e);
+                    }
+                    result => return result,
+                }
+            }
+            Err(failure.unwrap_or(LrcphileError::NotFound))
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        metadata: &'a TrackMetadata,
+    ) -> BoxFuture<'a, Result<Vec<LyricsResponse>>> {
+        Box::pin(async move {
+            let mut candidates = Vec::new();
+            for provider in &self.providers {
+                match provider.search(metadata).await {
+                    Ok(results) => candidates.extend(results),
+                    Err(LrcphileError::NotFound) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if candidates.is_empty() {
+                return Err(LrcphileError::NotFound);
+            }
+            Ok(candidates)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    prefix: String,
+    target: String,
+}
+
+/// Finds a nonce whose SHA-256 hash, appended to the prefix, is at or below the target
+fn solve_challenge(challenge: &Challenge) -> Result<u64> {
+    let target = (0..challenge.target.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(challenge.target.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| LrcphileError::Parse("LRCLIB challenge target".to_string()))?;
+
+    (0..u64::MAX)
+        .find(|nonce| {
+            let hash = Sha256::digest(format!("{}{}", challenge.prefix, nonce));
+            hash.as_slice() <= target.as_slice()
+        })
+        .ok_or_else(|| LrcphileError::Provider("Could not solve LRCLIB challenge".to_string()))
+}
+
+/// Answers requests with canned responses in turn, without touching the network
+#[cfg(test)]
+pub struct MockTransport {
+    responses: std::sync::Mutex<std::collections::VecDeque<HttpResponse>>,
+    /// URLs requested so far, in order
+    pub urls: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl MockTransport {
+    pub fn new(responses: impl IntoIterator<Item = HttpResponse>) -> Arc<Self> {
+        Arc::new(Self {
+            responses: std::sync::Mutex::new(responses.into_iter().collect()),
+            urls: std::sync::Mutex::default(),
+        })
+    }
+
+    /// A canned response with a JSON body
+    pub fn json(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn answer(&self, url: &str) -> Result<HttpResponse> {
+        self.urls.lock().unwrap().push(url.to_string());
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| LrcphileError::Provider(format!("Unexpected request to {}", url)))
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        _headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move { self.answer(url) })
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        _headers: Vec<(String, String)>,
+        _body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move { self.answer(url) })
+    }
+}
+
+/// Records and tracks shared by the tests of the modules that look lyrics up
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::TrackMetadata;
+
+    /// An LRCLIB record of `metadata()`'s track
+    pub const RECORD: &str = r#"{"id":11,"trackName":"Song","artistName":"Band","albumName":"Album","duration":200,"instrumental":false,"plainLyrics":"Hello","syncedLyrics":"[00:01.00]Hello"}"#;
+
+    pub fn metadata() -> TrackMetadata {
+        TrackMetadata {
+            track_name: "Song".to_string(),
+            artist_name: "Band".to_string(),
+            album_name: "Album".to_string(),
+            genre: None,
+            duration: 200.0,
+            disc_number: None,
+            track_number: None,
+            lrclib_id: None,
+            validators: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{RECORD, metadata};
+    use super::*;
+
+    #[tokio::test]
+    async fn fetch_parses_a_record() {
+        let transport = MockTransport::new([MockTransport::json(200, RECORD)]);
+        let lrclib = Lrclib::new("http://lrclib.test/", false, transport.clone());
+
+        let lyrics = lrclib.fetch(&metadata()).await.unwrap();
+        assert_eq!(lyrics.lrclib_id(), Some(11));
+        assert_eq!(lyrics.synced_lyrics.as_deref(), Some("[00:01.00]Hello"));
+        assert_eq!(
+            transport.urls.lock().unwrap()[0],
+            "http://lrclib.test/api/get?track_name=Song&artist_name=Band&album_name=Album&duration=200"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_maps_statuses_to_errors() {
+        let mut rate_limited = MockTransport::json(429, "{}");
+        rate_limited
+            .headers
+            .push(("Retry-After".to_string(), "7".to_string()));
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            rate_limited,
+            MockTransport::json(200, "{not json"),
+            MockTransport::json(500, ""),
+        ]);
+        let lrclib = Lrclib::new("http://lrclib.test", false, transport);
+
+        assert!(matches!(
+            lrclib.fetch(&metadata()).await,
+            Err(LrcphileError::NotFound)
+        ));
+        assert!(matches!(
+            lrclib.fetch(&metadata()).await,
+            Err(LrcphileError::RateLimited { retry_after: Some(wait) }) if wait == Duration::from_secs(7)
+        ));
+        assert!(matches!(
+            lrclib.fetch(&metadata()).await,
+            Err(LrcphileError::Parse(_))
+        ));
+        assert!(matches!(
+            lrclib.fetch(&metadata()).await,
+            Err(LrcphileError::Provider(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetch_falls_back_to_the_signature_when_the_known_record_is_gone() {
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            MockTransport::json(200, RECORD),
+        ]);
+        let lrclib = Lrclib::new("http://lrclib.test", false, transport.clone());
+        let metadata = TrackMetadata {
+            lrclib_id: Some(5),
+            ..metadata()
+        };
+
+        assert_eq!(lrclib.fetch(&metadata).await.unwrap().id, 11);
+        let urls = transport.urls.lock().unwrap();
+        assert_eq!(urls[0], "http://lrclib.test/api/get/5");
+        assert!(urls[1].starts_with("http://lrclib.test/api/get?"));
+    }
+
+    #[tokio::test]
+    async fn counts_each_request_reaching_the_network() {
+        let transport = MockTransport::new([
+            MockTransport::json(404, r#"{"code":404}"#),
+            MockTransport::json(200, RECORD),
+        ]);
+        let options = ClientOptions::default();
+        let lrclib = Lrclib::new(
+            "http://lrclib.test",
+            false,
+            wrap_transport(transport, &options).unwrap(),
+        );
+        let metadata = TrackMetadata {
+            lrclib_id: Some(5),
+            ..metadata()
+        };
+
+        lrclib.fetch(&metadata).await.unwrap();
+        assert_eq!(options.requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn chain_asks_the_next_provider_after_a_miss() {
+        let missing = MockTransport::new([MockTransport::json(404, "{}")]);
+        let found = MockTransport::new([MockTransport::json(200, RECORD)]);
+        let chain = ProviderChain::new(vec![
+            Arc::new(Lrclib::new("http://first.test", false, missing)),
+            Arc::new(Lrclib::new("http://second.test", false, found.clone())),
+        ]);
+
+        assert_eq!(chain.fetch(&metadata()).await.unwrap().id, 11);
+        assert_eq!(found.urls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn chain_reports_a_failure_over_a_miss() {
+        let failing = MockTransport::new([MockTransport::json(500, "")]);
+        let missing = MockTransport::new([MockTransport::json(404, "{}")]);
+        let chain = ProviderChain::new(vec![
+            Arc::new(Lrclib::new("http://first.test", false, failing)),
+            Arc::new(Lrclib::new("http://second.test", false, missing)),
+        ]);
+
+        assert!(matches!(
+            chain.fetch(&metadata()).await,
+            Err(LrcphileError::Provider(_))
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::error::{LrcphileError, Result};
+use crate::lock::LibraryLock;
+use crate::provider::LyricsProvider;
+use crate::state::StateDb;
+use crate::{
+    Cli, Console, LyricsStatus, ProcessingStats, Throttle, TrackMetadata, fetch_with_retry,
+    process_directory, process_file,
+};
+use futures::future::LocalBoxFuture;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Mutex;
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// A job that was understood but failed, e.g. a path that doesn't exist
+const JOB_FAILED: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    /// Echoed in the reply, as `null` when missing
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct FetchParams {
+    path: PathBuf,
+    /// Overrides `--recursive` for a directory
+    recursive: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct LookupParams {
+    title: String,
+    artist: String,
+    #[serde(default)]
+    album: String,
+    /// In seconds
+    duration: f64,
+}
+
+/// Serves JSON-RPC 2.0 requests read line by line from stdin, answering on stdout, until
+/// stdin closes or a `shutdown` request arrives. Jobs run side by side, each sending a
+/// `file_finished` notification per file before its result
+pub async fn run(args: &Cli, state: &Arc<Mutex<StateDb>>) -> Result<()> {
+    let provider = args.lyrics_provider()?;
+    let throttle = Throttle::default();
+    let server = Server {
+        args,
+        provider: provider.as_ref(),
+        throttle: &throttle,
+        state,
+    };
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut jobs: FuturesUnordered<LocalBoxFuture<()>> = FuturesUnordered::new();
+    let mut reading = true;
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if reading => match line {
+                Ok(Some(line)) if line.trim().is_empty() => {}
+                Ok(Some(line)) => match server.handle(&line) {
+                    Handled::Job(job) => jobs.push(job),
+                    Handled::Done => {}
+                    Handled::Shutdown => reading = false,
+                },
+                Ok(None) | Err(_) => reading = false,
+            },
+            Some(()) = jobs.next(), if !jobs.is_empty() => {}
+            else => break,
+        }
+    }
+    Ok(())
+}
+
+enum Handled<'a> {
+    /// Runs until the job's result has been sent
+    Job(LocalBoxFuture<'a, ()>),
+    /// Answered already
+    Done,
+    /// Answered, and no more requests are read; running jobs still finish
+    Shutdown,
+}
+
+struct Server<'a> {
+    args: &'a Cli,
+    provider: &'a dyn LyricsProvider,
+    throttle: &'a Throttle,
+    state: &'a Arc<Mutex<StateDb>>,
+}
+
+impl<'a> Server<'a> {
+    fn handle(&'a self, line: &str) -> Handled<'a> {
+        let request: Request = match serde_json::from_str::<Value>(line) {
+            Err(e) => {
+                send_error(&Value::Null, PARSE_ERROR, &e.to_string());
+                return Handled::Done;
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    send_error(&Value::Null, INVALID_REQUEST, &e.to_string());
+                    return Handled::Done;
+                }
+            },
+        };
+        let id = request.id.unwrap_or(Value::Null);
+
+        match request.method.as_str() {
+            "fetch" => match serde_json::from_value::<FetchParams>(request.params) {
+                Ok(params) => Handled::Job(Box::pin(self.fetch_job(id, params))),
+                Err(e) => {
+                    send_error(&id, INVALID_PARAMS, &e.to_string());
+                    Handled::Done
+                }
+            },
+            "lookup" => match serde_json::from_value::<LookupParams>(request.params) {
+                Ok(params) => Handled::Job(Box::pin(self.lookup_job(id, params))),
+                Err(e) => {
+                    send_error(&id, INVALID_PARAMS, &e.to_string());
+                    Handled::Done
+                }
+            },
+            "shutdown" => {
+                send(json!({"jsonrpc": "2.0", "id": id, "result": null}));
+                Handled::Shutdown
+            }
+            method => {
+                send_error(&id, METHOD_NOT_FOUND, &format!("unknown method {}", method));
+                Handled::Done
+            }
+        }
+    }
+
+    /// Fetches lyrics for a file or the audio files of a directory, as a run given that path
+    /// would, and answers with the counts
+    async fn fetch_job(&self, id: Value, params: FetchParams) {
+        let mut args = self.args.clone();
+        if let Some(recursive) = params.recursive {
+            args.recursive = recursive;
+        }
+        match self.fetch(&id, &params.path, &args).await {
+            Ok(stats) => send(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": { "counts": stats.progress_counts() },
+            })),
+            Err(e) => send_error(&id, JOB_FAILED, &e.to_string()),
+        }
+    }
+
+    async fn fetch(&self, id: &Value, path: &Path, args: &Cli) -> Result<ProcessingStats> {
+        let (audio_files, _lock) = if path.is_dir() {
+            let lock = LibraryLock::acquire(path, args.break_lock)?;
+            (process_directory(path, args)?, lock)
+        } else if path.is_file() {
+            (vec![path.to_path_buf()], None)
+        } else {
+            return Err(LrcphileError::InvalidPath {
+                path: path.to_path_buf(),
+                reason: "not a file or directory",
+            });
+        };
+
+        let mut stats = ProcessingStats::new(audio_files.len());
+        stream::iter(audio_files)
+            .map(|file_path| async move {
+                // Messages about the file go into its notification rather than stderr
+                let console = Console::default().keeping_notes();
+                let outcome = process_file(
+                    &file_path,
+                    args,
+                    self.provider,
+                    self.throttle,
+                    self.state.clone(),
+                    &console,
+                )
+                .await;
+                let messages: Vec<String> = console.take_notes().into_values().collect();
+                (file_path, outcome, messages)
+            })
+            .buffer_unordered(args.concurrency())
+            .for_each(|(file_path, outcome, messages)| {
+                stats.record(&file_path, outcome, self.args);
+                send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "file_finished",
+                    "params": {
+                        "job": id,
+                        "path": file_path,
+                        "outcome": outcome.as_str(),
+                        "lyrics": LyricsStatus::of(&file_path, args).as_str(),
+                        "messages": messages,
+                        "counts": stats.progress_counts(),
+                    },
+                }));
+                futures::future::ready(())
+            })
+            .await;
+        Ok(stats)
+    }
+
+    /// Looks lyrics up by the given details without touching any file, answering with the
+    /// record found or `null`
+    async fn lookup_job(&self, id: Value, params: LookupParams) {
+        let metadata = TrackMetadata {
+            track_name: params.title,
+            artist_name: params.artist,
+            album_name: params.album,
+            genre: None,
+            duration: params.duration,
+            disc_number: None,
+            track_number: None,
+            lrclib_id: None,
+            validators: None,
+        };
+        let console = Console::default().keeping_notes();
+        match fetch_with_retry(
+            self.provider,
+            &metadata,
+            self.throttle,
+            &console,
+            self.args.search_fallback,
+            false,
+        )
+        .await
+        {
+            Ok(lyrics) => send(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": {
+                    "provider": lyrics.provider,
+                    "title": lyrics.track_name,
+                    "artist": lyrics.artist_name,
+                    "album": lyrics.album_name,
+                    "duration": lyrics.duration,
+                    "instrumental": lyrics.instrumental,
+                    "synced": lyrics.synced_lyrics,
+                    "plain": lyrics.plain_lyrics,
+                    "confidence": lyrics.confidence,
+                },
+            })),
+            Err(LrcphileError::NotFound) => {
+                send(json!({"jsonrpc": "2.0", "id": id, "result": null}))
+            }
+            Err(e) => send_error(&id, JOB_FAILED, &e.to_string()),
+        }
+    }
+}
+
+fn send_error(id: &Value, code: i64, message: &str) {
+    send(json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    }));
+}
+
+/// Writes one message as a line of its own; a parent that stopped reading isn't an error
+fn send(message: Value) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", message).and_then(|()| stdout.flush());
+}
@@ -0,0 +1,30 @@
+use crate::ass::cues;
+use crate::lrc::{Lrc, strip_word_timings};
+use std::fmt::Write;
+
+/// Turns synced lyrics into SubRip subtitles, which every player that loads subtitles reads
+pub fn to_srt(lrc: &Lrc) -> String {
+    let mut srt = String::new();
+    for (index, cue) in cues(lrc).iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            srt_time(cue.start_ms),
+            srt_time(cue.end_ms),
+            strip_word_timings(cue.text).trim()
+        );
+    }
+    srt
+}
+
+/// SRT timestamps count hours and milliseconds, e.g. `00:01:10,250`
+fn srt_time(millis: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
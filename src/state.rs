@@ -0,0 +1,440 @@
+use crate::TrackMetadata;
+use crate::error::{LrcphileError, Result};
+use crate::provider::CacheValidators;
+use colored::Colorize;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What lrcphile remembers about an audio file between runs
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileRecord {
+    /// Modification time of the audio file (seconds since the epoch) when it was last processed
+    pub mtime: u64,
+    /// LRCLIB record the lyrics were last fetched from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lrclib_id: Option<u64>,
+    /// Hash of the tags the record was matched with, so it isn't reused once the track is
+    /// retagged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags_hash: Option<String>,
+    /// Hash of the lyrics lrcphile last wrote, to tell whether they were edited since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lyrics_hash: Option<String>,
+    /// Cache validators of the LRCLIB record, sent along when refreshing the lyrics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validators: Option<CacheValidators>,
+    /// LRCLIB record rejected in review, so it isn't offered for the track again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_lrclib_id: Option<u64>,
+    /// Confidence, from 0 to 100, that the last fetched record is the track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+    /// Artist tag of the track when it was last processed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Playing time of the track in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_secs: Option<u64>,
+    /// Disc and track number tags, to locate the track in reports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disc: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<u32>,
+    /// What the track's lyrics on disk looked like when it was last processed; `None` when
+    /// it had none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<LyricsContent>,
+    /// The last lookup found nothing to write, remembered here instead of with a placeholder
+    /// file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_lyrics: Option<NoLyrics>,
+}
+
+/// A lookup that found no lyrics, or that the track is instrumental
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct NoLyrics {
+    pub instrumental: bool,
+    /// When the track was looked up, in seconds since the epoch
+    pub checked: u64,
+}
+
+impl NoLyrics {
+    /// Whether the lookup is more than `expiry_days` old; never without an expiry
+    pub fn is_expired(&self, expiry_days: Option<u64>) -> bool {
+        expiry_days.is_some_and(|expiry_days| {
+            now_secs().saturating_sub(self.checked) > expiry_days * 24 * 60 * 60
+        })
+    }
+}
+
+/// Summary of a track's lyrics kept for `lrcphile stats`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LyricsContent {
+    pub synced: bool,
+    pub instrumental: bool,
+    /// Non-empty lyrics lines
+    pub lines: usize,
+    /// ISO 639-3 code of the language the lyrics are written in, when it could be told
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// A doubtful search match held back for review instead of being written
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuarantinedMatch {
+    /// LRCLIB record of the match; other providers' records have no ID to keep
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lrclib_id: Option<u64>,
+    /// Lyrics file contents, header included, as they would have been written
+    pub content: String,
+    pub extension: String,
+    /// Why the match was doubted
+    pub reason: String,
+}
+
+/// How a provider answered the lookups lrcphile sent it, including those answered from the
+/// response cache
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ProviderStats {
+    pub requests: u64,
+    /// Lookups that found lyrics, or found the known lyrics unchanged
+    pub hits: u64,
+    /// Lookups the provider had no lyrics for
+    pub misses: u64,
+    /// Failed lookups, rate limiting included
+    pub errors: u64,
+}
+
+impl ProviderStats {
+    pub fn add(&mut self, other: &ProviderStats) {
+        self.requests += other.requests;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.errors += other.errors;
+    }
+}
+
+/// Persistent state shared across runs, stored as JSON in the user's data directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StateDb {
+    #[serde(default)]
+    files: HashMap<String, FileRecord>,
+    /// Matches waiting for `lrcphile review`, by audio file
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    review: HashMap<String, QuarantinedMatch>,
+    /// Lookups of every run so far, by provider name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    provider_stats: HashMap<String, ProviderStats>,
+    /// Hash of the content two sidecars mirrored by `lrcphile sync` had when they were last
+    /// in step, by the pair of sidecar paths
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    sync_baselines: HashMap<String, String>,
+    #[serde(skip)]
+    location: Option<PathBuf>,
+}
+
+impl StateDb {
+    pub fn default_location() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "lrcphile").map(|dirs| dirs.data_dir().join("state.json"))
+    }
+
+    /// Loads the state file, starting empty when it does not exist yet
+    pub fn load(location: &Path) -> Result<Self> {
+        let mut state = if location.exists() {
+            // Parsed straight from the file, so large libraries never hold both the text and
+            // the records in memory
+            serde_json::from_reader::<_, StateDb>(BufReader::new(fs::File::open(location)?))?
+        } else {
+            StateDb::default()
+        };
+        state.location = Some(location.to_path_buf());
+        Ok(state)
+    }
+
+    /// Loads the state file a run keeps its records in; one that can't be parsed, say
+    /// truncated by a crash, is moved aside to `state.json.corrupt` so the run starts afresh
+    /// instead of failing every time
+    pub fn open(location: &Path) -> Result<Self> {
+        match Self::load(location) {
+            Err(LrcphileError::Parse(reason)) => {
+                let aside = location.with_extension("json.corrupt");
+                fs::rename(location, &aside)?;
+                eprintln!(
+                    "{} {}",
+                    "Warning:".yellow().bold(),
+                    format!(
+                        "State file {} is corrupt ({}); moved it to {} and starting afresh",
+                        location.display(),
+                        reason,
+                        aside.display()
+                    )
+                    .yellow()
+                );
+                Ok(StateDb {
+                    location: Some(location.to_path_buf()),
+                    ..StateDb::default()
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Writes the state back to where it was loaded from; in-memory state is not persisted
+    pub fn save(&self) -> Result<()> {
+        let Some(location) = &self.location else {
+            return Ok(());
+        };
+
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so an interrupted run can't truncate the state
+        let temp_location = location.with_extension("json.tmp");
+        let mut writer = BufWriter::new(fs::File::create(&temp_location)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&temp_location, location)?;
+        Ok(())
+    }
+
+    /// Writes the state as JSON, for `lrcphile state export`
+    pub fn export(&self, writer: impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Takes in a state exported elsewhere, its records replacing these for the same tracks;
+    /// paths starting with an `old` prefix of `relocate` are moved under its `new` one
+    pub fn merge(&mut self, other: StateDb, relocate: &[(PathBuf, PathBuf)]) {
+        let move_path = |path: PathBuf| {
+            relocate
+                .iter()
+                .find_map(|(old, new)| Some(new.join(path.strip_prefix(old).ok()?)))
+                .unwrap_or(path)
+        };
+        let move_key = |key: String| state_key(&move_path(PathBuf::from(key)));
+
+        for (key, record) in other.files {
+            self.files.insert(move_key(key), record);
+        }
+        for (key, quarantined) in other.review {
+            self.review.insert(move_key(key), quarantined);
+        }
+        for (provider, stats) in other.provider_stats {
+            self.provider_stats.entry(provider).or_default().add(&stats);
+        }
+        for (key, hash) in other.sync_baselines {
+            if let Some((a, b)) = key.split_once('\n') {
+                let (a, b) = (move_path(PathBuf::from(a)), move_path(PathBuf::from(b)));
+                self.sync_baselines.insert(pair_key(&a, &b), hash);
+            }
+        }
+    }
+
+    /// Forgets everything, keeping the location the state is saved to
+    pub fn clear(&mut self) {
+        *self = StateDb {
+            location: self.location.take(),
+            ..StateDb::default()
+        };
+    }
+
+    pub fn get(&self, audio_path: &Path) -> Option<&FileRecord> {
+        self.files.get(&state_key(audio_path))
+    }
+
+    /// Whether the audio file was processed before and hasn't been modified since
+    pub fn is_unchanged(&self, audio_path: &Path) -> bool {
+        match (self.get(audio_path), modified_secs(audio_path)) {
+            (Some(record), Some(mtime)) => record.mtime == mtime,
+            _ => false,
+        }
+    }
+
+    /// Remembers the record matched to the track along with the tags it was matched with
+    pub fn record_lrclib_id(
+        &mut self,
+        audio_path: &Path,
+        lrclib_id: Option<u64>,
+        metadata: &TrackMetadata,
+    ) {
+        let record = self.files.entry(state_key(audio_path)).or_default();
+        record.lrclib_id = lrclib_id;
+        record.tags_hash = lrclib_id.map(|_| tags_hash(metadata));
+    }
+
+    /// The record matched to the track before, unless its tags have changed since; without
+    /// `metadata` the tags aren't compared
+    pub fn lrclib_id(&self, audio_path: &Path, metadata: Option<&TrackMetadata>) -> Option<u64> {
+        let record = self.get(audio_path)?;
+        match (&record.tags_hash, metadata) {
+            (Some(hash), Some(metadata)) if *hash != tags_hash(metadata) => None,
+            _ => record.lrclib_id,
+        }
+    }
+
+    pub fn record_confidence(&mut self, audio_path: &Path, confidence: Option<u8>) {
+        self.files
+            .entry(state_key(audio_path))
+            .or_default()
+            .confidence = confidence;
+    }
+
+    /// Remembers the track's artist, length, position and lyrics for `lrcphile stats` and
+    /// `--report`
+    pub fn record_content(
+        &mut self,
+        audio_path: &Path,
+        metadata: &TrackMetadata,
+        content: Option<LyricsContent>,
+    ) {
+        let record = self.files.entry(state_key(audio_path)).or_default();
+        record.artist = Some(metadata.artist_name.clone());
+        record.track_secs = Some(metadata.duration as u64);
+        record.disc = metadata.disc_number;
+        record.track = metadata.track_number;
+        record.content = content;
+    }
+
+    /// Every file with a record, with the absolute path it was recorded under
+    pub fn records(&self) -> impl Iterator<Item = (PathBuf, &FileRecord)> {
+        self.files
+            .iter()
+            .map(|(key, record)| (PathBuf::from(key), record))
+    }
+
+    pub fn record_lyrics_hash(&mut self, audio_path: &Path, lyrics_hash: String) {
+        self.files
+            .entry(state_key(audio_path))
+            .or_default()
+            .lyrics_hash = Some(lyrics_hash);
+    }
+
+    /// Remembers the validators of the fetched record, forgetting stale ones when it sent none
+    pub fn record_validators(&mut self, audio_path: &Path, validators: CacheValidators) {
+        self.files
+            .entry(state_key(audio_path))
+            .or_default()
+            .validators = Some(validators).filter(|validators| !validators.is_empty());
+    }
+
+    /// Remembers that a lookup found no lyrics to write, or `None` once it found some
+    pub fn record_no_lyrics(&mut self, audio_path: &Path, instrumental: Option<bool>) {
+        self.files
+            .entry(state_key(audio_path))
+            .or_default()
+            .no_lyrics = instrumental.map(|instrumental| NoLyrics {
+            instrumental,
+            checked: now_secs(),
+        });
+    }
+
+    pub fn quarantine(&mut self, audio_path: &Path, quarantined: QuarantinedMatch) {
+        self.review.insert(state_key(audio_path), quarantined);
+    }
+
+    pub fn is_quarantined(&self, audio_path: &Path) -> bool {
+        self.review.contains_key(&state_key(audio_path))
+    }
+
+    /// Adds a run's lookups to the running totals of each provider
+    pub fn record_provider_stats(&mut self, run: HashMap<String, ProviderStats>) {
+        for (provider, stats) in run {
+            self.provider_stats.entry(provider).or_default().add(&stats);
+        }
+    }
+
+    pub fn provider_stats(&self) -> &HashMap<String, ProviderStats> {
+        &self.provider_stats
+    }
+
+    /// Every match waiting for review, ordered by audio file
+    pub fn quarantined(&self) -> Vec<(PathBuf, QuarantinedMatch)> {
+        let mut quarantined: Vec<(PathBuf, QuarantinedMatch)> = self
+            .review
+            .iter()
+            .map(|(key, quarantined)| (PathBuf::from(key), quarantined.clone()))
+            .collect();
+        quarantined.sort_by(|a, b| a.0.cmp(&b.0));
+        quarantined
+    }
+
+    /// Takes a match out of review, remembering its LRCLIB record if it was rejected
+    pub fn release(&mut self, audio_path: &Path, rejected: bool) -> Option<QuarantinedMatch> {
+        let quarantined = self.review.remove(&state_key(audio_path))?;
+        if rejected && let Some(lrclib_id) = quarantined.lrclib_id {
+            self.files
+                .entry(state_key(audio_path))
+                .or_default()
+                .rejected_lrclib_id = Some(lrclib_id);
+        }
+        Some(quarantined)
+    }
+
+    /// What two mirrored sidecars held when `lrcphile sync` last left them the same
+    pub fn sync_baseline(&self, a: &Path, b: &Path) -> Option<&str> {
+        self.sync_baselines.get(&pair_key(a, b)).map(String::as_str)
+    }
+
+    pub fn record_sync_baseline(&mut self, a: &Path, b: &Path, hash: String) {
+        self.sync_baselines.insert(pair_key(a, b), hash);
+    }
+
+    pub fn record_processed(&mut self, audio_path: &Path) {
+        if let Some(mtime) = modified_secs(audio_path) {
+            self.files.entry(state_key(audio_path)).or_default().mtime = mtime;
+        }
+    }
+}
+
+/// Fingerprint of the tags a track is looked up by
+fn tags_hash(metadata: &TrackMetadata) -> String {
+    let tags = format!(
+        "{}\0{}\0{}\0{}",
+        metadata.track_name,
+        metadata.artist_name,
+        metadata.album_name,
+        metadata.duration.round() as u64
+    );
+    Sha256::digest(tags)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn state_key(audio_path: &Path) -> String {
+    std::path::absolute(audio_path)
+        .unwrap_or_else(|_| audio_path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Key of a pair of files, the same whichever side is named first
+fn pair_key(a: &Path, b: &Path) -> String {
+    let (a, b) = (state_key(a), state_key(b));
+    if a <= b {
+        format!("{}\n{}", a, b)
+    } else {
+        format!("{}\n{}", b, a)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+pub fn modified_secs(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
@@ -0,0 +1,158 @@
+use crate::error::Result;
+use crate::provider::{HttpResponse, Transport};
+use chrono::{Local, SecondsFormat};
+use futures::future::BoxFuture;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::time::Instant;
+
+/// Header and query parameter names whose values never go into the log, matched case-insensitively by substring
+const SECRET_HEADERS: [&str; 5] = ["auth", "token", "cookie", "key", "secret"];
+
+/// How much of an error response's body is logged
+const MAX_LOGGED_BODY: usize = 300;
+
+/// Logs every request reaching the network with its status and timing, for `--trace-http`
+pub struct TracingTransport {
+    inner: Arc<dyn Transport>,
+    log: Mutex<File>,
+}
+
+impl TracingTransport {
+    /// Wraps `inner`, appending to the log file at `log_path`
+    pub fn new(inner: Arc<dyn Transport>, log_path: &Path) -> Result<Self> {
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?;
+        Ok(Self {
+            inner,
+            log: Mutex::new(log),
+        })
+    }
+
+    async fn traced(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        request: BoxFuture<'_, Result<HttpResponse>>,
+    ) -> Result<HttpResponse> {
+        let started = Instant::now();
+        let result = request.await;
+        let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+
+        let mut line = format!(
+            "{} {} {}",
+            Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            method,
+            redact_url(url)
+        );
+        for (name, value) in headers {
+            line.push_str(&format!(" [{}: {}]", name, redact_header(name, value)));
+        }
+        match &result {
+            Ok(response) => {
+                line.push_str(&format!(" -> {} ({:.1} ms)", response.status, elapsed));
+                // Error bodies usually say what a fork didn't like about the request
+                if response.status >= 400 {
+                    let body = String::from_utf8_lossy(&response.body);
+                    let body: String = body.chars().take(MAX_LOGGED_BODY).collect();
+                    line.push_str(&format!(" {}", body.replace('\n', " ")));
+                }
+            }
+            Err(e) => line.push_str(&format!(" -> failed ({:.1} ms): {}", elapsed, e)),
+        }
+        if let Ok(mut log) = self.log.lock() {
+            // Tracing is a debugging aid and never fails a lookup
+            let _ = writeln!(log, "{}", line);
+        }
+        result
+    }
+}
+
+impl Transport for TracingTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let logged = headers.clone();
+            self.traced("GET", url, &logged, self.inner.get(url, headers))
+                .await
+        })
+    }
+
+    fn post<'a>(
+        &'a self,
+        url: &'a str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> BoxFuture<'a, Result<HttpResponse>> {
+        Box::pin(async move {
+            let logged = headers.clone();
+            self.traced("POST", url, &logged, self.inner.post(url, headers, body))
+                .await
+        })
+    }
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SECRET_HEADERS.iter().any(|secret| name.contains(secret))
+}
+
+fn redact_header(name: &str, value: &str) -> String {
+    if is_secret(name) {
+        "[redacted]".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The URL with any `user:password@` credentials and secret-looking query values replaced
+fn redact_url(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    let has_credentials = !parsed.username().is_empty() || parsed.password().is_some();
+    let has_secret_query = parsed.query_pairs().any(|(name, _)| is_secret(&name));
+    if !has_credentials && !has_secret_query {
+        return url.to_string();
+    }
+    if has_credentials {
+        let _ = parsed.set_username("redacted");
+        let _ = parsed.set_password(None);
+    }
+    if has_secret_query {
+        let pairs: Vec<(String, String)> = parsed
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = redact_header(&name, &value);
+                (name.into_owned(), value)
+            })
+            .collect();
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    parsed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_credentials_and_secret_query_values() {
+        assert_eq!(
+            redact_url("https://user:pw@example.com/get?artist_name=A&api_key=abc&TOKEN=x"),
+            "https://redacted@example.com/get?artist_name=A&api_key=%5Bredacted%5D&TOKEN=%5Bredacted%5D"
+        );
+        let plain = "https://lrclib.net/api/get?track_name=Song&artist_name=Artist";
+        assert_eq!(redact_url(plain), plain);
+    }
+}